@@ -0,0 +1,399 @@
+//! Key-value separation: large values are written to append-only blob files when a memtable is
+//! flushed, and the SST keeps a [`BlobPointer`] to the value instead, so that compaction does not
+//! rewrite them. Reads resolve the pointers back into the values.
+//!
+//! Once enough of a blob file is garbage, blob garbage collection writes its live values back to
+//! the storage, so that the next flush moves them to a new blob file, and deletes the old one.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::iterators::ValueType;
+use crate::lsm_storage::{LsmStorageCore, LsmStorageInner};
+use crate::manifest::ManifestRecord;
+use crate::table::FileObject;
+use crate::varint::{get_varint, put_varint, varint_len, MAX_VARINT_LEN};
+use crate::write_batch::WriteBatchRecord;
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// The location of a value in a blob file, stored in an SST in place of the value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlobPointer {
+    pub file_id: usize,
+    /// The offset of the record holding the value in the blob file.
+    pub offset: u64,
+    /// The length of the record holding the value.
+    pub len: u64,
+}
+
+impl BlobPointer {
+    /// The size of an encoded pointer: `| file_id (u64) | offset (u64) | len (u64) |`.
+    const ENCODED_LEN: usize = SIZEOF_U64 * 3;
+
+    pub fn encode(&self) -> Bytes {
+        let mut buf = Vec::with_capacity(Self::ENCODED_LEN);
+        buf.put_u64(self.file_id as u64);
+        buf.put_u64(self.offset);
+        buf.put_u64(self.len);
+        buf.into()
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.len() != Self::ENCODED_LEN {
+            bail!("blob pointer has {} bytes", buf.len());
+        }
+        Ok(Self {
+            file_id: buf.get_u64() as usize,
+            offset: buf.get_u64(),
+            len: buf.get_u64(),
+        })
+    }
+}
+
+/// Builds a blob file from the values of a flush.
+pub struct BlobFileBuilder {
+    id: usize,
+    data: Vec<u8>,
+}
+
+impl BlobFileBuilder {
+    pub fn new(id: usize) -> Self {
+        Self {
+            id,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Append the value of `key` to the blob file, and return the pointer to it.
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> BlobPointer {
+        let offset = self.data.len();
+        self.data.reserve(
+            varint_len(key.len() as u64)
+                + key.len()
+                + varint_len(value.len() as u64)
+                + value.len()
+                + SIZEOF_U32,
+        );
+        put_varint(&mut self.data, key.len() as u64);
+        self.data.put_slice(key);
+        put_varint(&mut self.data, value.len() as u64);
+        self.data.put_slice(value);
+        let checksum = crc32fast::hash(&self.data[offset..]);
+        self.data.put_u32(checksum);
+        BlobPointer {
+            file_id: self.id,
+            offset: offset as u64,
+            len: (self.data.len() - offset) as u64,
+        }
+    }
+
+    /// Check if no value has been added to the blob file.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Write the blob file to `path`.
+    pub fn build(self, path: impl AsRef<Path>) -> Result<BlobFile> {
+        let file = FileObject::create(path.as_ref(), self.data)?;
+        Ok(BlobFile { id: self.id, file })
+    }
+}
+
+/// A blob file, made of records of the form:
+///
+/// ```text
+/// | key_len (varint) | key | value_len (varint) | value | checksum (u32) |
+/// ```
+///
+/// The checksum is the crc32 of the rest of the record. The key is kept for garbage collection,
+/// which looks up whether the value is still live.
+pub struct BlobFile {
+    id: usize,
+    file: FileObject,
+}
+
+impl BlobFile {
+    pub fn open(id: usize, file: FileObject) -> Self {
+        Self { id, file }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The size of the blob file in bytes.
+    pub fn size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Read the value `pointer` points to, and check its checksum.
+    pub fn read(&self, pointer: &BlobPointer) -> Result<Bytes> {
+        if pointer.file_id != self.id
+            || !matches!(pointer.offset.checked_add(pointer.len), Some(end) if end <= self.size())
+        {
+            bail!("blob pointer {:?} is out of blob file {}", pointer, self.id);
+        }
+        let record = self.file.read(pointer.offset, pointer.len)?;
+        let (_, value) = self.decode_record(pointer.offset, &record)?;
+        Ok(value)
+    }
+
+    /// Read all records in the blob file, as `(pointer, key, value)`.
+    pub fn records(&self) -> Result<Vec<(BlobPointer, Bytes, Bytes)>> {
+        let data = Bytes::from(self.file.read(0, self.size())?);
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let len = self.record_len(&data[offset..])?;
+            let (key, value) = self.decode_record(offset as u64, &data[offset..offset + len])?;
+            let pointer = BlobPointer {
+                file_id: self.id,
+                offset: offset as u64,
+                len: len as u64,
+            };
+            records.push((pointer, key, value));
+            offset += len;
+        }
+        Ok(records)
+    }
+
+    /// The length of the record at the front of `data`.
+    fn record_len(&self, data: &[u8]) -> Result<usize> {
+        let mut buf = data;
+        for _ in 0..2 {
+            // The length of the key, then of the value.
+            let len = match buf.iter().take(MAX_VARINT_LEN).position(|x| *x < 0x80) {
+                Some(_) => get_varint(&mut buf) as usize,
+                None => bail!("blob file {} has a truncated record", self.id),
+            };
+            if buf.len() < len {
+                bail!("blob file {} has a truncated record", self.id);
+            }
+            buf.advance(len);
+        }
+        if buf.len() < SIZEOF_U32 {
+            bail!("blob file {} has a truncated record", self.id);
+        }
+        Ok(data.len() - buf.len() + SIZEOF_U32)
+    }
+
+    /// Check the checksum of a record read from `offset`, and split it into the key and the value.
+    fn decode_record(&self, offset: u64, record: &[u8]) -> Result<(Bytes, Bytes)> {
+        let corrupted = || format!("blob file {} is corrupted at offset {}", self.id, offset);
+        if record.len() < SIZEOF_U32 {
+            bail!(corrupted());
+        }
+        let (mut buf, mut checksum) = record.split_at(record.len() - SIZEOF_U32);
+        if checksum.get_u32() != crc32fast::hash(buf) {
+            bail!(corrupted());
+        }
+        let key_len = get_varint(&mut buf) as usize;
+        let key = Bytes::copy_from_slice(buf.get(..key_len).with_context(corrupted)?);
+        buf.advance(key_len);
+        let value_len = get_varint(&mut buf) as usize;
+        if buf.len() != value_len {
+            bail!(corrupted());
+        }
+        Ok((key, Bytes::copy_from_slice(buf)))
+    }
+}
+
+/// Read the value an encoded blob pointer points to from one of `blob_files`.
+pub(crate) fn read_blob(
+    blob_files: &BTreeMap<usize, Arc<BlobFile>>,
+    pointer: &[u8],
+) -> Result<Bytes> {
+    let pointer = BlobPointer::decode(pointer)?;
+    match blob_files.get(&pointer.file_id) {
+        Some(blob_file) => blob_file.read(&pointer),
+        None => bail!("blob file {} does not exist", pointer.file_id),
+    }
+}
+
+/// Encode the blob references of an SST, each as `| file_id (u64) | bytes (u64) |`.
+pub(crate) fn encode_blob_refs(blob_refs: &[(usize, u64)], buf: &mut Vec<u8>) {
+    buf.reserve(blob_refs.len() * SIZEOF_U64 * 2);
+    for (file_id, bytes) in blob_refs {
+        buf.put_u64(*file_id as u64);
+        buf.put_u64(*bytes);
+    }
+}
+
+pub(crate) fn decode_blob_refs(mut buf: impl Buf) -> Vec<(usize, u64)> {
+    let mut blob_refs = Vec::new();
+    while buf.has_remaining() {
+        blob_refs.push((buf.get_u64() as usize, buf.get_u64()));
+    }
+    blob_refs
+}
+
+impl LsmStorageCore {
+    /// The number of bytes of each blob file that the SSTs in `snapshot` point to. The blob files
+    /// no SST points to are left out.
+    fn blob_live_bytes(snapshot: &LsmStorageInner) -> BTreeMap<usize, u64> {
+        let mut live_bytes = BTreeMap::<usize, u64>::new();
+        for table in snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flatten())
+        {
+            for (file_id, bytes) in table.blob_refs() {
+                *live_bytes.entry(*file_id).or_default() += bytes;
+            }
+        }
+        live_bytes
+    }
+
+    /// The fraction of each blob file in `snapshot` that no SST points to anymore.
+    pub(crate) fn blob_dead_ratios(snapshot: &LsmStorageInner) -> BTreeMap<usize, f64> {
+        let live_bytes = Self::blob_live_bytes(snapshot);
+        snapshot
+            .blob_files
+            .iter()
+            .map(|(id, blob_file)| {
+                let live = live_bytes.get(id).copied().unwrap_or(0);
+                let ratio = 1.0 - live as f64 / blob_file.size().max(1) as f64;
+                (*id, ratio.max(0.0))
+            })
+            .collect()
+    }
+
+    /// The value the visible value of `key` is based on: the latest version at `read_seq` that is
+    /// not a merge operand, or `None` if the key is deleted.
+    fn base_version(
+        &self,
+        snapshot: &LsmStorageInner,
+        key: &[u8],
+        read_seq: u64,
+    ) -> Result<Option<(u64, ValueType, Bytes)>> {
+        let covering_seq = Self::latest_covering_seq(snapshot, key, read_seq);
+        let mut seq = read_seq;
+        while let Some(version) = self.get_latest_version(snapshot, key, seq)? {
+            if version.0 < covering_seq {
+                break;
+            }
+            if version.1 != ValueType::Merge {
+                return Ok(Some(version));
+            }
+            if version.0 == 0 {
+                break;
+            }
+            seq = version.0 - 1;
+        }
+        Ok(None)
+    }
+
+    /// Collect the garbage of one blob file whose dead ratio reached `blob_gc_dead_ratio`, and
+    /// delete the blob files collected earlier that no reader needs anymore. Returns whether
+    /// anything was done.
+    ///
+    /// The live values of the blob file, the ones the visible value of a key is based on, are
+    /// written back to the storage as the current values of their keys, which hide the versions
+    /// pointing to the blob file. The blob file is deleted once no snapshot older than that write
+    /// is left, and compaction has dropped the hidden versions from every SST, since it still
+    /// reads the values of the versions it keeps.
+    pub(crate) fn trigger_blob_gc(&self) -> Result<bool> {
+        let _compaction_lock = self.compaction_lock.lock();
+        let snapshot = self.inner.read().clone();
+        let candidate = {
+            let collected = self.collected_blob_files.lock();
+            Self::blob_dead_ratios(&snapshot)
+                .into_iter()
+                .filter(|(id, _)| !collected.iter().any(|(x, _)| x == id))
+                .find(|(_, ratio)| *ratio >= self.options.blob_gc_dead_ratio)
+                .map(|(id, _)| snapshot.blob_files[&id].clone())
+        };
+        let collected = match candidate {
+            Some(blob_file) => {
+                self.collect_blob_file(&blob_file)?;
+                true
+            }
+            None => false,
+        };
+        Ok(self.remove_collected_blob_files()? || collected)
+    }
+
+    /// Write the live values of `blob_file` back to the storage, and schedule it for deletion.
+    fn collect_blob_file(&self, blob_file: &BlobFile) -> Result<()> {
+        let records = blob_file.records()?;
+        // Writes are blocked while the values are looked up, so none of them changes in between.
+        let write_lock = self.write_lock.lock();
+        let (snapshot, read_seq) = self.read_view();
+        let mut entries = Vec::new();
+        for (pointer, key, _) in records {
+            let Some((_, value_type, base)) = self.base_version(&snapshot, &key, read_seq)? else {
+                continue;
+            };
+            if value_type != ValueType::Blob || base != pointer.encode() {
+                continue;
+            }
+            // The merge operands on top of the value are merged into it.
+            if let Some(value) = self.get(&snapshot, &key, read_seq)? {
+                entries.push(WriteBatchRecord::Put(key, value));
+            }
+        }
+        let collected_seq = if entries.is_empty() {
+            drop(write_lock);
+            read_seq
+        } else {
+            self.rewrite_entries_locked(write_lock, &entries)?;
+            read_seq + 1
+        };
+        self.collected_blob_files
+            .lock()
+            .push((blob_file.id(), collected_seq));
+        Ok(())
+    }
+
+    /// Delete the collected blob files that neither the snapshots nor the SSTs may read anymore.
+    /// Returns whether any was deleted.
+    ///
+    /// Must be called with the compaction lock held, so that no compaction copies the pointers to
+    /// a blob file into new SSTs while it is deleted.
+    fn remove_collected_blob_files(&self) -> Result<bool> {
+        let watermark = self.compaction_watermark();
+        let live_bytes = Self::blob_live_bytes(&self.inner.read());
+        let removed = {
+            let mut collected = self.collected_blob_files.lock();
+            let (removed, kept) = collected.iter().partition::<Vec<_>, _>(|(id, seq)| {
+                *seq <= watermark && !live_bytes.contains_key(id)
+            });
+            *collected = kept;
+            removed.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        if removed.is_empty() {
+            return Ok(false);
+        }
+        {
+            let _state_lock = self.state_lock.lock();
+            for id in &removed {
+                self.manifest
+                    .add_record(&ManifestRecord::DeleteBlobFile(*id))?;
+            }
+            let mut guard = self.inner.write();
+            let mut snapshot = guard.as_ref().clone();
+            for id in &removed {
+                snapshot.blob_files.remove(id);
+            }
+            *guard = Arc::new(snapshot);
+        }
+        // Readers holding an older snapshot keep the files open, so they can still read them.
+        for id in removed {
+            std::fs::remove_file(self.path_of_blob(id))?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,71 @@
+use tempfile::tempdir;
+
+use super::*;
+
+#[test]
+fn test_blob_pointer_roundtrip() {
+    let pointer = BlobPointer {
+        file_id: 3,
+        offset: 1 << 40,
+        len: 100,
+    };
+    assert_eq!(BlobPointer::decode(&pointer.encode()).unwrap(), pointer);
+    assert!(BlobPointer::decode(&pointer.encode()[1..]).is_err());
+}
+
+#[test]
+fn test_blob_file_read() {
+    let dir = tempdir().unwrap();
+    let mut builder = BlobFileBuilder::new(1);
+    assert!(builder.is_empty());
+    let values = [b"".to_vec(), b"value".to_vec(), vec![b'x'; 1000]];
+    let pointers = values
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| builder.add(format!("key{}", idx).as_bytes(), value))
+        .collect::<Vec<_>>();
+    let blob_file = builder.build(dir.path().join("1.blob")).unwrap();
+    for (pointer, value) in pointers.iter().zip(&values) {
+        assert_eq!(blob_file.read(pointer).unwrap(), value[..]);
+    }
+    let records = blob_file.records().unwrap();
+    assert_eq!(records.len(), values.len());
+    for (idx, (pointer, key, value)) in records.into_iter().enumerate() {
+        assert_eq!(pointer, pointers[idx]);
+        assert_eq!(key, format!("key{}", idx));
+        assert_eq!(value, values[idx]);
+    }
+
+    // A pointer into another file or past the end of the file is rejected.
+    let pointer = pointers[2];
+    assert!(blob_file
+        .read(&BlobPointer {
+            file_id: 2,
+            ..pointer
+        })
+        .is_err());
+    assert!(blob_file
+        .read(&BlobPointer {
+            len: pointer.len + 1,
+            ..pointer
+        })
+        .is_err());
+}
+
+#[test]
+fn test_blob_file_corrupted() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.blob");
+    let mut builder = BlobFileBuilder::new(1);
+    let pointer = builder.add(b"key", b"value");
+    builder.build(&path).unwrap();
+    let mut data = std::fs::read(&path).unwrap();
+    data[pointer.offset as usize + 5] ^= 1;
+    std::fs::write(&path, data).unwrap();
+    let blob_file = BlobFile::open(1, FileObject::open(&path).unwrap());
+    assert_eq!(
+        blob_file.read(&pointer).err().unwrap().to_string(),
+        "blob file 1 is corrupted at offset 0"
+    );
+    assert!(blob_file.records().is_err());
+}
@@ -6,35 +6,166 @@ use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
 pub const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+pub const SIZEOF_U64: usize = std::mem::size_of::<u64>();
 
-/// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
-/// key-value pairs.
+/// Marks a block that ends with a format version. A block in the plain format ends with its
+/// number of entries instead, which can never be this large.
+const BLOCK_FORMAT_MARKER: u16 = u16::MAX;
+
+/// The encoding of the entries in a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// Every entry stores its full key:
+    ///
+    /// ```text
+    /// | key_len (u16) | key | value_len (u16) | value |
+    /// ```
+    ///
+    /// The block ends with the offset of each entry and the number of entries (u16).
+    Plain,
+    /// Each entry only stores the part of its key that differs from the key before it:
+    ///
+    /// ```text
+    /// | overlap_len (u16) | rest_key_len (u16) | rest_key | value_len (u16) | value |
+    /// ```
+    ///
+    /// Every `restart_interval` entries, an entry stores its full key (`overlap_len` is 0), so
+    /// that a seek can start from it. The block ends with the offset of each restart point, the
+    /// number of restart points (u16), the format version (u8) and [`BLOCK_FORMAT_MARKER`].
+    PrefixCompressed,
+    /// Like [`BlockFormat::PrefixCompressed`], but each entry also stores the sequence number of
+    /// the write, so that a block can hold several versions of a key:
+    ///
+    /// ```text
+    /// | overlap_len (u16) | rest_key_len (u16) | rest_key | seq (u64) | value_len (u16) | value |
+    /// ```
+    ///
+    /// The entries of blocks in the older formats read as sequence number 0.
+    Versioned,
+    /// Like [`BlockFormat::Versioned`], but each entry also stores the
+    /// [`ValueType`](crate::iterators::ValueType) of the write:
+    ///
+    /// ```text
+    /// | overlap_len (u16) | rest_key_len (u16) | rest_key | seq (u64) | value_type (u8) | value_len (u16) | value |
+    /// ```
+    ///
+    /// The entries of blocks in the older formats read as puts, or as deletions if their value is
+    /// empty.
+    Typed,
+    /// Like [`BlockFormat::Typed`], but the lengths are varints, so that keys and values may be
+    /// larger than 64 KiB:
+    ///
+    /// ```text
+    /// | overlap_len (varint) | rest_key_len (varint) | rest_key | seq (u64) | value_type (u8) | value_len (varint) | value |
+    /// ```
+    ///
+    /// The offsets of the restart points and their number are u32, since an entry larger than the
+    /// block size makes a block of its own.
+    Varint,
+}
+
+impl BlockFormat {
+    const PREFIX_COMPRESSED_VERSION: u8 = 2;
+    const VERSIONED_VERSION: u8 = 3;
+    const TYPED_VERSION: u8 = 4;
+    const VARINT_VERSION: u8 = 5;
+
+    /// The format version written at the end of the block, if the format has one.
+    fn version(self) -> Option<u8> {
+        match self {
+            BlockFormat::Plain => None,
+            BlockFormat::PrefixCompressed => Some(Self::PREFIX_COMPRESSED_VERSION),
+            BlockFormat::Versioned => Some(Self::VERSIONED_VERSION),
+            BlockFormat::Typed => Some(Self::TYPED_VERSION),
+            BlockFormat::Varint => Some(Self::VARINT_VERSION),
+        }
+    }
+
+    /// The size of a restart offset and of the number of restart points.
+    fn sizeof_offset(self) -> usize {
+        match self {
+            BlockFormat::Varint => SIZEOF_U32,
+            _ => SIZEOF_U16,
+        }
+    }
+}
+
+/// A block is the smallest unit of read and caching in LSM tree. It is a collection of key-value
+/// pairs, sorted by key and then from the latest version to the earliest.
 pub struct Block {
     data: Vec<u8>,
-    offsets: Vec<u16>,
+    /// Offsets of the restart points. In the plain format, every entry is a restart point.
+    offsets: Vec<u32>,
+    format: BlockFormat,
 }
 
 impl Block {
+    /// A block without entries, for iterating over an SST that has no data blocks.
+    pub(crate) fn empty() -> Self {
+        Self {
+            data: Vec::new(),
+            offsets: Vec::new(),
+            format: BlockFormat::Varint,
+        }
+    }
+
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
-        let offsets_len = self.offsets.len();
+        let put_offset = |buf: &mut Vec<u8>, offset: usize| match self.format {
+            BlockFormat::Varint => buf.put_u32(offset as u32),
+            _ => buf.put_u16(offset as u16),
+        };
         for offset in &self.offsets {
-            buf.put_u16(*offset);
+            put_offset(&mut buf, *offset as usize);
+        }
+        put_offset(&mut buf, self.offsets.len());
+        if let Some(version) = self.format.version() {
+            buf.put_u8(version);
+            buf.put_u16(BLOCK_FORMAT_MARKER);
         }
-        buf.put_u16(offsets_len as u16);
         buf.into()
     }
 
-    pub fn decode(data: &[u8]) -> Self {
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
-        let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
-            .collect();
+    /// Decode a block in any format. Returns `None` if the block is malformed, or of a format
+    /// version we do not understand.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let marker_offset = data.len().checked_sub(SIZEOF_U16)?;
+        let marker = (&data[marker_offset..]).get_u16();
+        // The end of the number of restart points.
+        let (format, offsets_len_end) = if marker == BLOCK_FORMAT_MARKER {
+            let version_offset = marker_offset.checked_sub(1)?;
+            let format = match data[version_offset] {
+                BlockFormat::PREFIX_COMPRESSED_VERSION => BlockFormat::PrefixCompressed,
+                BlockFormat::VERSIONED_VERSION => BlockFormat::Versioned,
+                BlockFormat::TYPED_VERSION => BlockFormat::Typed,
+                BlockFormat::VARINT_VERSION => BlockFormat::Varint,
+                _ => return None,
+            };
+            (format, version_offset)
+        } else {
+            (BlockFormat::Plain, data.len())
+        };
+        let sizeof_offset = format.sizeof_offset();
+        let get_offset = |mut buf: &[u8]| match format {
+            BlockFormat::Varint => buf.get_u32(),
+            _ => buf.get_u16() as u32,
+        };
+        let offsets_end = offsets_len_end.checked_sub(sizeof_offset)?;
+        let entry_offsets_len = get_offset(&data[offsets_end..offsets_len_end]) as usize;
+        let data_end = offsets_end.checked_sub(entry_offsets_len * sizeof_offset)?;
+        let offsets_raw = &data[data_end..offsets_end];
+        let offsets = offsets_raw.chunks(sizeof_offset).map(get_offset).collect();
         let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        Some(Self {
+            data,
+            offsets,
+            format,
+        })
+    }
+
+    pub fn format(&self) -> BlockFormat {
+        self.format
     }
 }
 
@@ -1,51 +1,117 @@
 use bytes::BufMut;
 
-use super::{Block, SIZEOF_U16};
+use super::{Block, BlockFormat, SIZEOF_U16, SIZEOF_U32, SIZEOF_U64};
+use crate::iterators::ValueType;
+use crate::varint::{put_varint, varint_len};
 
-/// Builds a block.
+/// Builds a block in the varint format.
 pub struct BlockBuilder {
-    /// Offsets of each key-value entries.
-    offsets: Vec<u16>,
+    /// Offsets of each restart point.
+    offsets: Vec<u32>,
     /// All key-value pairs in the block.
     data: Vec<u8>,
     /// The expected block size.
     block_size: usize,
+    /// Number of entries between two restart points.
+    restart_interval: usize,
+    /// Number of entries in the block.
+    num_entries: usize,
+    /// The key of the last entry added.
+    last_key: Vec<u8>,
+}
+
+fn key_overlap(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
 }
 
 impl BlockBuilder {
-    /// Creates a new block builder.
+    /// Creates a new block builder. An entry larger than `block_size` makes a block of its own.
     pub fn new(block_size: usize) -> Self {
+        // Restart points are only added below the block size, so their offsets fit in u32.
+        assert!(
+            block_size <= u32::MAX as usize,
+            "block size cannot exceed {}",
+            u32::MAX
+        );
         Self {
             offsets: Vec::new(),
             data: Vec::new(),
             block_size,
+            restart_interval: 16,
+            num_entries: 0,
+            last_key: Vec::new(),
         }
     }
 
+    /// Set the number of entries between two restart points. The default is 16. A larger interval
+    /// makes the block smaller, but a seek has to scan more entries.
+    pub fn with_restart_interval(mut self, restart_interval: usize) -> Self {
+        assert!(restart_interval > 0, "restart interval must be positive");
+        self.restart_interval = restart_interval;
+        self
+    }
+
     fn estimated_size(&self) -> usize {
-        self.offsets.len() * SIZEOF_U16 + self.data.len() + SIZEOF_U16
+        // The restart offsets, the number of restart points, the format version and the marker.
+        self.offsets.len() * SIZEOF_U32 + self.data.len() + SIZEOF_U32 + 1 + SIZEOF_U16
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    /// Adds a key-value pair written with sequence number `seq` to the block. Returns false when
+    /// the block is full.
     #[must_use]
-    pub fn add(&mut self, key: &[u8], value: &[u8]) -> bool {
+    pub fn add(&mut self, key: &[u8], seq: u64, value: &[u8]) -> bool {
+        self.add_with_type(key, seq, ValueType::Put, value)
+    }
+
+    /// Like [`BlockBuilder::add`], for an entry written by any kind of write.
+    #[must_use]
+    pub fn add_with_type(
+        &mut self,
+        key: &[u8],
+        seq: u64,
+        value_type: ValueType,
+        value: &[u8],
+    ) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        if self.estimated_size() + key.len() + value.len() + SIZEOF_U16 * 3 > self.block_size
-            && !self.is_empty()
-        {
+        let is_restart = self.num_entries % self.restart_interval == 0;
+        let overlap = if is_restart {
+            0
+        } else {
+            key_overlap(&self.last_key, key)
+        };
+        let rest_key_len = key.len() - overlap;
+        let mut entry_size = varint_len(overlap as u64)
+            + varint_len(rest_key_len as u64)
+            + rest_key_len
+            + SIZEOF_U64
+            + 1
+            + varint_len(value.len() as u64)
+            + value.len();
+        if is_restart {
+            entry_size += SIZEOF_U32;
+        }
+        if self.estimated_size() + entry_size > self.block_size && !self.is_empty() {
             return false;
         }
-        self.offsets.push(self.data.len() as u16);
-        self.data.put_u16(key.len() as u16);
-        self.data.put(key);
-        self.data.put_u16(value.len() as u16);
+        if is_restart {
+            self.offsets.push(self.data.len() as u32);
+        }
+        put_varint(&mut self.data, overlap as u64);
+        put_varint(&mut self.data, rest_key_len as u64);
+        self.data.put(&key[overlap..]);
+        self.data.put_u64(seq);
+        self.data.put_u8(value_type.encode());
+        put_varint(&mut self.data, value.len() as u64);
         self.data.put(value);
+        self.num_entries += 1;
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
         true
     }
 
     /// Check if there is no key-value pair in the block.
     pub fn is_empty(&self) -> bool {
-        self.offsets.is_empty()
+        self.num_entries == 0
     }
 
     /// Finalize the block.
@@ -56,6 +122,7 @@ impl BlockBuilder {
         Block {
             data: self.data,
             offsets: self.offsets,
+            format: BlockFormat::Varint,
         }
     }
 }
@@ -2,14 +2,21 @@ use std::sync::Arc;
 
 use bytes::Buf;
 
-use super::Block;
+use super::{Block, BlockFormat};
+use crate::iterators::ValueType;
+use crate::varint::get_varint;
 
 /// Iterates on a block.
 pub struct BlockIterator {
     block: Arc<Block>,
     key: Vec<u8>,
+    seq: u64,
+    value_type: ValueType,
     value: Vec<u8>,
-    idx: usize,
+    /// Offset of the current entry.
+    offset: usize,
+    /// Offset of the entry after the current one.
+    next_offset: usize,
 }
 
 impl BlockIterator {
@@ -17,8 +24,11 @@ impl BlockIterator {
         Self {
             block,
             key: Vec::new(),
+            seq: 0,
+            value_type: ValueType::Put,
             value: Vec::new(),
-            idx: 0,
+            offset: 0,
+            next_offset: 0,
         }
     }
 
@@ -29,6 +39,13 @@ impl BlockIterator {
         iter
     }
 
+    /// Creates a block iterator and seek to the last entry.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_last();
+        iter
+    }
+
     /// Creates a block iterator and seek to the first key that >= `key`.
     pub fn create_and_seek_to_key(block: Arc<Block>, key: &[u8]) -> Self {
         let mut iter = Self::new(block);
@@ -42,6 +59,18 @@ impl BlockIterator {
         &self.key
     }
 
+    /// Returns the sequence number of the current entry.
+    pub fn seq(&self) -> u64 {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.seq
+    }
+
+    /// Returns the kind of write that produced the current entry.
+    pub fn value_type(&self) -> ValueType {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.value_type
+    }
+
     /// Returns the value of the current entry.
     pub fn value(&self) -> &[u8] {
         debug_assert!(!self.key.is_empty(), "invalid iterator");
@@ -55,55 +84,126 @@ impl BlockIterator {
 
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        self.seek_to(0);
+        self.seek_to_restart(0);
     }
 
-    /// Seeks to the idx-th key in the block.
-    fn seek_to(&mut self, idx: usize) {
+    /// Seeks to the last entry in the block.
+    pub fn seek_to_last(&mut self) {
+        self.seek_to_restart(self.block.offsets.len().saturating_sub(1));
+        while self.is_valid() && self.next_offset < self.block.data.len() {
+            self.next();
+        }
+    }
+
+    /// Seeks to the idx-th restart point in the block.
+    fn seek_to_restart(&mut self, idx: usize) {
+        self.key.clear();
         if idx >= self.block.offsets.len() {
-            self.key.clear();
-            self.value.clear();
+            // The block is empty.
             return;
         }
-        let offset = self.block.offsets[idx] as usize;
-        self.seek_to_offset(offset);
-        self.idx = idx;
+        self.next_offset = self.block.offsets[idx] as usize;
+        self.next();
+    }
+
+    /// Read a key or value length, whose size depends on the format of the block.
+    fn get_len(&self, entry: &mut &[u8]) -> usize {
+        match self.block.format {
+            BlockFormat::Varint => get_varint(entry) as usize,
+            _ => entry.get_u16() as usize,
+        }
+    }
+
+    /// The full key stored at the idx-th restart point.
+    fn restart_key(&self, idx: usize) -> &[u8] {
+        let mut entry = &self.block.data[self.block.offsets[idx] as usize..];
+        if self.block.format != BlockFormat::Plain {
+            // The overlap of a restart point is always 0.
+            self.get_len(&mut entry);
+        }
+        let key_len = self.get_len(&mut entry);
+        &entry[..key_len]
     }
 
     /// Move to the next key in the block.
     pub fn next(&mut self) {
-        self.idx += 1;
-        self.seek_to(self.idx);
+        if self.next_offset >= self.block.data.len() {
+            self.key.clear();
+            self.value.clear();
+            return;
+        }
+        self.offset = self.next_offset;
+        let mut entry = &self.block.data[self.next_offset..];
+        let entry_len = entry.len();
+        let overlap = match self.block.format {
+            BlockFormat::Plain => 0,
+            _ => self.get_len(&mut entry),
+        };
+        let rest_key_len = self.get_len(&mut entry);
+        self.key.truncate(overlap);
+        self.key.extend_from_slice(&entry[..rest_key_len]);
+        entry.advance(rest_key_len);
+        self.seq = match self.block.format {
+            BlockFormat::Versioned | BlockFormat::Typed | BlockFormat::Varint => entry.get_u64(),
+            BlockFormat::Plain | BlockFormat::PrefixCompressed => 0,
+        };
+        let value_type = match self.block.format {
+            BlockFormat::Typed | BlockFormat::Varint => {
+                Some(ValueType::decode(entry.get_u8()).expect("unknown value type in block"))
+            }
+            BlockFormat::Plain | BlockFormat::PrefixCompressed | BlockFormat::Versioned => None,
+        };
+        let value_len = self.get_len(&mut entry);
+        self.value.clear();
+        self.value.extend_from_slice(&entry[..value_len]);
+        entry.advance(value_len);
+        // Older formats mark a deletion with an empty value.
+        self.value_type = value_type.unwrap_or(if self.value.is_empty() {
+            ValueType::Delete
+        } else {
+            ValueType::Put
+        });
+        self.next_offset += entry_len - entry.len();
     }
 
-    fn seek_to_offset(&mut self, offset: usize) {
-        let mut entry = &self.block.data[offset..];
-        let key_len = entry.get_u16() as usize;
-        let key = entry[..key_len].to_vec();
-        entry.advance(key_len);
-        self.key.clear();
-        self.key.extend(key);
-        let value_len = entry.get_u16() as usize;
-        let value = entry[..value_len].to_vec();
-        entry.advance(value_len);
-        self.value.clear();
-        self.value.extend(value);
+    /// Move to the previous key in the block. An entry can only be decoded from the restart point
+    /// before it, so the entries from there are decoded again.
+    pub fn prev(&mut self) {
+        let offset = self.offset;
+        if self.key.is_empty() || offset == 0 {
+            self.key.clear();
+            self.value.clear();
+            self.next_offset = self.block.data.len();
+            return;
+        }
+        let restart_idx = self
+            .block
+            .offsets
+            .partition_point(|x| (*x as usize) < offset)
+            - 1;
+        self.seek_to_restart(restart_idx);
+        while self.next_offset < offset {
+            self.next();
+        }
     }
 
-    /// Seek to the first key that >= `key`.
+    /// Seek to the latest version of the first key that >= `key`. The restart points are binary
+    /// searched for the last one before `key`, and the entries after it are scanned. A restart
+    /// point equal to `key` may not hold its latest version, so it cannot be the start.
     pub fn seek_to_key(&mut self, key: &[u8]) {
         let mut low = 0;
         let mut high = self.block.offsets.len();
         while low < high {
             let mid = low + (high - low) / 2;
-            self.seek_to(mid);
-            assert!(self.is_valid());
-            match self.key().cmp(key) {
-                std::cmp::Ordering::Less => low = mid + 1,
-                std::cmp::Ordering::Greater => high = mid,
-                std::cmp::Ordering::Equal => return,
+            if self.restart_key(mid) < key {
+                low = mid + 1;
+            } else {
+                high = mid;
             }
         }
-        self.seek_to(low);
+        self.seek_to_restart(low.saturating_sub(1));
+        while self.is_valid() && self.key() < key {
+            self.next();
+        }
     }
 }
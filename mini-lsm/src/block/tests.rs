@@ -3,19 +3,20 @@ use std::sync::Arc;
 use super::builder::BlockBuilder;
 use super::iterator::BlockIterator;
 use super::*;
+use crate::iterators::ValueType;
 
 #[test]
 fn test_block_build_single_key() {
     let mut builder = BlockBuilder::new(16);
-    assert!(builder.add(b"233", b"233333"));
+    assert!(builder.add(b"233", 0, b"233333"));
     builder.build();
 }
 
 #[test]
 fn test_block_build_full() {
     let mut builder = BlockBuilder::new(16);
-    assert!(builder.add(b"11", b"11"));
-    assert!(!builder.add(b"22", b"22"));
+    assert!(builder.add(b"11", 0, b"11"));
+    assert!(!builder.add(b"22", 0, b"22"));
     builder.build();
 }
 
@@ -36,7 +37,7 @@ fn generate_block() -> Block {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        assert!(builder.add(&key[..], &value[..]));
+        assert!(builder.add(&key[..], idx as u64, &value[..]));
     }
     builder.build()
 }
@@ -56,9 +57,10 @@ fn test_block_encode() {
 fn test_block_decode() {
     let block = generate_block();
     let encoded = block.encode();
-    let decoded_block = Block::decode(&encoded);
+    let decoded_block = Block::decode(&encoded).unwrap();
     assert_eq!(block.offsets, decoded_block.offsets);
     assert_eq!(block.data, decoded_block.data);
+    assert_eq!(block.format, decoded_block.format);
 }
 
 fn as_bytes(x: &[u8]) -> Bytes {
@@ -87,6 +89,7 @@ fn test_block_iterator() {
                 as_bytes(&value_of(i)),
                 as_bytes(value)
             );
+            assert_eq!(iter.seq(), i as u64);
             iter.next();
         }
         iter.seek_to_first();
@@ -120,3 +123,270 @@ fn test_block_seek_key() {
         iter.seek_to_key(b"k");
     }
 }
+
+#[test]
+fn test_block_iterator_prev() {
+    let block = Arc::new(generate_block());
+    let mut iter = BlockIterator::create_and_seek_to_last(block);
+    for _ in 0..2 {
+        for i in (0..num_of_keys()).rev() {
+            assert_eq!(
+                iter.key(),
+                key_of(i),
+                "expected key: {:?}, actual key: {:?}",
+                as_bytes(&key_of(i)),
+                as_bytes(iter.key())
+            );
+            assert_eq!(iter.value(), value_of(i));
+            assert_eq!(iter.seq(), i as u64);
+            iter.prev();
+        }
+        assert!(!iter.is_valid());
+        // Moving an invalid iterator leaves it invalid.
+        iter.prev();
+        assert!(!iter.is_valid());
+        iter.seek_to_last();
+    }
+
+    // Switch directions in the middle of the block.
+    iter.seek_to_key(&key_of(50));
+    iter.prev();
+    assert_eq!(iter.key(), key_of(49));
+    iter.next();
+    iter.next();
+    assert_eq!(iter.key(), key_of(51));
+    iter.prev();
+    assert_eq!(iter.key(), key_of(50));
+}
+
+#[test]
+fn test_block_prefix_compression() {
+    let block = generate_block();
+    assert_eq!(block.format(), BlockFormat::Varint);
+    // Only one in every 16 keys is stored in full.
+    assert_eq!(block.offsets.len(), (num_of_keys() + 15) / 16);
+    // Each of the three lengths of an entry takes a byte.
+    let full_size = (0..num_of_keys())
+        .map(|idx| key_of(idx).len() + value_of(idx).len() + 3 + SIZEOF_U64 + 1)
+        .sum::<usize>();
+    assert!(block.encode().len() < full_size);
+}
+
+#[test]
+fn test_block_restart_interval() {
+    for restart_interval in [1, 3, 100] {
+        let mut builder = BlockBuilder::new(10000).with_restart_interval(restart_interval);
+        for idx in 0..num_of_keys() {
+            assert!(builder.add(&key_of(idx), idx as u64, &value_of(idx)));
+        }
+        let block = Arc::new(Block::decode(&builder.build().encode()).unwrap());
+        for i in 0..num_of_keys() {
+            let iter = BlockIterator::create_and_seek_to_key(block.clone(), &key_of(i));
+            assert_eq!(iter.key(), key_of(i));
+            assert_eq!(iter.value(), value_of(i));
+            let next_key = format!("key_{:03}", i * 5 + 1).into_bytes();
+            let iter = BlockIterator::create_and_seek_to_key(block.clone(), &next_key);
+            if i + 1 < num_of_keys() {
+                assert_eq!(iter.key(), key_of(i + 1));
+            } else {
+                assert!(!iter.is_valid());
+            }
+        }
+    }
+}
+
+/// Encode a block in the plain format, as blocks were written before prefix compression.
+fn encode_plain_block() -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut offsets = Vec::new();
+    for idx in 0..num_of_keys() {
+        offsets.push(buf.len() as u16);
+        buf.put_u16(key_of(idx).len() as u16);
+        buf.put_slice(&key_of(idx));
+        buf.put_u16(value_of(idx).len() as u16);
+        buf.put_slice(&value_of(idx));
+    }
+    for offset in &offsets {
+        buf.put_u16(*offset);
+    }
+    buf.put_u16(offsets.len() as u16);
+    buf
+}
+
+#[test]
+fn test_block_decode_plain_format() {
+    let encoded = encode_plain_block();
+    let block = Block::decode(&encoded).unwrap();
+    assert_eq!(block.format(), BlockFormat::Plain);
+    assert_eq!(&block.encode()[..], &encoded[..]);
+    let block = Arc::new(block);
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for i in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    for i in 0..num_of_keys() {
+        let next_key = format!("key_{:03}", i * 5 + 1).into_bytes();
+        let iter = BlockIterator::create_and_seek_to_key(block.clone(), &next_key);
+        if i + 1 < num_of_keys() {
+            assert_eq!(iter.key(), key_of(i + 1));
+        } else {
+            assert!(!iter.is_valid());
+        }
+    }
+}
+
+#[test]
+fn test_block_decode_unknown_format_version() {
+    let mut encoded = generate_block().encode().to_vec();
+    let version_offset = encoded.len() - 3;
+    encoded[version_offset] = 0xff;
+    assert!(Block::decode(&encoded).is_none());
+    assert!(Block::decode(&[]).is_none());
+}
+
+#[test]
+fn test_block_versions() {
+    // Every key has 3 versions, so the restart points fall in the middle of the versions of a key.
+    let mut builder = BlockBuilder::new(10000).with_restart_interval(2);
+    for idx in 0..num_of_keys() {
+        for seq in (1..=3).rev() {
+            let value = format!("value_{}_{}", idx, seq).into_bytes();
+            assert!(builder.add(&key_of(idx), seq, &value));
+        }
+    }
+    let block = Arc::new(Block::decode(&builder.build().encode()).unwrap());
+    for i in 0..num_of_keys() {
+        let mut iter = BlockIterator::create_and_seek_to_key(block.clone(), &key_of(i));
+        for seq in (1..=3).rev() {
+            assert_eq!(iter.key(), key_of(i));
+            assert_eq!(iter.seq(), seq);
+            assert_eq!(iter.value(), format!("value_{}_{}", i, seq).as_bytes());
+            iter.next();
+        }
+    }
+}
+
+#[test]
+fn test_block_value_types() {
+    let mut builder = BlockBuilder::new(10000);
+    for idx in 0..num_of_keys() {
+        let (value_type, value) = match idx % 3 {
+            0 => (ValueType::Merge, value_of(idx)),
+            1 => (ValueType::Put, Vec::new()),
+            _ => (ValueType::Delete, Vec::new()),
+        };
+        assert!(builder.add_with_type(&key_of(idx), idx as u64, value_type, &value));
+    }
+    let block = builder.build();
+    assert_eq!(block.format(), BlockFormat::Varint);
+    let block = Arc::new(Block::decode(&block.encode()).unwrap());
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        match idx % 3 {
+            0 => {
+                assert_eq!(iter.value_type(), ValueType::Merge);
+                assert_eq!(iter.value(), value_of(idx));
+            }
+            1 => {
+                // An empty value is not a deletion.
+                assert_eq!(iter.value_type(), ValueType::Put);
+                assert_eq!(iter.value(), b"");
+            }
+            _ => assert_eq!(iter.value_type(), ValueType::Delete),
+        }
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+
+    // Blocks in an older format only have values, and deletions with an empty value.
+    let block = Arc::new(Block::decode(&encode_plain_block()).unwrap());
+    let iter = BlockIterator::create_and_seek_to_first(block);
+    assert_eq!(iter.value_type(), ValueType::Put);
+    let mut buf = Vec::new();
+    buf.put_u16(3);
+    buf.put_slice(b"key");
+    buf.put_u16(0);
+    buf.put_u16(0);
+    buf.put_u16(1);
+    let iter = BlockIterator::create_and_seek_to_first(Arc::new(Block::decode(&buf).unwrap()));
+    assert_eq!(iter.key(), b"key");
+    assert_eq!(iter.value_type(), ValueType::Delete);
+}
+
+#[test]
+fn test_block_decode_typed_format() {
+    // A block in the typed format, with u16 lengths and offsets, and every entry a restart point.
+    let mut buf = Vec::new();
+    let mut offsets = Vec::new();
+    for idx in 0..num_of_keys() {
+        offsets.push(buf.len() as u16);
+        buf.put_u16(0);
+        buf.put_u16(key_of(idx).len() as u16);
+        buf.put_slice(&key_of(idx));
+        buf.put_u64(idx as u64);
+        buf.put_u8(ValueType::Put.encode());
+        buf.put_u16(value_of(idx).len() as u16);
+        buf.put_slice(&value_of(idx));
+    }
+    for offset in &offsets {
+        buf.put_u16(*offset);
+    }
+    buf.put_u16(offsets.len() as u16);
+    buf.put_u8(4);
+    buf.put_u16(u16::MAX);
+
+    let block = Block::decode(&buf).unwrap();
+    assert_eq!(block.format(), BlockFormat::Typed);
+    assert_eq!(&block.encode()[..], &buf[..]);
+    let block = Arc::new(block);
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.seq(), idx as u64);
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    let iter = BlockIterator::create_and_seek_to_key(block, &key_of(42));
+    assert_eq!(iter.value(), value_of(42));
+}
+
+#[test]
+fn test_block_large_entries() {
+    // Keys and values larger than 64 KiB, which is more than a u16 length can hold.
+    let key_of = |idx: usize| format!("key_{:03}", idx).repeat(10000).into_bytes();
+    let value_of = |idx: usize| format!("value_{:03}", idx).repeat(20000).into_bytes();
+    let mut builder = BlockBuilder::new(4096);
+    // An entry larger than the block size still goes into an empty block, but fills it.
+    assert!(builder.add(&key_of(0), 0, &value_of(0)));
+    assert!(!builder.add(b"k", 0, b"v"));
+    let block = Arc::new(Block::decode(&builder.build().encode()).unwrap());
+    let iter = BlockIterator::create_and_seek_to_first(block.clone());
+    assert_eq!(iter.key(), key_of(0));
+    assert_eq!(iter.value(), value_of(0));
+
+    let mut builder = BlockBuilder::new(4 << 20).with_restart_interval(2);
+    for idx in 0..5 {
+        assert!(builder.add(&key_of(idx), 0, &value_of(idx)));
+    }
+    let block = Arc::new(Block::decode(&builder.build().encode()).unwrap());
+    assert!(block.data.len() > u16::MAX as usize);
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for idx in 0..5 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    // Restart points past 64 KiB.
+    let iter = BlockIterator::create_and_seek_to_key(block.clone(), &key_of(4));
+    assert_eq!(iter.value(), value_of(4));
+    let mut iter = BlockIterator::create_and_seek_to_last(block);
+    assert_eq!(iter.key(), key_of(4));
+    iter.prev();
+    assert_eq!(iter.key(), key_of(3));
+}
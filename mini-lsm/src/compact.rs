@@ -0,0 +1,527 @@
+mod fifo;
+mod leveled;
+mod tiered;
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+pub use fifo::{FifoCompactionController, FifoCompactionOptions};
+pub use leveled::{LeveledCompactionController, LeveledCompactionOptions};
+pub use tiered::{TieredCompactionController, TieredCompactionOptions};
+
+use crate::blob::{self, BlobFile};
+use crate::compaction_filter::FilterDecision;
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::{StorageIterator, ValueType};
+use crate::lsm_storage::{LsmStorageCore, LsmStorageInner};
+use crate::manifest::ManifestRecord;
+use crate::merge_operator;
+use crate::range_tombstone::RangeTombstone;
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::ttl;
+
+/// Decides which SSTs are compacted together, and the level the result goes to. Chosen with
+/// [`LsmStorageOptions::compaction_strategy`](crate::lsm_storage::LsmStorageOptions).
+///
+/// Reads take the first version of a key they find going down the levels, so the tasks must keep
+/// every version in L0 newer than its versions in the levels, and every version in a level newer
+/// than its versions in the levels below. The SSTs in a level must not overlap.
+pub trait CompactionStrategy: Send + Sync {
+    /// Pick the next compaction to run in `snapshot`, if any.
+    fn generate_compaction_task(&self, snapshot: &LsmStorageInner) -> Option<CompactionTask>;
+
+    /// The number of levels below L0.
+    fn num_levels(&self) -> usize;
+
+    /// Check the options of the strategy when the storage is opened.
+    fn check_options(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for dyn CompactionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CompactionStrategy")
+    }
+}
+
+/// Merge the L0 SSTs in `l0_sst_ids` and the SSTs in `level_sst_ids`, given as `(level, sst_ids)`
+/// for each level they are in, and write the result to `output_level`. The SSTs left in the output
+/// level must not overlap with them. If `drop_inputs` is set, the SSTs are deleted instead, and
+/// nothing is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionTask {
+    pub l0_sst_ids: Vec<usize>,
+    pub level_sst_ids: Vec<(usize, Vec<usize>)>,
+    pub output_level: usize,
+    /// If there is no data below the output level, tombstones can be dropped.
+    pub is_output_bottom_level: bool,
+    /// Delete the input SSTs along with their data, instead of compacting them.
+    pub drop_inputs: bool,
+}
+
+impl CompactionTask {
+    /// A task that moves the SSTs of a single level to another level can be done without
+    /// rewriting them.
+    fn is_trivial_move(&self) -> bool {
+        let mut levels = self
+            .level_sst_ids
+            .iter()
+            .filter(|(_, sst_ids)| !sst_ids.is_empty());
+        !self.drop_inputs
+            && self.l0_sst_ids.is_empty()
+            && matches!(
+                (levels.next(), levels.next()),
+                (Some((level, _)), None) if *level != self.output_level
+            )
+    }
+
+    /// The IDs of all SSTs compacted by the task.
+    fn input_sst_ids(&self) -> Vec<usize> {
+        let level_sst_ids = self.level_sst_ids.iter().flat_map(|(_, ids)| ids);
+        self.l0_sst_ids
+            .iter()
+            .chain(level_sst_ids)
+            .copied()
+            .collect()
+    }
+}
+
+/// Check if the keys of `sst` overlap with the range between `lower` and `upper`.
+fn overlaps_range(sst: &SsTable, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+    let after_lower = match lower {
+        Bound::Included(key) => sst.last_key().as_ref() >= key,
+        Bound::Excluded(key) => sst.last_key().as_ref() > key,
+        Bound::Unbounded => true,
+    };
+    let before_upper = match upper {
+        Bound::Included(key) => sst.first_key().as_ref() <= key,
+        Bound::Excluded(key) => sst.first_key().as_ref() < key,
+        Bound::Unbounded => true,
+    };
+    after_lower && before_upper
+}
+
+/// Check if there is no SST below `level`, where L1 is the first level.
+pub(crate) fn is_bottom_level(snapshot: &LsmStorageInner, level: usize) -> bool {
+    snapshot.levels[level..].iter().all(|x| x.is_empty())
+}
+
+impl LsmStorageCore {
+    /// Write the contents of `iter` to new SSTs of about `target_sst_size` bytes each, to be placed
+    /// in `level`.
+    ///
+    /// Versions no reader can see are dropped, as described in [`LsmStorageCore::add_versions`].
+    /// The versions of a key are never split across SSTs.
+    ///
+    /// The range tombstones are written to the new SSTs, except at the bottom level, where nothing
+    /// older is left for the ones at or below the watermark to delete. Since an SST covers its
+    /// range tombstones, each new SST gets the part of them between its first key and the first
+    /// key of the next SST, so that the new SSTs do not overlap.
+    fn compact_generate_sst_from_iter(
+        &self,
+        mut iter: impl StorageIterator,
+        level: usize,
+        compact_to_bottom_level: bool,
+        watermark: u64,
+        range_tombstones: &[RangeTombstone],
+        blob_files: &BTreeMap<usize, Arc<BlobFile>>,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let kept_range_tombstones = range_tombstones
+            .iter()
+            .filter(|t| !compact_to_bottom_level || t.seq > watermark)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut builder: Option<SsTableBuilder> = None;
+        let mut new_ssts = Vec::new();
+        // The first key of the SST being built, unless it is the first one.
+        let mut lower_bound: Option<Vec<u8>> = None;
+        while iter.is_valid() {
+            if matches!(&builder, Some(b) if b.estimated_size() >= self.options.target_sst_size) {
+                let mut full_builder = builder.take().unwrap();
+                let upper_bound = Some(iter.key());
+                for tombstone in &kept_range_tombstones {
+                    if let Some(clipped) = tombstone.clip(lower_bound.as_deref(), upper_bound) {
+                        full_builder.add_range_tombstone(clipped);
+                    }
+                }
+                new_ssts.push(self.build_sst(full_builder)?);
+                lower_bound = Some(iter.key().to_vec());
+            }
+            let builder = builder.get_or_insert_with(|| self.new_sst_builder(level));
+            self.add_versions(
+                &mut iter,
+                builder,
+                watermark,
+                range_tombstones,
+                blob_files,
+                level,
+                compact_to_bottom_level,
+            )?;
+        }
+        for tombstone in &kept_range_tombstones {
+            if let Some(clipped) = tombstone.clip(lower_bound.as_deref(), None) {
+                builder
+                    .get_or_insert_with(|| self.new_sst_builder(level))
+                    .add_range_tombstone(clipped);
+            }
+        }
+        if let Some(builder) = builder.filter(|builder| !builder.is_empty()) {
+            new_ssts.push(self.build_sst(builder)?);
+        }
+        Ok(new_ssts)
+    }
+
+    /// Add the versions of the key `iter` is at to `builder`, moving `iter` past them. Versions no
+    /// reader can see are dropped: every version above `watermark` is kept, but only the latest
+    /// one at or below it, unless one of `range_tombstones` at or below the watermark deletes it.
+    /// If that version is a merge operand, the versions before it are merged into it, reading the
+    /// value from `blob_files` if it is in a blob file. Blob pointers are kept as they are
+    /// otherwise.
+    ///
+    /// At the bottom level, nothing older is left for a deletion to shadow, so it is dropped, and
+    /// merge operands are merged into a value even if no value comes before them.
+    ///
+    /// The versions go to `level`, which is 0 for a flush. When compacting into a level below L0,
+    /// the latest version at or below the watermark goes through the compaction filter.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn add_versions(
+        &self,
+        iter: &mut impl StorageIterator,
+        builder: &mut SsTableBuilder,
+        watermark: u64,
+        range_tombstones: &[RangeTombstone],
+        blob_files: &BTreeMap<usize, Arc<BlobFile>>,
+        level: usize,
+        compact_to_bottom_level: bool,
+    ) -> Result<()> {
+        let key = iter.key().to_vec();
+        while iter.is_valid() && iter.key() == key && iter.seq() > watermark {
+            builder.add_with_type(&key, iter.seq(), iter.value_type(), iter.value());
+            iter.next()?;
+        }
+        // Only the versions up to the first value are needed to collapse the rest.
+        let mut versions = Vec::new();
+        while iter.is_valid() && iter.key() == key {
+            if matches!(versions.last(), None | Some((_, ValueType::Merge, _))) {
+                versions.push((
+                    iter.seq(),
+                    iter.value_type(),
+                    Bytes::copy_from_slice(iter.value()),
+                ));
+            }
+            iter.next()?;
+        }
+        let version = merge_operator::collapse_versions(
+            self.options.merge_operator.as_deref(),
+            &key,
+            &versions,
+            |seq| {
+                range_tombstones
+                    .iter()
+                    .any(|t| t.seq <= watermark && t.covers(&key, seq))
+            },
+            |pointer| blob::read_blob(blob_files, pointer),
+            compact_to_bottom_level,
+        )?;
+        // An expired value is deleted, so that the versions before it do not come back.
+        let version = version.map(|(seq, value_type, value)| match value_type {
+            ValueType::PutWithExpiry if ttl::live_value(&value).is_none() => {
+                (seq, ValueType::Delete, Bytes::new())
+            }
+            _ => (seq, value_type, value),
+        });
+        let version = match version {
+            Some(version) if level > 0 => Some(self.apply_compaction_filter(
+                &key,
+                version,
+                blob_files,
+                level,
+                compact_to_bottom_level,
+            )?),
+            version => version,
+        };
+        if let Some((seq, value_type, value)) = version {
+            if !(compact_to_bottom_level && value_type == ValueType::Delete) {
+                builder.add_with_type(&key, seq, value_type, &value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the compaction filter on a version of `key` compacted into `level`, if the version is a
+    /// value. A removed value is replaced with a tombstone at the same sequence number.
+    fn apply_compaction_filter(
+        &self,
+        key: &[u8],
+        version: (u64, ValueType, Bytes),
+        blob_files: &BTreeMap<usize, Arc<BlobFile>>,
+        level: usize,
+        compact_to_bottom_level: bool,
+    ) -> Result<(u64, ValueType, Bytes)> {
+        let Some(filter) = &self.options.compaction_filter else {
+            return Ok(version);
+        };
+        let (seq, value_type, value) = version;
+        let (full_value, expires_at) = match value_type {
+            ValueType::Put => (value.clone(), None),
+            ValueType::Blob => (blob::read_blob(blob_files, &value)?, None),
+            ValueType::PutWithExpiry => {
+                let (expires_at, full_value) = ttl::decode(&value);
+                (Bytes::copy_from_slice(full_value), Some(expires_at))
+            }
+            ValueType::Merge | ValueType::Delete => return Ok((seq, value_type, value)),
+        };
+        Ok(
+            match filter.filter(level, key, &full_value, compact_to_bottom_level) {
+                FilterDecision::Keep => (seq, value_type, value),
+                FilterDecision::Remove => (seq, ValueType::Delete, Bytes::new()),
+                // A rewritten value keeps its expiry time.
+                FilterDecision::ChangeValue(new_value) => match expires_at {
+                    Some(expires_at) => (
+                        seq,
+                        ValueType::PutWithExpiry,
+                        ttl::encode(expires_at, &new_value),
+                    ),
+                    None => (seq, ValueType::Put, new_value),
+                },
+            },
+        )
+    }
+
+    fn build_sst(&self, builder: SsTableBuilder) -> Result<Arc<SsTable>> {
+        let sst_id = self.next_sst_id();
+        Ok(Arc::new(builder.build(
+            sst_id,
+            Some(self.block_cache.clone()),
+            self.path_of_sst(sst_id),
+        )?))
+    }
+
+    fn compact(
+        &self,
+        snapshot: &LsmStorageInner,
+        task: &CompactionTask,
+        watermark: u64,
+    ) -> Result<Vec<Arc<SsTable>>> {
+        let select_ssts = |ssts: &[Arc<SsTable>], ids: &[usize]| {
+            let ids = ids.iter().collect::<HashSet<_>>();
+            ssts.iter()
+                .filter(|x| ids.contains(&x.sst_id()))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let l0_ssts = select_ssts(&snapshot.l0_sstables, &task.l0_sst_ids);
+        let level_ssts = task
+            .level_sst_ids
+            .iter()
+            .map(|(level, ids)| select_ssts(&snapshot.levels[level - 1], ids))
+            .collect::<Vec<_>>();
+        let range_tombstones = l0_ssts
+            .iter()
+            .chain(level_ssts.iter().flatten())
+            .flat_map(|x| x.range_tombstones().iter().cloned())
+            .collect::<Vec<_>>();
+        // Newer L0 SSTs come first, so that they take precedence in the merge iterator.
+        let mut l0_iters = Vec::with_capacity(l0_ssts.len());
+        for sst in l0_ssts.into_iter().rev() {
+            l0_iters.push(Box::new(SsTableIterator::create_and_seek_to_first(sst)?));
+        }
+        let mut level_iters = Vec::with_capacity(level_ssts.len());
+        for ssts in level_ssts {
+            level_iters.push(Box::new(SstConcatIterator::create_and_seek_to_first(ssts)?));
+        }
+        let iter = TwoMergeIterator::create(
+            MergeIterator::create(l0_iters),
+            MergeIterator::create(level_iters),
+        )?;
+        self.compact_generate_sst_from_iter(
+            iter,
+            task.output_level,
+            task.is_output_bottom_level,
+            watermark,
+            &range_tombstones,
+            &snapshot.blob_files,
+        )
+    }
+
+    /// Run one compaction if any level needs it. Returns whether a compaction was done.
+    pub(crate) fn trigger_compaction(&self) -> Result<bool> {
+        let _compaction_lock = self.compaction_lock.lock();
+        let watermark = self.compaction_watermark();
+        let snapshot = self.inner.read().clone();
+        let Some(task) = self.compaction_strategy.generate_compaction_task(&snapshot) else {
+            return Ok(false);
+        };
+
+        let new_ssts = if task.drop_inputs {
+            vec![]
+        } else if task.is_trivial_move() {
+            let moved = task.input_sst_ids().into_iter().collect::<HashSet<_>>();
+            snapshot
+                .levels
+                .iter()
+                .flatten()
+                .filter(|x| moved.contains(&x.sst_id()))
+                .cloned()
+                .collect()
+        } else {
+            self.compact(&snapshot, &task, watermark)?
+        };
+        self.apply_compaction(&task, new_ssts)?;
+        Ok(true)
+    }
+
+    /// Compact every SST whose keys overlap with the range between `lower` and `upper` into the
+    /// last level, dropping the tombstones and the versions no reader can see. The SSTs overlapping
+    /// with the compacted ones are compacted as well, until no SST left overlaps with the result.
+    /// Returns whether a compaction was done.
+    pub(crate) fn compact_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<bool> {
+        let _compaction_lock = self.compaction_lock.lock();
+        // A background compaction may have failed while the lock was held.
+        self.check_background_error()?;
+        let watermark = self.compaction_watermark();
+        let snapshot = self.inner.read().clone();
+        let output_level = snapshot.levels.len();
+        if output_level == 0 {
+            bail!("cannot compact a range without a level below L0");
+        }
+
+        let all_ssts = snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flatten());
+        let mut selected = all_ssts
+            .clone()
+            .filter(|x| overlaps_range(x, lower, upper))
+            .map(|x| x.sst_id())
+            .collect::<HashSet<_>>();
+        if selected.is_empty() {
+            return Ok(false);
+        }
+        loop {
+            let selected_ssts = all_ssts.clone().filter(|x| selected.contains(&x.sst_id()));
+            let first_key = selected_ssts.clone().map(|x| x.first_key()).min().unwrap();
+            let last_key = selected_ssts.map(|x| x.last_key()).max().unwrap();
+            let num_selected = selected.len();
+            selected.extend(
+                all_ssts
+                    .clone()
+                    .filter(|x| x.overlaps(first_key, last_key))
+                    .map(|x| x.sst_id()),
+            );
+            if selected.len() == num_selected {
+                break;
+            }
+        }
+
+        let select_ids = |ssts: &[Arc<SsTable>]| {
+            ssts.iter()
+                .map(|x| x.sst_id())
+                .filter(|id| selected.contains(id))
+                .collect::<Vec<_>>()
+        };
+        let task = CompactionTask {
+            l0_sst_ids: select_ids(&snapshot.l0_sstables),
+            level_sst_ids: snapshot
+                .levels
+                .iter()
+                .enumerate()
+                .map(|(idx, level)| (idx + 1, select_ids(level)))
+                .filter(|(_, ids)| !ids.is_empty())
+                .collect(),
+            output_level,
+            is_output_bottom_level: true,
+            drop_inputs: false,
+        };
+        let new_ssts = self.compact(&snapshot, &task, watermark)?;
+        self.apply_compaction(&task, new_ssts)?;
+        Ok(true)
+    }
+
+    /// Replace the input SSTs of `task` with `new_ssts` in the output level, and delete the files
+    /// of the input SSTs that are not among them.
+    fn apply_compaction(&self, task: &CompactionTask, new_ssts: Vec<Arc<SsTable>>) -> Result<()> {
+        let removed = task.input_sst_ids();
+        let kept = new_ssts.iter().map(|x| x.sst_id()).collect::<HashSet<_>>();
+        let deleted_ids = removed
+            .iter()
+            .copied()
+            .filter(|id| !kept.contains(id))
+            .collect::<Vec<_>>();
+        {
+            let _state_lock = self.state_lock.lock();
+            self.manifest.add_record(&ManifestRecord::Compaction {
+                removed: removed.clone(),
+                added: new_ssts
+                    .iter()
+                    .map(|x| (task.output_level, x.sst_id()))
+                    .collect(),
+            })?;
+
+            let removed = removed.into_iter().collect::<HashSet<_>>();
+            let mut guard = self.inner.write();
+            let mut snapshot = guard.as_ref().clone();
+            // New SSTs may have been flushed to L0 in the meantime, so only remove the SSTs in the
+            // task.
+            snapshot
+                .l0_sstables
+                .retain(|x| !removed.contains(&x.sst_id()));
+            for level in &mut snapshot.levels {
+                level.retain(|x| !removed.contains(&x.sst_id()));
+            }
+            if !new_ssts.is_empty() {
+                let output_level = &mut snapshot.levels[task.output_level - 1];
+                output_level.extend(new_ssts);
+                output_level.sort_by(|x, y| x.first_key().cmp(y.first_key()));
+            }
+            *guard = Arc::new(snapshot);
+        }
+
+        // Readers holding an older snapshot keep the files open, so they can still read them.
+        for sst_id in deleted_ids {
+            std::fs::remove_file(self.path_of_sst(sst_id))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn spawn_compaction_thread(
+        self: &Arc<Self>,
+        rx: crossbeam_channel::Receiver<()>,
+    ) -> Result<JoinHandle<()>> {
+        let this = self.clone();
+        let handle = std::thread::Builder::new()
+            .name("compaction".to_string())
+            .spawn(move || {
+                let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+                loop {
+                    crossbeam_channel::select! {
+                        recv(ticker) -> _ => {
+                            // A failed task would fail again, so nothing is retried after it.
+                            if this.check_background_error().is_err() {
+                                continue;
+                            }
+                            if let Err(e) = this.trigger_compaction() {
+                                this.set_background_error("compaction", e);
+                            } else if let Err(e) = this.trigger_blob_gc() {
+                                this.set_background_error("blob garbage collection", e);
+                            }
+                        },
+                        recv(rx) -> _ => return,
+                    }
+                }
+            })?;
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,70 @@
+use std::time::{Duration, SystemTime};
+
+use super::{CompactionStrategy, CompactionTask};
+use crate::lsm_storage::LsmStorageInner;
+
+#[derive(Debug, Clone, Default)]
+pub struct FifoCompactionOptions {
+    /// Delete the oldest SSTs while the SSTs in L0 take more than this many bytes.
+    pub max_table_files_size: Option<u64>,
+    /// Delete the SSTs written longer than this ago.
+    pub ttl: Option<Duration>,
+}
+
+/// FIFO compaction, for data that is only kept for a while, such as time series: SSTs are never
+/// merged, and stay in L0 until they are deleted, oldest first, once they are older than the TTL
+/// or all of them together are over the size limit.
+///
+/// Deleting an SST deletes all versions in it, so a key may go back to an older value still kept
+/// in a newer SST, or lose the value its merge operands apply to.
+pub struct FifoCompactionController {
+    options: FifoCompactionOptions,
+}
+
+impl FifoCompactionController {
+    pub fn new(options: FifoCompactionOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl CompactionStrategy for FifoCompactionController {
+    /// Pick the oldest L0 SSTs to delete, if they are over the TTL or the size limit.
+    fn generate_compaction_task(&self, snapshot: &LsmStorageInner) -> Option<CompactionTask> {
+        let now = SystemTime::now();
+        let is_expired = |created_at: SystemTime| match self.options.ttl {
+            Some(ttl) => now.duration_since(created_at).unwrap_or_default() > ttl,
+            None => false,
+        };
+        let mut total_size = snapshot
+            .l0_sstables
+            .iter()
+            .map(|x| x.table_size())
+            .sum::<u64>();
+        let mut l0_sst_ids = Vec::new();
+        // The oldest SSTs are at the front of L0.
+        for sst in &snapshot.l0_sstables {
+            let is_over_size =
+                matches!(self.options.max_table_files_size, Some(max) if total_size > max);
+            if !is_over_size && !is_expired(sst.created_at()) {
+                break;
+            }
+            l0_sst_ids.push(sst.sst_id());
+            total_size -= sst.table_size();
+        }
+        if l0_sst_ids.is_empty() {
+            return None;
+        }
+        Some(CompactionTask {
+            l0_sst_ids,
+            level_sst_ids: vec![],
+            output_level: 0,
+            is_output_bottom_level: false,
+            drop_inputs: true,
+        })
+    }
+
+    /// All SSTs stay in L0.
+    fn num_levels(&self) -> usize {
+        0
+    }
+}
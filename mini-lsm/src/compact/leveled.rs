@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use super::{is_bottom_level, CompactionStrategy, CompactionTask};
+use crate::lsm_storage::LsmStorageInner;
+use crate::table::SsTable;
+
+#[derive(Debug, Clone)]
+pub struct LeveledCompactionOptions {
+    /// Compact all L0 SSTs into L1 once there are this many of them.
+    pub level0_file_num_compaction_trigger: usize,
+    /// The number of levels below L0.
+    pub max_levels: usize,
+    /// The target size of L1 in bytes.
+    pub base_level_size: u64,
+    /// The fan-out ratio: each level is this many times larger than the level above it.
+    pub level_size_multiplier: u64,
+}
+
+impl Default for LeveledCompactionOptions {
+    fn default() -> Self {
+        Self {
+            level0_file_num_compaction_trigger: 4,
+            max_levels: 6,
+            base_level_size: 64 << 20,
+            level_size_multiplier: 10,
+        }
+    }
+}
+
+/// Leveled compaction: each level below L0 is a single sorted run, several times larger than the
+/// level above it. A level over its target size is compacted into the next one, an SST at a time.
+pub struct LeveledCompactionController {
+    options: LeveledCompactionOptions,
+}
+
+impl LeveledCompactionController {
+    pub fn new(options: LeveledCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    /// The target size of `level` in bytes. L1 is the first level.
+    pub fn target_size(&self, level: usize) -> u64 {
+        self.options.base_level_size * self.options.level_size_multiplier.pow(level as u32 - 1)
+    }
+
+    fn level_size(level: &[Arc<SsTable>]) -> u64 {
+        level.iter().map(|x| x.table_size()).sum()
+    }
+
+    fn find_overlapping_ssts(level: &[Arc<SsTable>], upper_ssts: &[&Arc<SsTable>]) -> Vec<usize> {
+        let first_key = upper_ssts.iter().map(|x| x.first_key()).min().unwrap();
+        let last_key = upper_ssts.iter().map(|x| x.last_key()).max().unwrap();
+        level
+            .iter()
+            .filter(|x| x.overlaps(first_key, last_key))
+            .map(|x| x.sst_id())
+            .collect()
+    }
+}
+
+impl CompactionStrategy for LeveledCompactionController {
+    /// Pick the next compaction to run, if any level exceeds its limit. L0 is compacted first;
+    /// otherwise the level with the largest size to target size ratio above 1 is compacted.
+    fn generate_compaction_task(&self, snapshot: &LsmStorageInner) -> Option<CompactionTask> {
+        if !snapshot.l0_sstables.is_empty()
+            && snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+        {
+            let upper_ssts = snapshot.l0_sstables.iter().collect::<Vec<_>>();
+            return Some(CompactionTask {
+                l0_sst_ids: upper_ssts.iter().map(|x| x.sst_id()).collect(),
+                level_sst_ids: vec![(
+                    1,
+                    Self::find_overlapping_ssts(&snapshot.levels[0], &upper_ssts),
+                )],
+                output_level: 1,
+                is_output_bottom_level: is_bottom_level(snapshot, 1),
+                drop_inputs: false,
+            });
+        }
+
+        // The last level has no level to be compacted into.
+        let mut max_ratio = 1.0;
+        let mut upper_level = None;
+        for level in 1..self.options.max_levels {
+            let ratio = Self::level_size(&snapshot.levels[level - 1]) as f64
+                / self.target_size(level) as f64;
+            if ratio > max_ratio {
+                max_ratio = ratio;
+                upper_level = Some(level);
+            }
+        }
+        let upper_level = upper_level?;
+        // Compact the oldest SST in the level.
+        let upper_sst = snapshot.levels[upper_level - 1]
+            .iter()
+            .min_by_key(|x| x.sst_id())
+            .unwrap();
+        Some(CompactionTask {
+            l0_sst_ids: Vec::new(),
+            level_sst_ids: vec![
+                (upper_level, vec![upper_sst.sst_id()]),
+                (
+                    upper_level + 1,
+                    Self::find_overlapping_ssts(&snapshot.levels[upper_level], &[upper_sst]),
+                ),
+            ],
+            output_level: upper_level + 1,
+            is_output_bottom_level: is_bottom_level(snapshot, upper_level + 1),
+            drop_inputs: false,
+        })
+    }
+
+    fn num_levels(&self) -> usize {
+        self.options.max_levels
+    }
+
+    fn check_options(&self) -> Result<()> {
+        if self.options.max_levels == 0 {
+            bail!("max_levels must be at least 1");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use super::tiered::{TieredCompactionController, TieredCompactionOptions};
+
+fn pick_sorted_runs(sizes: &[u64]) -> Option<std::ops::Range<usize>> {
+    TieredCompactionController::new(TieredCompactionOptions::default()).pick_sorted_runs(sizes)
+}
+
+#[test]
+fn test_tiered_trigger() {
+    assert_eq!(pick_sorted_runs(&[]), None);
+    assert_eq!(pick_sorted_runs(&[100, 100, 100]), None);
+}
+
+#[test]
+fn test_tiered_space_amplification() {
+    // The newer runs take 300% of the oldest one.
+    assert_eq!(pick_sorted_runs(&[100, 100, 100, 100]), Some(0..4));
+    assert_eq!(pick_sorted_runs(&[100, 100, 100, 150]), Some(0..4));
+}
+
+#[test]
+fn test_tiered_size_ratio() {
+    assert_eq!(pick_sorted_runs(&[1, 1, 2, 100000]), Some(0..3));
+    // A run much larger than the runs before it stops the group.
+    assert_eq!(pick_sorted_runs(&[1, 100, 100, 100000]), Some(1..3));
+    assert_eq!(pick_sorted_runs(&[100, 1, 1, 100000]), Some(0..3));
+}
+
+#[test]
+fn test_tiered_reduce_sorted_runs() {
+    // No group of runs has similar sizes, so the newest runs are compacted to leave 3 runs.
+    let sizes = [1, 100, 10000, 1000000, 100000000];
+    assert_eq!(pick_sorted_runs(&sizes), Some(0..3));
+}
@@ -0,0 +1,159 @@
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+
+use super::{is_bottom_level, CompactionStrategy, CompactionTask};
+use crate::lsm_storage::LsmStorageInner;
+
+#[derive(Debug, Clone)]
+pub struct TieredCompactionOptions {
+    /// Compact once there are this many sorted runs. Each L0 SST is a sorted run, and so is each
+    /// level below it that is not empty.
+    pub num_sorted_runs_trigger: usize,
+    /// Compact all sorted runs together once the runs other than the oldest one take this many
+    /// percent of its size, which bounds the space taken by overwritten versions.
+    pub max_size_amplification_percent: u64,
+    /// A sorted run is compacted together with the newer runs before it if it is at most this
+    /// many percent larger than all of them.
+    pub size_ratio: u64,
+    /// The smallest number of sorted runs compacted together by the size ratio.
+    pub min_merge_width: usize,
+    /// The number of levels below L0, which is the largest number of sorted runs outside L0.
+    pub max_levels: usize,
+}
+
+impl Default for TieredCompactionOptions {
+    fn default() -> Self {
+        Self {
+            num_sorted_runs_trigger: 4,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_levels: 6,
+        }
+    }
+}
+
+/// A sorted run: an L0 SST with the given ID, or a level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SortedRun {
+    L0(usize),
+    Level(usize),
+}
+
+/// Tiered (universal) compaction: sorted runs of similar sizes are compacted together into a
+/// single run, so that each version is rewritten fewer times than with leveled compaction, at the
+/// cost of more sorted runs to read and more space taken by overwritten versions.
+///
+/// The runs go from the newest to the oldest: L0 from its latest SST, then the levels from L1.
+/// A compaction merges consecutive runs into the level of the oldest one, or into the empty level
+/// right above the next older run if all of them are in L0, so that every level stays newer than
+/// the levels below it.
+pub struct TieredCompactionController {
+    options: TieredCompactionOptions,
+}
+
+impl TieredCompactionController {
+    pub fn new(options: TieredCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    /// Pick the sorted runs to compact together, given their sizes from the newest to the oldest.
+    ///
+    /// All runs are compacted if the space amplification is too large. Otherwise, the first group
+    /// of at least `min_merge_width` runs where each run is not much larger than the runs before
+    /// it is compacted. Failing that, the newest runs are compacted, so that fewer than
+    /// `num_sorted_runs_trigger` runs are left.
+    pub(super) fn pick_sorted_runs(&self, sizes: &[u64]) -> Option<Range<usize>> {
+        let num_runs = sizes.len();
+        let trigger = self.options.num_sorted_runs_trigger.max(2);
+        if num_runs < trigger {
+            return None;
+        }
+        let (oldest_size, newer_sizes) = sizes.split_last().unwrap();
+        let newer_size = newer_sizes.iter().sum::<u64>();
+        if newer_size * 100 >= self.options.max_size_amplification_percent * oldest_size {
+            return Some(0..num_runs);
+        }
+        for start in 0..num_runs - 1 {
+            let mut candidate_size = sizes[start];
+            let mut end = start + 1;
+            while end < num_runs
+                && sizes[end] * 100 <= candidate_size * (100 + self.options.size_ratio)
+            {
+                candidate_size += sizes[end];
+                end += 1;
+            }
+            if end - start >= self.options.min_merge_width.max(2) {
+                return Some(start..end);
+            }
+        }
+        Some(0..num_runs - trigger + 2)
+    }
+}
+
+impl CompactionStrategy for TieredCompactionController {
+    fn generate_compaction_task(&self, snapshot: &LsmStorageInner) -> Option<CompactionTask> {
+        let l0_runs = snapshot
+            .l0_sstables
+            .iter()
+            .rev()
+            .map(|sst| (SortedRun::L0(sst.sst_id()), sst.table_size()));
+        let level_runs = snapshot
+            .levels
+            .iter()
+            .enumerate()
+            .filter(|(_, level)| !level.is_empty())
+            .map(|(idx, level)| {
+                let size = level.iter().map(|sst| sst.table_size()).sum();
+                (SortedRun::Level(idx + 1), size)
+            });
+        let runs = l0_runs.chain(level_runs).collect::<Vec<_>>();
+        let sizes = runs.iter().map(|(_, size)| *size).collect::<Vec<_>>();
+        let Range { start, mut end } = self.pick_sorted_runs(&sizes)?;
+
+        let output_level = loop {
+            match (runs[end - 1].0, runs.get(end).map(|(run, _)| *run)) {
+                (SortedRun::Level(level), _) => break level,
+                // The older L0 SSTs must stay newer than the levels, so they go with the newer
+                // ones.
+                (SortedRun::L0(_), Some(SortedRun::L0(_))) => end += 1,
+                (SortedRun::L0(_), Some(SortedRun::Level(level))) if level > 1 => break level - 1,
+                // There is no empty level above L1.
+                (SortedRun::L0(_), Some(SortedRun::Level(_))) => end += 1,
+                (SortedRun::L0(_), None) => break self.options.max_levels,
+            }
+        };
+        let mut task = CompactionTask {
+            l0_sst_ids: Vec::new(),
+            level_sst_ids: Vec::new(),
+            output_level,
+            is_output_bottom_level: is_bottom_level(snapshot, output_level),
+            drop_inputs: false,
+        };
+        for (run, _) in &runs[start..end] {
+            match *run {
+                SortedRun::L0(sst_id) => task.l0_sst_ids.push(sst_id),
+                SortedRun::Level(level) => {
+                    let sst_ids = snapshot.levels[level - 1]
+                        .iter()
+                        .map(|sst| sst.sst_id())
+                        .collect();
+                    task.level_sst_ids.push((level, sst_ids));
+                }
+            }
+        }
+        Some(task)
+    }
+
+    fn num_levels(&self) -> usize {
+        self.options.max_levels
+    }
+
+    fn check_options(&self) -> Result<()> {
+        if self.options.max_levels == 0 {
+            bail!("max_levels must be at least 1");
+        }
+        Ok(())
+    }
+}
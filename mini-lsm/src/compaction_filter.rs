@@ -0,0 +1,40 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+/// What a [`CompactionFilter`] does with an entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Keep the entry as it is.
+    Keep,
+    /// Delete the key. Unless the compaction is into the bottom level, the entry is replaced with
+    /// a tombstone, so that the versions of the key in the levels below do not come back.
+    Remove,
+    /// Replace the value of the entry.
+    ChangeValue(Bytes),
+}
+
+/// Decides which entries are dropped or rewritten by compaction, for garbage collection defined
+/// by the application, such as dropping the values past an expiry time stored in them. Set with
+/// [`LsmStorageOptions::compaction_filter`](crate::lsm_storage::LsmStorageOptions).
+///
+/// The filter sees the latest value of each key that is not newer than the oldest snapshot, after
+/// the merge operands before it are merged into it. Newer versions, tombstones, and merge operands
+/// without a value are kept as they are. Flushes do not call the filter.
+pub trait CompactionFilter: Send + Sync {
+    /// Decide what to do with `value` of `key`, which is compacted into `level`. If
+    /// `is_bottom_level` is set, there are no older versions of the key below the level.
+    fn filter(
+        &self,
+        level: usize,
+        key: &[u8],
+        value: &[u8],
+        is_bottom_level: bool,
+    ) -> FilterDecision;
+}
+
+impl fmt::Debug for dyn CompactionFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CompactionFilter")
+    }
+}
@@ -1,6 +1,50 @@
+pub mod concat_iterator;
 pub mod merge_iterator;
 pub mod two_merge_iterator;
 
+/// The kind of write that produced an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    /// A value written by a put. The value may be empty.
+    Put,
+    /// A tombstone written by a deletion. Its value is always empty.
+    Delete,
+    /// An operand written by [`LsmStorage::merge`](crate::lsm_storage::LsmStorage::merge), which
+    /// reads merge onto the earlier versions of the key.
+    Merge,
+    /// A value stored in a blob file, only found in SSTs. Its value is the encoded
+    /// [`BlobPointer`](crate::blob::BlobPointer) to it, which reads resolve into the value.
+    Blob,
+    /// A value written by
+    /// [`LsmStorage::put_with_ttl`](crate::lsm_storage::LsmStorage::put_with_ttl), which reads
+    /// treat as deleted once it expires. Its value is `| expires_at (u64) | value |`, where
+    /// `expires_at` is in milliseconds since the UNIX epoch.
+    PutWithExpiry,
+}
+
+impl ValueType {
+    pub(crate) fn encode(self) -> u8 {
+        match self {
+            ValueType::Put => 0,
+            ValueType::Merge => 1,
+            ValueType::Delete => 2,
+            ValueType::Blob => 3,
+            ValueType::PutWithExpiry => 4,
+        }
+    }
+
+    pub(crate) fn decode(value_type: u8) -> Option<Self> {
+        match value_type {
+            0 => Some(ValueType::Put),
+            1 => Some(ValueType::Merge),
+            2 => Some(ValueType::Delete),
+            3 => Some(ValueType::Blob),
+            4 => Some(ValueType::PutWithExpiry),
+            _ => None,
+        }
+    }
+}
+
 pub trait StorageIterator {
     /// Get the current value.
     fn value(&self) -> &[u8];
@@ -8,6 +52,13 @@ pub trait StorageIterator {
     /// Get the current key.
     fn key(&self) -> &[u8];
 
+    /// Get the kind of write that produced the current entry.
+    fn value_type(&self) -> ValueType;
+
+    /// Get the sequence number of the write that produced the current entry. Iterators yield the
+    /// versions of a key from the latest to the earliest.
+    fn seq(&self) -> u64;
+
     /// Check if the current iterator is valid.
     fn is_valid(&self) -> bool;
 
@@ -15,5 +66,24 @@ pub trait StorageIterator {
     fn next(&mut self) -> anyhow::Result<()>;
 }
 
+/// An iterator that can also go backward, through the entries in the reverse order: by key from
+/// the largest to the smallest, and the versions of a key from the earliest to the latest.
+///
+/// Moving an invalid iterator in either direction leaves it invalid.
+pub trait BidirectionalIterator: StorageIterator {
+    /// Move to the previous position.
+    fn prev(&mut self) -> anyhow::Result<()>;
+
+    /// Seek to the last entry.
+    fn seek_to_last(&mut self) -> anyhow::Result<()>;
+}
+
+/// An iterator that can be moved to a key in place, so that a caller seeking repeatedly does not
+/// create a new iterator for each seek.
+pub trait SeekableIterator: StorageIterator {
+    /// Seek to the latest version of the first key which >= `key`.
+    fn seek_to_key(&mut self, key: &[u8]) -> anyhow::Result<()>;
+}
+
 #[cfg(test)]
 mod tests;
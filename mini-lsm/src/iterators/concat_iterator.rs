@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::{BidirectionalIterator, SeekableIterator, StorageIterator, ValueType};
+use crate::table::{SsTable, SsTableIterator};
+
+/// Concat multiple iterators ordered in key-order and their key ranges do not overlap. We do not
+/// want to create the iterators when initializing this iterator to reduce the overhead of
+/// seeking.
+///
+/// An SST may end where the next one starts, when its last key is the end of a range tombstone,
+/// which is not in the deleted range.
+pub struct SstConcatIterator {
+    current: Option<SsTableIterator>,
+    next_sst_idx: usize,
+    sstables: Vec<Arc<SsTable>>,
+}
+
+impl SstConcatIterator {
+    fn check_sst_valid(sstables: &[Arc<SsTable>]) {
+        for sst in sstables {
+            assert!(sst.first_key() <= sst.last_key());
+        }
+        if !sstables.is_empty() {
+            for i in 0..(sstables.len() - 1) {
+                assert!(sstables[i].last_key() <= sstables[i + 1].first_key());
+            }
+        }
+    }
+
+    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::check_sst_valid(&sstables);
+        if sstables.is_empty() {
+            return Ok(Self {
+                current: None,
+                next_sst_idx: 0,
+                sstables,
+            });
+        }
+        let mut iter = Self {
+            current: Some(SsTableIterator::create_and_seek_to_first(
+                sstables[0].clone(),
+            )?),
+            next_sst_idx: 1,
+            sstables,
+        };
+        iter.move_until_valid()?;
+        Ok(iter)
+    }
+
+    pub fn create_and_seek_to_last(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::check_sst_valid(&sstables);
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: sstables.len(),
+            sstables,
+        };
+        iter.seek_to_last()?;
+        Ok(iter)
+    }
+
+    pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: &[u8]) -> Result<Self> {
+        Self::check_sst_valid(&sstables);
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: sstables.len(),
+            sstables,
+        };
+        iter.seek_to_key(key)?;
+        Ok(iter)
+    }
+
+    /// Seek to the first key-value pair which >= `key`. The iterator of the current SST is reused
+    /// if the key is in it.
+    pub fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        let idx: usize = self
+            .sstables
+            .partition_point(|table| table.first_key() <= key)
+            .saturating_sub(1);
+        if idx >= self.sstables.len() {
+            self.current = None;
+            self.next_sst_idx = self.sstables.len();
+            return Ok(());
+        }
+        match self.current.as_mut() {
+            Some(iter) if self.next_sst_idx == idx + 1 => iter.seek_to_key(key)?,
+            _ => {
+                self.current = Some(SsTableIterator::create_and_seek_to_key(
+                    self.sstables[idx].clone(),
+                    key,
+                )?)
+            }
+        }
+        self.next_sst_idx = idx + 1;
+        self.move_until_valid()
+    }
+
+    fn move_until_valid(&mut self) -> Result<()> {
+        while let Some(iter) = self.current.as_mut() {
+            if iter.is_valid() {
+                break;
+            }
+            if self.next_sst_idx >= self.sstables.len() {
+                self.current = None;
+            } else {
+                self.current = Some(SsTableIterator::create_and_seek_to_first(
+                    self.sstables[self.next_sst_idx].clone(),
+                )?);
+                self.next_sst_idx += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [`SstConcatIterator::move_until_valid`], but moving to the SSTs before the current one.
+    fn move_back_until_valid(&mut self) -> Result<()> {
+        while let Some(iter) = self.current.as_mut() {
+            if iter.is_valid() {
+                break;
+            }
+            // `next_sst_idx` is the index of the current SST plus one.
+            if self.next_sst_idx <= 1 {
+                self.current = None;
+            } else {
+                self.next_sst_idx -= 1;
+                self.current = Some(SsTableIterator::create_and_seek_to_last(
+                    self.sstables[self.next_sst_idx - 1].clone(),
+                )?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for SstConcatIterator {
+    fn key(&self) -> &[u8] {
+        self.current.as_ref().unwrap().key()
+    }
+
+    fn seq(&self) -> u64 {
+        self.current.as_ref().unwrap().seq()
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.current.as_ref().unwrap().value_type()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().value()
+    }
+
+    fn is_valid(&self) -> bool {
+        if let Some(current) = &self.current {
+            assert!(current.is_valid());
+            true
+        } else {
+            false
+        }
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.current.as_mut().unwrap().next()?;
+        self.move_until_valid()?;
+        Ok(())
+    }
+}
+
+impl BidirectionalIterator for SstConcatIterator {
+    fn prev(&mut self) -> Result<()> {
+        if let Some(current) = self.current.as_mut() {
+            current.prev()?;
+            self.move_back_until_valid()?;
+        }
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        let Some(last) = self.sstables.last() else {
+            return Ok(());
+        };
+        self.current = Some(SsTableIterator::create_and_seek_to_last(last.clone())?);
+        self.next_sst_idx = self.sstables.len();
+        self.move_back_until_valid()
+    }
+}
+
+impl SeekableIterator for SstConcatIterator {
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        SstConcatIterator::seek_to_key(self, key)
+    }
+}
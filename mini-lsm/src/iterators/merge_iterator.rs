@@ -1,16 +1,17 @@
-use std::cmp::{self};
+use std::cmp::{self, Reverse};
 use std::collections::binary_heap::PeekMut;
 use std::collections::BinaryHeap;
 
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{BidirectionalIterator, SeekableIterator, StorageIterator, ValueType};
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
+/// An iterator with its index, and whether the merge goes in the reverse order.
+struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>, pub bool);
 
 impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
-        self.partial_cmp(other).unwrap() == cmp::Ordering::Equal
+        self.cmp(other) == cmp::Ordering::Equal
     }
 }
 
@@ -18,59 +19,79 @@ impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 
 impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(other.1.key()) {
-            cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
-            cmp::Ordering::Less => Some(cmp::Ordering::Less),
-            cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
-        }
-        .map(|x| x.reverse())
+        Some(self.cmp(other))
     }
 }
 
 impl<I: StorageIterator> Ord for HeapWrapper<I> {
     fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        let order =
+            (self.1.key(), Reverse(self.1.seq())).cmp(&(other.1.key(), Reverse(other.1.seq())));
+        // The heap pops the first entry in the order of the merge, and the smaller index among
+        // equal ones.
+        let order = if self.2 { order } else { order.reverse() };
+        order.then_with(|| other.0.cmp(&self.0))
     }
 }
 
-/// Merge multiple iterators of the same type. If the same key occurs multiple times in some
-/// iterators, perfer the one with smaller index.
+/// Merge multiple iterators of the same type. Entries are ordered by key and then from the latest
+/// version to the earliest. If the same version of a key occurs multiple times in some iterators,
+/// perfer the one with smaller index.
+///
+/// A merge iterator created by [`MergeIterator::create_rev`] goes through the entries in the
+/// reverse order instead.
 pub struct MergeIterator<I: StorageIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// The iterators that are no longer valid, kept for a seek to reuse them.
+    exhausted: Vec<HeapWrapper<I>>,
+    /// Moves an iterator to its next entry in the order of the merge.
+    advance: fn(&mut I) -> Result<()>,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
-        if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
-        }
-
-        let mut heap = BinaryHeap::new();
+        Self::create_inner(iters, false, I::next)
+    }
 
-        if iters.iter().all(|x| !x.is_valid()) {
-            // All invalid, select the last one as the current.
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
-        }
+    fn create_inner(iters: Vec<Box<I>>, reverse: bool, advance: fn(&mut I) -> Result<()>) -> Self {
+        let mut iter = Self {
+            iters: BinaryHeap::new(),
+            current: None,
+            exhausted: Vec::new(),
+            advance,
+        };
+        iter.reset(
+            iters
+                .into_iter()
+                .enumerate()
+                .map(|(idx, iter)| HeapWrapper(idx, iter, reverse))
+                .collect(),
+        );
+        iter
+    }
 
-        for (idx, iter) in iters.into_iter().enumerate() {
-            if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+    /// Put the valid iterators into the heap and select the current one. If all are invalid, select
+    /// the last one as the current.
+    fn reset(&mut self, iters: Vec<HeapWrapper<I>>) {
+        self.iters.clear();
+        self.exhausted.clear();
+        for iter in iters {
+            if iter.1.is_valid() {
+                self.iters.push(iter);
+            } else {
+                self.exhausted.push(iter);
             }
         }
+        self.current = self.iters.pop().or_else(|| self.exhausted.pop());
+    }
+}
 
-        let current = heap.pop().unwrap();
-        Self {
-            iters: heap,
-            current: Some(current),
-        }
+impl<I: BidirectionalIterator> MergeIterator<I> {
+    /// Merge iterators positioned at their last entries, going backward: by key from the largest
+    /// to the smallest, and the versions of a key from the earliest to the latest.
+    pub fn create_rev(iters: Vec<Box<I>>) -> Self {
+        Self::create_inner(iters, true, I::prev)
     }
 }
 
@@ -79,6 +100,16 @@ impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
         unsafe { self.current.as_ref().unwrap_unchecked() }.1.key()
     }
 
+    fn seq(&self) -> u64 {
+        unsafe { self.current.as_ref().unwrap_unchecked() }.1.seq()
+    }
+
+    fn value_type(&self) -> ValueType {
+        unsafe { self.current.as_ref().unwrap_unchecked() }
+            .1
+            .value_type()
+    }
+
     fn value(&self) -> &[u8] {
         unsafe { self.current.as_ref().unwrap_unchecked() }
             .1
@@ -94,34 +125,32 @@ impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
 
     fn next(&mut self) -> Result<()> {
         let current = unsafe { self.current.as_mut().unwrap_unchecked() };
-        // Pop the item out of the heap if they have the same value.
+        let advance = self.advance;
+        // Pop the item out of the heap if they have the same version of the key.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
-            debug_assert!(
-                inner_iter.1.key() >= current.1.key(),
-                "heap invariant violated"
-            );
-            if inner_iter.1.key() == current.1.key() {
+            debug_assert!(*inner_iter <= *current, "heap invariant violated");
+            if inner_iter.1.key() == current.1.key() && inner_iter.1.seq() == current.1.seq() {
                 // Case 1: an error occurred when calling `next`.
-                if let e @ Err(_) = inner_iter.1.next() {
+                if let e @ Err(_) = advance(&mut inner_iter.1) {
                     PeekMut::pop(inner_iter);
                     return e;
                 }
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
             }
         }
 
-        current.1.next()?;
+        advance(&mut current.1)?;
 
         // If the current iterator is invalid, pop it out of the heap and select the next one.
         if !current.1.is_valid() {
             if let Some(iter) = self.iters.pop() {
-                *current = iter;
+                self.exhausted.push(std::mem::replace(current, iter));
             }
             return Ok(());
         }
@@ -136,3 +165,21 @@ impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
         Ok(())
     }
 }
+
+impl<I: SeekableIterator> SeekableIterator for MergeIterator<I> {
+    /// Seek all iterators, including the ones that are no longer valid, to `key`. It only works
+    /// for a merge iterator created by [`MergeIterator::create`].
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        let mut iters = self
+            .iters
+            .drain()
+            .chain(self.exhausted.drain(..))
+            .chain(self.current.take())
+            .collect::<Vec<_>>();
+        let result = iters
+            .iter_mut()
+            .try_for_each(|iter| iter.1.seek_to_key(key));
+        self.reset(iters);
+        result
+    }
+}
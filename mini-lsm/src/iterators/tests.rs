@@ -1,7 +1,7 @@
 use anyhow::Result;
 use bytes::Bytes;
 
-use super::StorageIterator;
+use super::{BidirectionalIterator, SeekableIterator, StorageIterator, ValueType};
 
 pub mod merge_iterator_test;
 pub mod two_merge_iterator_test;
@@ -30,6 +30,15 @@ impl StorageIterator for MockIterator {
         self.data[self.index].0.as_ref()
     }
 
+    /// All entries are the same version.
+    fn seq(&self) -> u64 {
+        0
+    }
+
+    fn value_type(&self) -> ValueType {
+        ValueType::Put
+    }
+
     fn value(&self) -> &[u8] {
         self.data[self.index].1.as_ref()
     }
@@ -38,3 +47,26 @@ impl StorageIterator for MockIterator {
         self.index < self.data.len()
     }
 }
+
+impl BidirectionalIterator for MockIterator {
+    fn prev(&mut self) -> Result<()> {
+        if self.index == 0 {
+            self.index = self.data.len();
+        } else if self.index < self.data.len() {
+            self.index -= 1;
+        }
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.index = self.data.len().saturating_sub(1);
+        Ok(())
+    }
+}
+
+impl SeekableIterator for MockIterator {
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        self.index = self.data.partition_point(|(k, _)| &k[..] < key);
+        Ok(())
+    }
+}
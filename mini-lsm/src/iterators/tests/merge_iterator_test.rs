@@ -1,5 +1,6 @@
 use super::*;
 use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::SeekableIterator;
 
 fn as_bytes(x: &[u8]) -> Bytes {
     Bytes::copy_from_slice(x)
@@ -135,3 +136,66 @@ fn test_merge_empty() {
     let iter = MergeIterator::<MockIterator>::create(vec![]);
     check_iter_result(iter, vec![]);
 }
+
+fn rev_iter(data: &[(&'static str, &'static str)]) -> Box<MockIterator> {
+    let mut iter = MockIterator::new(
+        data.iter()
+            .map(|(k, v)| (Bytes::from(*k), Bytes::from(*v)))
+            .collect(),
+    );
+    iter.seek_to_last().unwrap();
+    Box::new(iter)
+}
+
+#[test]
+fn test_merge_rev() {
+    let i1 = rev_iter(&[("a", "1.1"), ("b", "2.1"), ("c", "3.1")]);
+    let i2 = rev_iter(&[("a", "1.2"), ("b", "2.2"), ("c", "3.2"), ("d", "4.2")]);
+    let i3 = rev_iter(&[("b", "2.3"), ("c", "3.3"), ("d", "4.3")]);
+    let i4 = rev_iter(&[]);
+
+    let iter = MergeIterator::create_rev(vec![i1, i2, i3, i4]);
+    check_iter_result(
+        iter,
+        vec![
+            (Bytes::from("d"), Bytes::from("4.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("b"), Bytes::from("2.1")),
+            (Bytes::from("a"), Bytes::from("1.1")),
+        ],
+    );
+}
+
+#[test]
+fn test_merge_seek() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("c"), Bytes::from("3.2")),
+        (Bytes::from("e"), Bytes::from("5.2")),
+    ]);
+    let i3 = MockIterator::new(vec![]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2), Box::new(i3)]);
+
+    iter.seek_to_key(b"c").unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"c"[..], &b"3.1"[..]));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"e"[..], &b"5.2"[..]));
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+
+    // The iterators that are no longer valid are seeked as well.
+    iter.seek_to_key(b"a").unwrap();
+    check_iter_result(
+        iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("1.1")),
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("e"), Bytes::from("5.2")),
+        ],
+    );
+}
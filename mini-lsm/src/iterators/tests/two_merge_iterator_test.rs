@@ -1,5 +1,6 @@
 use super::*;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::SeekableIterator;
 
 fn check_iter_result(iter: impl StorageIterator, expected: Vec<(Bytes, Bytes)>) {
     let mut iter = iter;
@@ -127,3 +128,49 @@ fn test_merge_5() {
     let iter = TwoMergeIterator::create(i1, i2).unwrap();
     check_iter_result(iter, vec![])
 }
+
+#[test]
+fn test_merge_rev() {
+    // The iterators merged in reverse go from the largest key to the smallest.
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("c"), Bytes::from("3.1")),
+        (Bytes::from("b"), Bytes::from("2.1")),
+        (Bytes::from("a"), Bytes::from("1.1")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("d"), Bytes::from("4.2")),
+        (Bytes::from("c"), Bytes::from("3.2")),
+        (Bytes::from("a"), Bytes::from("1.2")),
+    ]);
+    let iter = TwoMergeIterator::create_rev(i1, i2).unwrap();
+    check_iter_result(
+        iter,
+        vec![
+            (Bytes::from("d"), Bytes::from("4.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("b"), Bytes::from("2.1")),
+            (Bytes::from("a"), Bytes::from("1.1")),
+        ],
+    )
+}
+
+#[test]
+fn test_merge_seek() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("c"), Bytes::from("3.2")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+    ]);
+    let mut iter = TwoMergeIterator::create(i1, i2).unwrap();
+    iter.seek_to_key(b"b").unwrap();
+    check_iter_result(
+        iter,
+        vec![
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("d"), Bytes::from("4.2")),
+        ],
+    )
+}
@@ -1,29 +1,42 @@
+use std::cmp::Reverse;
+
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{SeekableIterator, StorageIterator, ValueType};
 
-/// Merges two iterators of different types into one. If the two iterators have the same key, only
-/// produce the key once and prefer the entry from A.
+/// Merges two iterators of different types into one. Entries are ordered by key and then from the
+/// latest version to the earliest. If the two iterators have the same version of a key, only
+/// produce it once and prefer the entry from A.
+///
+/// A two-merge iterator created by [`TwoMergeIterator::create_rev`] merges iterators going in the
+/// reverse order, such as the ones from [`MergeIterator::create_rev`](super::merge_iterator::MergeIterator::create_rev).
 pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
     a: A,
     b: B,
     choose_a: bool,
+    reverse: bool,
 }
 
 impl<A: StorageIterator, B: StorageIterator> TwoMergeIterator<A, B> {
-    fn choose_a(a: &A, b: &B) -> bool {
+    fn choose_a(a: &A, b: &B, reverse: bool) -> bool {
         if !a.is_valid() {
             return false;
         }
         if !b.is_valid() {
             return true;
         }
-        a.key() < b.key()
+        let (a, b) = ((a.key(), Reverse(a.seq())), (b.key(), Reverse(b.seq())));
+        if reverse {
+            a > b
+        } else {
+            a < b
+        }
     }
 
     fn skip_b(&mut self) -> Result<()> {
         if self.a.is_valid() {
-            while self.b.is_valid() && self.b.key() == self.a.key() {
+            while self.b.is_valid() && self.b.key() == self.a.key() && self.b.seq() == self.a.seq()
+            {
                 self.b.next()?;
             }
         }
@@ -31,13 +44,24 @@ impl<A: StorageIterator, B: StorageIterator> TwoMergeIterator<A, B> {
     }
 
     pub fn create(a: A, b: B) -> Result<Self> {
+        Self::create_inner(a, b, false)
+    }
+
+    /// Merge two iterators going backward: by key from the largest to the smallest, and the
+    /// versions of a key from the earliest to the latest.
+    pub fn create_rev(a: A, b: B) -> Result<Self> {
+        Self::create_inner(a, b, true)
+    }
+
+    fn create_inner(a: A, b: B, reverse: bool) -> Result<Self> {
         let mut iter = Self {
             choose_a: false,
             a,
             b,
+            reverse,
         };
         iter.skip_b()?;
-        iter.choose_a = Self::choose_a(&iter.a, &iter.b);
+        iter.choose_a = Self::choose_a(&iter.a, &iter.b, reverse);
         Ok(iter)
     }
 }
@@ -51,6 +75,22 @@ impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterato
         }
     }
 
+    fn seq(&self) -> u64 {
+        if self.choose_a {
+            self.a.seq()
+        } else {
+            self.b.seq()
+        }
+    }
+
+    fn value_type(&self) -> ValueType {
+        if self.choose_a {
+            self.a.value_type()
+        } else {
+            self.b.value_type()
+        }
+    }
+
     fn value(&self) -> &[u8] {
         if self.choose_a {
             self.a.value()
@@ -74,7 +114,17 @@ impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterato
             self.b.next()?;
         }
         self.skip_b()?;
-        self.choose_a = Self::choose_a(&self.a, &self.b);
+        self.choose_a = Self::choose_a(&self.a, &self.b, self.reverse);
+        Ok(())
+    }
+}
+
+impl<A: SeekableIterator, B: SeekableIterator> SeekableIterator for TwoMergeIterator<A, B> {
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        self.a.seek_to_key(key)?;
+        self.b.seek_to_key(key)?;
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(&self.a, &self.b, self.reverse);
         Ok(())
     }
 }
@@ -1,9 +1,21 @@
+pub mod blob;
 pub mod block;
+pub mod compact;
+pub mod compaction_filter;
 pub mod iterators;
 pub mod lsm_iterator;
 pub mod lsm_storage;
+pub mod manifest;
 pub mod mem_table;
+pub mod merge_operator;
+pub mod range_tombstone;
+pub mod snapshot;
 pub mod table;
+pub mod transaction;
+mod ttl;
+mod varint;
+pub mod wal;
+pub mod write_batch;
 
 #[cfg(test)]
 mod tests;
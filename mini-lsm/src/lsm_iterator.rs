@@ -1,73 +1,305 @@
+use std::collections::BTreeMap;
 use std::ops::Bound;
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bytes::Bytes;
 
+use crate::blob::{self, BlobFile};
+use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{SeekableIterator, StorageIterator, ValueType};
 use crate::mem_table::MemTableIterator;
+use crate::merge_operator::{self, MergeOperator};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableIterator;
+use crate::ttl;
 
-type LsmIteratorInner =
-    TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>;
+type LsmIteratorInner = TwoMergeIterator<
+    TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>,
+    MergeIterator<SstConcatIterator>,
+>;
 
+/// Iterates over the keys as of sequence number `read_seq`: for each key, only the latest version
+/// written at or before `read_seq` is produced, and deleted keys are skipped, including the ones
+/// covered by a range tombstone written after the version. A merge operand is produced merged onto
+/// the versions before it, and a value in a blob file is read from it.
+///
+/// An iterator created by [`LsmIterator::new_rev`] produces the keys from the largest to the
+/// smallest instead.
+///
+/// Seeking moves the memtable and SST iterators in place, and reads as of the same `read_seq`.
 pub struct LsmIterator {
     iter: LsmIteratorInner,
+    /// The upper bound of the keys, or the lower bound when going backward.
     end_bound: Bound<Bytes>,
+    /// Whether `iter` is valid and within `end_bound`.
     is_valid: bool,
+    read_seq: u64,
+    /// The key of the last version looked at. Its earlier versions are skipped.
+    prev_key: Vec<u8>,
+    /// The range tombstones visible at `read_seq`.
+    range_tombstones: Vec<RangeTombstone>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The blob files the SSTs point to.
+    blob_files: BTreeMap<usize, Arc<BlobFile>>,
+    /// The current entry as `(seq, value)`, with the key in `prev_key`, if it is only known after
+    /// `iter` moves past its versions: when it is merged from several versions or read from a blob
+    /// file, or when going backward, where the versions of a key come from the earliest to the latest.
+    entry: Option<(u64, Bytes)>,
+    reverse: bool,
 }
 
 impl LsmIterator {
-    pub(crate) fn new(iter: LsmIteratorInner, end_bound: Bound<Bytes>) -> Result<Self> {
+    pub(crate) fn new(
+        iter: LsmIteratorInner,
+        end_bound: Bound<Bytes>,
+        read_seq: u64,
+        range_tombstones: Vec<RangeTombstone>,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        blob_files: BTreeMap<usize, Arc<BlobFile>>,
+    ) -> Result<Self> {
+        Self::new_inner(
+            iter,
+            end_bound,
+            read_seq,
+            range_tombstones,
+            merge_operator,
+            blob_files,
+            false,
+        )
+    }
+
+    /// Create an iterator going backward over a reverse merge of the entries, stopping at the
+    /// lower bound `end_bound`.
+    pub(crate) fn new_rev(
+        iter: LsmIteratorInner,
+        end_bound: Bound<Bytes>,
+        read_seq: u64,
+        range_tombstones: Vec<RangeTombstone>,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        blob_files: BTreeMap<usize, Arc<BlobFile>>,
+    ) -> Result<Self> {
+        Self::new_inner(
+            iter,
+            end_bound,
+            read_seq,
+            range_tombstones,
+            merge_operator,
+            blob_files,
+            true,
+        )
+    }
+
+    fn new_inner(
+        iter: LsmIteratorInner,
+        end_bound: Bound<Bytes>,
+        read_seq: u64,
+        range_tombstones: Vec<RangeTombstone>,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        blob_files: BTreeMap<usize, Arc<BlobFile>>,
+        reverse: bool,
+    ) -> Result<Self> {
         let mut iter = Self {
-            is_valid: iter.is_valid(),
+            is_valid: false,
             iter,
             end_bound,
+            read_seq,
+            prev_key: Vec::new(),
+            range_tombstones,
+            merge_operator,
+            blob_files,
+            entry: None,
+            reverse,
         };
-        iter.move_to_non_delete()?;
+        iter.update_valid();
+        if reverse {
+            iter.move_to_visible_rev()?;
+        } else {
+            iter.move_to_visible()?;
+        }
         Ok(iter)
     }
 
+    fn update_valid(&mut self) {
+        self.is_valid = self.iter.is_valid()
+            && match (self.end_bound.as_ref(), self.reverse) {
+                (Bound::Unbounded, _) => true,
+                (Bound::Included(key), false) => self.iter.key() <= key.as_ref(),
+                (Bound::Excluded(key), false) => self.iter.key() < key.as_ref(),
+                (Bound::Included(key), true) => self.iter.key() >= key.as_ref(),
+                (Bound::Excluded(key), true) => self.iter.key() > key.as_ref(),
+            };
+    }
+
     fn next_inner(&mut self) -> Result<()> {
         self.iter.next()?;
-        if !self.iter.is_valid() {
-            self.is_valid = false;
-            return Ok(());
-        }
-        match self.end_bound.as_ref() {
-            Bound::Unbounded => {}
-            Bound::Included(key) => self.is_valid = self.iter.key() <= key.as_ref(),
-            Bound::Excluded(key) => self.is_valid = self.iter.key() < key.as_ref(),
-        }
+        self.update_valid();
         Ok(())
     }
 
-    fn move_to_non_delete(&mut self) -> Result<()> {
-        while self.is_valid() && self.iter.value().is_empty() {
+    /// Move to the latest visible version of the next key that is not deleted.
+    fn move_to_visible(&mut self) -> Result<()> {
+        self.entry = None;
+        loop {
+            while self.is_valid
+                && (self.iter.seq() > self.read_seq || self.iter.key() == self.prev_key)
+            {
+                self.next_inner()?;
+            }
+            if !self.is_valid {
+                return Ok(());
+            }
+            self.prev_key.clear();
+            self.prev_key.extend_from_slice(self.iter.key());
+            if matches!(
+                self.iter.value_type(),
+                ValueType::Merge | ValueType::Blob | ValueType::PutWithExpiry
+            ) {
+                // Only the versions up to the first value are needed to collapse the rest.
+                let mut versions = Vec::new();
+                while self.is_valid && self.iter.key() == self.prev_key {
+                    if matches!(versions.last(), None | Some((_, ValueType::Merge, _))) {
+                        versions.push(self.version());
+                    }
+                    self.next_inner()?;
+                }
+                self.entry = self.collapse_versions(&versions)?;
+                if self.entry.is_some() {
+                    return Ok(());
+                }
+                continue;
+            }
+            let (key, seq) = (self.iter.key(), self.iter.seq());
+            if self.iter.value_type() != ValueType::Delete
+                && !self.range_tombstones.iter().any(|t| t.covers(key, seq))
+            {
+                return Ok(());
+            }
             self.next_inner()?;
         }
+    }
+
+    /// Going backward, move past all versions of the previous key that is not deleted, and keep
+    /// the entry they collapse into in `entry`.
+    fn move_to_visible_rev(&mut self) -> Result<()> {
+        self.entry = None;
+        while self.is_valid {
+            self.prev_key.clear();
+            self.prev_key.extend_from_slice(self.iter.key());
+            let mut versions = Vec::new();
+            while self.is_valid && self.iter.key() == self.prev_key {
+                if self.iter.seq() <= self.read_seq {
+                    versions.push(self.version());
+                }
+                self.next_inner()?;
+            }
+            versions.reverse();
+            self.entry = self.collapse_versions(&versions)?;
+            if self.entry.is_some() {
+                return Ok(());
+            }
+        }
         Ok(())
     }
+
+    /// The version `iter` is at.
+    fn version(&self) -> (u64, ValueType, Bytes) {
+        (
+            self.iter.seq(),
+            self.iter.value_type(),
+            Bytes::copy_from_slice(self.iter.value()),
+        )
+    }
+
+    /// Collapse the visible versions of `prev_key`, from the latest to the earliest, into the
+    /// entry to produce, or `None` if the key is deleted.
+    fn collapse_versions(
+        &self,
+        versions: &[(u64, ValueType, Bytes)],
+    ) -> Result<Option<(u64, Bytes)>> {
+        let version = merge_operator::collapse_versions(
+            self.merge_operator.as_deref(),
+            &self.prev_key,
+            versions,
+            |seq| {
+                self.range_tombstones
+                    .iter()
+                    .any(|t| t.covers(&self.prev_key, seq))
+            },
+            |pointer| blob::read_blob(&self.blob_files, pointer),
+            true,
+        )?;
+        match version {
+            None | Some((_, ValueType::Delete, _)) => Ok(None),
+            Some((seq, ValueType::Blob, pointer)) => {
+                Ok(Some((seq, blob::read_blob(&self.blob_files, &pointer)?)))
+            }
+            Some((seq, ValueType::PutWithExpiry, value)) => {
+                Ok(ttl::live_value(&value).map(|value| (seq, value)))
+            }
+            Some((seq, _, value)) => Ok(Some((seq, value))),
+        }
+    }
+}
+
+impl SeekableIterator for LsmIterator {
+    /// Seek to the first key which >= `key` and is not deleted. The iterator still ends at the
+    /// upper bound of the scan, but it may move before the lower bound.
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        if self.reverse {
+            bail!("cannot seek an iterator going backward");
+        }
+        self.iter.seek_to_key(key)?;
+        self.prev_key.clear();
+        self.update_valid();
+        self.move_to_visible()
+    }
 }
 
 impl StorageIterator for LsmIterator {
     fn is_valid(&self) -> bool {
-        self.is_valid
+        self.entry.is_some() || (!self.reverse && self.is_valid)
     }
 
     fn key(&self) -> &[u8] {
-        self.iter.key()
+        if self.entry.is_some() {
+            &self.prev_key
+        } else {
+            self.iter.key()
+        }
+    }
+
+    fn seq(&self) -> u64 {
+        match &self.entry {
+            Some((seq, _)) => *seq,
+            None => self.iter.seq(),
+        }
+    }
+
+    /// Merge operands are merged, blob pointers are resolved and expiry times are stripped by the
+    /// iterator, so every entry is a value.
+    fn value_type(&self) -> ValueType {
+        ValueType::Put
     }
 
     fn value(&self) -> &[u8] {
-        self.iter.value()
+        match &self.entry {
+            Some((_, value)) => value,
+            None => self.iter.value(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        self.next_inner()?;
-        self.move_to_non_delete()?;
-        Ok(())
+        if self.reverse {
+            return self.move_to_visible_rev();
+        }
+        // `iter` is already past the versions of a collapsed entry.
+        if self.entry.is_none() {
+            self.next_inner()?;
+        }
+        self.move_to_visible()
     }
 }
 
@@ -83,6 +315,14 @@ impl<I: StorageIterator> FusedIterator<I> {
     }
 }
 
+impl<I: SeekableIterator> FusedIterator<I> {
+    /// Seek to `key`, even if the iterator is no longer valid. See
+    /// [`LsmIterator::seek_to_key`](SeekableIterator::seek_to_key) for the position it moves to.
+    pub fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek_to_key(key)
+    }
+}
+
 impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
     fn is_valid(&self) -> bool {
         self.iter.is_valid()
@@ -92,6 +332,14 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.key()
     }
 
+    fn seq(&self) -> u64 {
+        self.iter.seq()
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.iter.value_type()
+    }
+
     fn value(&self) -> &[u8] {
         self.iter.value()
     }
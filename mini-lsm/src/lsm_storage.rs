@@ -1,205 +1,1105 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::iter;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use bytes::Bytes;
-use parking_lot::{Mutex, RwLock};
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
 
+use crate::blob::{self, BlobFile, BlobFileBuilder};
 use crate::block::Block;
+use crate::compact::{CompactionStrategy, LeveledCompactionController, LeveledCompactionOptions};
+use crate::compaction_filter::CompactionFilter;
+use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{BidirectionalIterator, StorageIterator, ValueType};
 use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{map_bound, MemTable};
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::merge_operator::{self, MergeOperator};
+use crate::range_tombstone::RangeTombstone;
+use crate::snapshot::Snapshot;
+use crate::table::{
+    BlockCompressor, CompressionType, FileObject, SsTable, SsTableBuilder, SsTableIterator,
+};
+use crate::transaction::{CommittedWrites, IsolationLevel, Transaction};
+use crate::ttl;
+use crate::write_batch::{WriteBatch, WriteBatchRecord};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
+/// Options of the storage engine.
+#[derive(Debug, Clone)]
+pub struct LsmStorageOptions {
+    /// Block size in bytes.
+    pub block_size: usize,
+    /// Target size of the SSTs written by compaction, in bytes.
+    pub target_sst_size: usize,
+    /// The memtable is frozen and flushed in the background once its keys and values take this
+    /// many bytes.
+    pub write_buffer_size: usize,
+    /// Writes wait while this many frozen memtables are not flushed yet, so that the memtables do
+    /// not pile up in memory when writes outpace the flushes.
+    pub max_imm_memtables: usize,
+    /// Sync the WAL to disk on every write, so that the writes survive a power loss as soon as
+    /// they return, and not only a crash of the process. Each write waits for the disk then.
+    pub sync_writes: bool,
+    /// Number of bloom filter bits for each key in an SST. 0 disables bloom filters.
+    pub bloom_bits_per_key: usize,
+    /// The compression of the SSTs in each level, starting from L0. Levels past the end of the
+    /// list use its last entry, and no SST is compressed if it is empty. The SSTs are read with
+    /// the custom codecs in the list, so a codec must stay in it while SSTs written with it are
+    /// left.
+    pub compression_per_level: Vec<CompressionType>,
+    /// Options of leveled compaction, used unless another `compaction_strategy` is set.
+    pub compaction_options: LeveledCompactionOptions,
+    /// Decides which SSTs are compacted together, such as a
+    /// [`TieredCompactionController`](crate::compact::TieredCompactionController). `None` uses
+    /// leveled compaction with `compaction_options`.
+    pub compaction_strategy: Option<Arc<dyn CompactionStrategy>>,
+    /// Merges the operands written by [`LsmStorage::merge`]. A storage with merge operands must
+    /// be opened with the same operator.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Drops or rewrites entries during compaction.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+    /// Values of at least this many bytes are moved to blob files when they are flushed, and the
+    /// SSTs only keep pointers to them. `None` keeps all values in the SSTs.
+    pub min_blob_size: Option<usize>,
+    /// A blob file is garbage collected once this fraction of it is no longer pointed to.
+    pub blob_gc_dead_ratio: f64,
+}
+
+impl Default for LsmStorageOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            target_sst_size: 2 << 20,
+            write_buffer_size: 4 << 20,
+            max_imm_memtables: 4,
+            sync_writes: false,
+            bloom_bits_per_key: 10,
+            compression_per_level: vec![CompressionType::Snappy],
+            compaction_options: LeveledCompactionOptions::default(),
+            compaction_strategy: None,
+            merge_operator: None,
+            compaction_filter: None,
+            min_blob_size: None,
+            blob_gc_dead_ratio: 0.5,
+        }
+    }
+}
+
+impl LsmStorageOptions {
+    /// The compression of the SSTs in `level`, where level 0 is L0.
+    pub fn compression_of_level(&self, level: usize) -> CompressionType {
+        self.compression_per_level
+            .get(level)
+            .or(self.compression_per_level.last())
+            .cloned()
+            .unwrap_or(CompressionType::None)
+    }
+
+    /// The custom codecs in `compression_per_level`, to read the SSTs with.
+    fn custom_compressors(&self) -> Vec<Arc<dyn BlockCompressor>> {
+        self.compression_per_level
+            .iter()
+            .filter_map(|compression| match compression {
+                CompressionType::Custom(compressor) => Some(compressor.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
 #[derive(Clone)]
 pub struct LsmStorageInner {
     /// The current memtable.
-    memtable: Arc<MemTable>,
+    pub(crate) memtable: Arc<MemTable>,
     /// Immutable memTables, from earliest to latest.
-    imm_memtables: Vec<Arc<MemTable>>,
+    pub(crate) imm_memtables: Vec<Arc<MemTable>>,
     /// L0 SsTables, from earliest to latest.
-    l0_sstables: Vec<Arc<SsTable>>,
-    /// L1 - L6 SsTables, sorted by key range.
-    #[allow(dead_code)]
-    levels: Vec<Vec<Arc<SsTable>>>,
-    /// The next SSTable ID.
-    next_sst_id: usize,
+    pub(crate) l0_sstables: Vec<Arc<SsTable>>,
+    /// The SsTables of each level from L1, sorted by key range.
+    pub(crate) levels: Vec<Vec<Arc<SsTable>>>,
+    /// The blob files the SSTs point to, by ID.
+    pub(crate) blob_files: BTreeMap<usize, Arc<BlobFile>>,
 }
 
 impl LsmStorageInner {
-    fn create() -> Self {
-        Self {
-            memtable: Arc::new(MemTable::create()),
-            imm_memtables: vec![],
-            l0_sstables: vec![],
-            levels: vec![],
-            next_sst_id: 1,
-        }
+    /// The L0 SSTs, from the earliest to the latest.
+    pub fn l0_sstables(&self) -> &[Arc<SsTable>] {
+        &self.l0_sstables
+    }
+
+    /// The SSTs in each level from L1, sorted by key range.
+    pub fn levels(&self) -> &[Vec<Arc<SsTable>>] {
+        &self.levels
     }
 }
 
-/// The storage interface of the LSM tree.
-pub struct LsmStorage {
-    inner: Arc<RwLock<Arc<LsmStorageInner>>>,
+/// Counters of the bloom filter checks done by `get`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BloomFilterStats {
+    /// Number of times a bloom filter was checked. The SSTs without a filter are not counted.
+    pub checked: u64,
+    /// Number of times a bloom filter ruled out an SST, so that no block was read from it.
+    pub useful: u64,
+    /// Number of times a bloom filter passed but the SST did not contain the key.
+    pub false_positive: u64,
+}
+
+#[derive(Default)]
+struct BloomFilterCounters {
+    checked: AtomicU64,
+    useful: AtomicU64,
+    false_positive: AtomicU64,
+}
+
+/// The state shared by the storage handle and the background compaction thread.
+pub(crate) struct LsmStorageCore {
+    pub(crate) inner: RwLock<Arc<LsmStorageInner>>,
     flush_lock: Mutex<()>,
+    /// Held while applying a change to the structure of the tree, so that the manifest records
+    /// changes in the same order as they are applied.
+    pub(crate) state_lock: Mutex<()>,
+    /// Held while running a compaction, so that only one compaction runs at a time.
+    pub(crate) compaction_lock: Mutex<()>,
+    /// Held while applying a write, so that writes get their sequence numbers and become visible
+    /// in order.
+    pub(crate) write_lock: Mutex<()>,
+    /// The sequence number of the latest write. Reads see the writes up to it.
+    latest_seq: AtomicU64,
+    /// The sequence numbers of the live snapshots, with the number of snapshots at each.
+    snapshots: Mutex<BTreeMap<u64, usize>>,
+    /// The keys written while transactions are live, for conflict detection at commit.
+    pub(crate) committed_writes: Mutex<CommittedWrites>,
     path: PathBuf,
-    block_cache: Arc<BlockCache>,
+    pub(crate) block_cache: Arc<BlockCache>,
+    pub(crate) manifest: Manifest,
+    pub(crate) options: Arc<LsmStorageOptions>,
+    pub(crate) compaction_strategy: Arc<dyn CompactionStrategy>,
+    /// The blob files whose live values were written back by garbage collection, with the
+    /// sequence number of that write. Each is deleted once no snapshot before the write is left
+    /// and no SST points to it anymore.
+    pub(crate) collected_blob_files: Mutex<Vec<(usize, u64)>>,
+    /// The next SSTable ID. Memtables take their IDs from the same sequence, and each memtable is
+    /// flushed to the SST with its ID.
+    next_sst_id: AtomicUsize,
+    bloom_filter_counters: BloomFilterCounters,
+    /// Wakes up the flush thread when a memtable is frozen.
+    flush_notifier: Sender<()>,
+    flush_rx: Receiver<()>,
+    /// The first error of a background flush, compaction or blob garbage collection. The tree may
+    /// no longer be flushed or compacted after it, so writes, syncs and range compactions fail
+    /// with it until the storage is reopened.
+    background_error: Mutex<Option<String>>,
+    /// Notified with `background_error` locked when a memtable is flushed or a flush fails, to
+    /// wake up the writers waiting for the flushes to catch up.
+    memtable_flushed: Condvar,
+}
+
+/// The storage interface of the LSM tree.
+pub struct LsmStorage {
+    pub(crate) core: Arc<LsmStorageCore>,
+    /// Notifies the compaction thread to stop.
+    compaction_notifier: Sender<()>,
+    compaction_thread: Mutex<Option<JoinHandle<()>>>,
+    /// Notifies the flush thread to stop.
+    flush_notifier: Sender<()>,
+    flush_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for LsmStorage {
+    fn drop(&mut self) {
+        self.compaction_notifier.send(()).ok();
+        self.flush_notifier.send(()).ok();
+        if let Some(compaction_thread) = self.compaction_thread.lock().take() {
+            compaction_thread
+                .join()
+                .expect("compaction thread panicked");
+        }
+        if let Some(flush_thread) = self.flush_thread.lock().take() {
+            flush_thread.join().expect("flush thread panicked");
+        }
+    }
 }
 
 impl LsmStorage {
+    /// Open the storage at `path` with the default options.
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_options(path, LsmStorageOptions::default())
+    }
+
+    /// Open the storage at `path`, and start the background flush and compaction threads.
+    pub fn open_with_options(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        let core = Arc::new(LsmStorageCore::open(path, options)?);
+        let (compaction_notifier, rx) = crossbeam_channel::unbounded();
+        let compaction_thread = core.spawn_compaction_thread(rx)?;
+        let (flush_notifier, rx) = crossbeam_channel::unbounded();
+        let flush_thread = core.spawn_flush_thread(rx)?;
         Ok(Self {
-            inner: Arc::new(RwLock::new(Arc::new(LsmStorageInner::create()))),
-            flush_lock: Mutex::new(()),
-            path: path.as_ref().to_path_buf(),
-            block_cache: Arc::new(BlockCache::new(1 << 20)), // 4GB block cache
+            core,
+            compaction_notifier,
+            compaction_thread: Mutex::new(Some(compaction_thread)),
+            flush_notifier,
+            flush_thread: Mutex::new(Some(flush_thread)),
         })
     }
 
-    /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
+    /// Get a key from the storage. The bloom filter of an SST is checked before reading any block
+    /// from it.
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        let snapshot = {
-            let guard = self.inner.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
+        let (snapshot, read_seq) = self.core.read_view();
+        self.core.get(&snapshot, key, read_seq)
+    }
 
-        // Search on the current memtable.
-        if let Some(value) = snapshot.memtable.get(key) {
-            if value.is_empty() {
-                // found tomestone, return key not exists
-                return Ok(None);
-            }
-            return Ok(Some(value));
+    /// Put a key-value pair into the storage by writing into the current memtable. The memtable is
+    /// frozen once it reaches `write_buffer_size`, and flushed to L0 by a background thread.
+    /// Writes wait while `max_imm_memtables` memtables are not flushed yet, and fail once a
+    /// background flush has failed.
+    ///
+    /// The write is in the WAL once this returns, so it survives a crash of the process. It only
+    /// survives a power loss with `sync_writes`, or once [`LsmStorage::sync`] returns.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.core.put(key, value)
+    }
+
+    /// Put a key-value pair into the storage, which is deleted once `ttl` has passed. Reads treat
+    /// the value as deleted after that, and compaction drops it. It is as durable as a
+    /// [`LsmStorage::put`].
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.core.put_with_ttl(key, value, ttl)
+    }
+
+    /// Remove a key from the storage by writing a tombstone. It is as durable as a
+    /// [`LsmStorage::put`].
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.core.delete(key)
+    }
+
+    /// Remove every key in `[lower, upper)` from the storage, by writing a single range tombstone.
+    /// Fails if the range is empty.
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.core.delete_range(lower, upper)
+    }
+
+    /// Merge an operand into the value of a key with the `merge_operator` of the storage, without
+    /// reading the value. The operand is only merged when the key is read, or when flush and
+    /// compaction fold it into the versions of the key before it.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.core.merge(key, operand)
+    }
+
+    /// Apply all writes in the batch atomically. The writes share one sequence number, so reads see
+    /// all of them or none, and the batch is logged to the WAL as a single record, so it is
+    /// recovered completely or not at all. It is as durable as a [`LsmStorage::put`].
+    pub fn write(&self, batch: &WriteBatch) -> Result<()> {
+        self.core.write(batch)
+    }
+
+    /// Flush the current memtable and all frozen memtables to L0 SSTs, and remove their WALs.
+    ///
+    /// Every write made before this call survives a power loss once it returns, as the SSTs are
+    /// synced to disk before the WALs are removed.
+    pub fn sync(&self) -> Result<()> {
+        self.core.sync()
+    }
+
+    /// Create an iterator over a range of keys.
+    pub fn scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let (snapshot, read_seq) = self.core.read_view();
+        self.core.scan(&snapshot, lower, upper, read_seq)
+    }
+
+    /// Create an iterator over a range of keys, going from the largest key to the smallest.
+    pub fn scan_rev(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let (snapshot, read_seq) = self.core.read_view();
+        self.core.scan_rev(&snapshot, lower, upper, read_seq)
+    }
+
+    /// Take a snapshot of the storage. Reads through the snapshot see the writes done before it
+    /// was taken and none after, and compaction keeps the versions it needs until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.core.clone())
+    }
+
+    /// Start an optimistic transaction at [`IsolationLevel::RepeatableRead`]. Its writes are kept
+    /// in the transaction until [`Transaction::commit`], which fails if another write changed a key
+    /// the transaction read or wrote after it started.
+    pub fn new_txn(&self) -> Transaction {
+        self.new_txn_with_isolation(IsolationLevel::default())
+    }
+
+    /// Start an optimistic transaction at the given isolation level.
+    pub fn new_txn_with_isolation(&self, isolation_level: IsolationLevel) -> Transaction {
+        Transaction::new(self.core.clone(), isolation_level)
+    }
+
+    /// Flush the memtables, and compact every SST with keys in the range into the last level, to
+    /// reclaim the space taken by deleted and overwritten keys. Versions still visible to a
+    /// snapshot are kept. Blocks until the compaction is done, waiting for a background
+    /// compaction to finish first.
+    pub fn compact_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<()> {
+        self.core.sync()?;
+        self.core.compact_range(lower, upper)?;
+        Ok(())
+    }
+
+    /// Read all SSTs in the storage and check their checksums. The error of a corrupted SST can be
+    /// downcast to [`CorruptionError`](crate::table::CorruptionError).
+    pub fn verify_checksums(&self) -> Result<()> {
+        self.core.verify_checksums()
+    }
+
+    /// The bloom filter counters since the storage was opened, for tuning `bloom_bits_per_key`.
+    pub fn bloom_filter_stats(&self) -> BloomFilterStats {
+        let counters = &self.core.bloom_filter_counters;
+        BloomFilterStats {
+            checked: counters.checked.load(Ordering::Relaxed),
+            useful: counters.useful.load(Ordering::Relaxed),
+            false_positive: counters.false_positive.load(Ordering::Relaxed),
         }
-        // Search on immutable memtables.
-        for memtable in snapshot.imm_memtables.iter().rev() {
-            if let Some(value) = memtable.get(key) {
-                if value.is_empty() {
-                    // found tomestone, return key not exists
-                    return Ok(None);
+    }
+}
+
+impl LsmStorageCore {
+    /// Open the storage at `path`. The SSTs in each level are restored from the manifest, and the
+    /// memtables that were not flushed yet are rebuilt from their WALs as immutable memtables.
+    fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        let compaction_strategy = match &options.compaction_strategy {
+            Some(compaction_strategy) => compaction_strategy.clone(),
+            None => Arc::new(LeveledCompactionController::new(
+                options.compaction_options.clone(),
+            )),
+        };
+        compaction_strategy.check_options()?;
+        let path = path.as_ref();
+        let compressors = options.custom_compressors();
+        if let Some(compressor) = compressors.iter().find(|x| x.id() <= 1) {
+            bail!(
+                "compression ID {} is taken by a built-in codec",
+                compressor.id()
+            );
+        }
+        std::fs::create_dir_all(path)?;
+        let block_cache = Arc::new(BlockCache::new(1 << 20)); // 4GB block cache
+
+        let manifest_path = path.join("MANIFEST");
+        let (manifest, records) = if manifest_path.exists() {
+            Manifest::recover(&manifest_path)?
+        } else {
+            (Manifest::create(&manifest_path)?, Vec::new())
+        };
+
+        // Replay the manifest to find out which SST ids are in each level.
+        let mut memtable_ids = BTreeSet::new();
+        let mut l0_sst_ids = Vec::new();
+        let mut level_sst_ids: Vec<Vec<usize>> = Vec::new();
+        let mut blob_file_ids = BTreeSet::new();
+        let mut max_id = 0;
+        for record in records {
+            match record {
+                ManifestRecord::NewMemtable(id) => {
+                    memtable_ids.insert(id);
+                    max_id = max_id.max(id);
+                }
+                ManifestRecord::Flush(id) => {
+                    memtable_ids.remove(&id);
+                    l0_sst_ids.push(id);
+                }
+                ManifestRecord::Compaction { removed, added } => {
+                    let removed = removed.into_iter().collect::<HashSet<_>>();
+                    l0_sst_ids.retain(|x| !removed.contains(x));
+                    for level in &mut level_sst_ids {
+                        level.retain(|x| !removed.contains(x));
+                    }
+                    for (level, id) in added {
+                        max_id = max_id.max(id);
+                        if level == 0 {
+                            l0_sst_ids.push(id);
+                        } else {
+                            if level_sst_ids.len() < level {
+                                level_sst_ids.resize_with(level, Vec::new);
+                            }
+                            level_sst_ids[level - 1].push(id);
+                        }
+                    }
+                }
+                ManifestRecord::NewBlobFile(id) => {
+                    blob_file_ids.insert(id);
+                    max_id = max_id.max(id);
+                }
+                ManifestRecord::DeleteBlobFile(id) => {
+                    blob_file_ids.remove(&id);
                 }
-                return Ok(Some(value));
             }
         }
-        let mut iters = Vec::new();
-        iters.reserve(snapshot.l0_sstables.len());
-        for table in snapshot.l0_sstables.iter().rev() {
-            iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                table.clone(),
-                key,
-            )?));
+
+        let open_sst = |id: usize| -> Result<Arc<SsTable>> {
+            let file = FileObject::open(&Self::path_of_sst_static(path, id))
+                .with_context(|| format!("failed to open SST {}", id))?;
+            Ok(Arc::new(SsTable::open(
+                id,
+                Some(block_cache.clone()),
+                compressors.clone(),
+                file,
+            )?))
+        };
+        let l0_sstables = l0_sst_ids
+            .into_iter()
+            .map(open_sst)
+            .collect::<Result<Vec<_>>>()?;
+        let mut levels = level_sst_ids
+            .into_iter()
+            .map(|level| level.into_iter().map(open_sst).collect())
+            .collect::<Result<Vec<Vec<_>>>>()?;
+        let num_levels = compaction_strategy.num_levels();
+        if levels.len() < num_levels {
+            levels.resize_with(num_levels, Vec::new);
         }
-        let iter = MergeIterator::create(iters);
-        if iter.is_valid() {
-            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+        for level in &mut levels {
+            level.sort_by(|x, y| x.first_key().cmp(y.first_key()));
         }
-        Ok(None)
+        let blob_files = blob_file_ids
+            .into_iter()
+            .map(|id| {
+                let file = FileObject::open(&Self::path_of_blob_static(path, id))
+                    .with_context(|| format!("failed to open blob file {}", id))?;
+                Ok((id, Arc::new(BlobFile::open(id, file))))
+            })
+            .collect::<Result<_>>()?;
+
+        // Rebuild the memtables that were not flushed. A memtable without a WAL was empty when the
+        // previous run stopped.
+        let mut imm_memtables = Vec::with_capacity(memtable_ids.len());
+        for id in memtable_ids {
+            let wal_path = Self::path_of_wal_static(path, id);
+            if !wal_path.exists() {
+                continue;
+            }
+            let memtable = MemTable::recover_from_wal(id, &wal_path)?;
+            if memtable.is_empty() {
+                std::fs::remove_file(wal_path)?;
+            } else {
+                imm_memtables.push(Arc::new(memtable));
+            }
+        }
+
+        // Sequence numbers go on from the latest write that survived.
+        let latest_seq = imm_memtables
+            .iter()
+            .map(|x| x.max_seq())
+            .chain(
+                l0_sstables
+                    .iter()
+                    .chain(levels.iter().flatten())
+                    .map(|x| x.max_seq()),
+            )
+            .max()
+            .unwrap_or(0);
+
+        let memtable_id = max_id + 1;
+        manifest.add_record(&ManifestRecord::NewMemtable(memtable_id))?;
+        let memtable =
+            MemTable::create_with_wal(memtable_id, Self::path_of_wal_static(path, memtable_id))?;
+        // A single pending wake-up is enough, as the flush thread flushes all immutable memtables.
+        let (flush_notifier, flush_rx) = crossbeam_channel::bounded(1);
+        if !imm_memtables.is_empty() {
+            flush_notifier.send(())?;
+        }
+        let inner = LsmStorageInner {
+            memtable: Arc::new(memtable),
+            imm_memtables,
+            l0_sstables,
+            levels,
+            blob_files,
+        };
+
+        Ok(Self {
+            inner: RwLock::new(Arc::new(inner)),
+            flush_lock: Mutex::new(()),
+            state_lock: Mutex::new(()),
+            compaction_lock: Mutex::new(()),
+            write_lock: Mutex::new(()),
+            latest_seq: AtomicU64::new(latest_seq),
+            snapshots: Mutex::new(BTreeMap::new()),
+            committed_writes: Mutex::new(CommittedWrites::default()),
+            path: path.to_path_buf(),
+            block_cache,
+            manifest,
+            compaction_strategy,
+            collected_blob_files: Mutex::new(Vec::new()),
+            options: Arc::new(options),
+            next_sst_id: AtomicUsize::new(memtable_id + 1),
+            bloom_filter_counters: BloomFilterCounters::default(),
+            flush_notifier,
+            flush_rx,
+            background_error: Mutex::new(None),
+            memtable_flushed: Condvar::new(),
+        })
     }
 
-    /// Put a key-value pair into the storage by writing into the current memtable.
-    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        assert!(!value.is_empty(), "value cannot be empty");
-        assert!(!key.is_empty(), "key cannot be empty");
+    pub(crate) fn next_sst_id(&self) -> usize {
+        self.next_sst_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Create a builder for an SST to be placed in `level`, where level 0 is L0.
+    pub(crate) fn new_sst_builder(&self, level: usize) -> SsTableBuilder {
+        SsTableBuilder::new(self.options.block_size)
+            .with_bloom_bits_per_key(self.options.bloom_bits_per_key)
+            .with_compression(self.options.compression_of_level(level))
+    }
 
+    /// The current state of the tree, and the sequence number reads on it see writes up to.
+    ///
+    /// Both are read under the same lock, so a compaction installed in the state was started
+    /// before the sequence number was read, and kept every version visible at it.
+    pub(crate) fn read_view(&self) -> (Arc<LsmStorageInner>, u64) {
         let guard = self.inner.read();
-        guard.memtable.put(key, value);
+        let read_seq = self.latest_seq.load(Ordering::SeqCst);
+        (Arc::clone(&guard), read_seq)
+    }
 
-        Ok(())
+    /// Register a snapshot at the latest sequence number, and return it.
+    pub(crate) fn acquire_snapshot(&self) -> u64 {
+        let mut snapshots = self.snapshots.lock();
+        let seq = self.latest_seq.load(Ordering::SeqCst);
+        *snapshots.entry(seq).or_default() += 1;
+        seq
     }
 
-    /// Remove a key from the storage by writing an empty value.
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
-        assert!(!key.is_empty(), "key cannot be empty");
+    /// Deregister a snapshot taken by [`LsmStorageCore::acquire_snapshot`].
+    pub(crate) fn release_snapshot(&self, seq: u64) {
+        let mut snapshots = self.snapshots.lock();
+        let count = snapshots.get_mut(&seq).expect("snapshot not registered");
+        *count -= 1;
+        if *count == 0 {
+            snapshots.remove(&seq);
+        }
+    }
 
-        let guard = self.inner.read();
-        guard.memtable.put(key, b"");
+    /// The sequence number below which compaction only has to keep the latest version of each key:
+    /// the oldest live snapshot, or the latest write if there is none.
+    pub(crate) fn compaction_watermark(&self) -> u64 {
+        let snapshots = self.snapshots.lock();
+        let latest_seq = self.latest_seq.load(Ordering::SeqCst);
+        snapshots.keys().next().copied().unwrap_or(latest_seq)
+    }
+
+    /// Get the latest version of `key` visible at `read_seq` in `snapshot`.
+    pub(crate) fn get(
+        &self,
+        snapshot: &LsmStorageInner,
+        key: &[u8],
+        read_seq: u64,
+    ) -> Result<Option<Bytes>> {
+        let Some((seq, value_type, value)) = self.get_latest_version(snapshot, key, read_seq)?
+        else {
+            return Ok(None);
+        };
+        if value_type == ValueType::Merge {
+            // The operand is merged onto the earlier versions of the key, which a scan reads.
+            let iter = self.scan(
+                snapshot,
+                Bound::Included(key),
+                Bound::Included(key),
+                read_seq,
+            )?;
+            return Ok(iter
+                .is_valid()
+                .then(|| Bytes::copy_from_slice(iter.value())));
+        }
+        // A tombstone, or a version deleted by a later range deletion.
+        if value_type == ValueType::Delete
+            || Self::latest_covering_seq(snapshot, key, read_seq) > seq
+        {
+            return Ok(None);
+        }
+        match value_type {
+            ValueType::Blob => blob::read_blob(&snapshot.blob_files, &value).map(Some),
+            ValueType::PutWithExpiry => Ok(ttl::live_value(&value)),
+            _ => Ok(Some(value)),
+        }
+    }
+
+    /// Get the latest version of `key` visible at `read_seq` in `snapshot`, with its sequence
+    /// number and value type, without taking range tombstones into account.
+    pub(crate) fn get_latest_version(
+        &self,
+        snapshot: &LsmStorageInner,
+        key: &[u8],
+        read_seq: u64,
+    ) -> Result<Option<(u64, ValueType, Bytes)>> {
+        // Search on the current memtable, then on immutable memtables.
+        for memtable in iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter().rev()) {
+            if let Some(version) = memtable.get(key, read_seq) {
+                return Ok(Some(version));
+            }
+        }
+        for table in Self::ssts_containing(snapshot, key) {
+            if let Some(version) = self.get_from_sst(table, key, read_seq)? {
+                return Ok(Some(version));
+            }
+        }
+        Ok(None)
+    }
 
+    /// The SSTs whose key range contains `key`, from latest to earliest: the L0 SSTs, then one SST
+    /// in each level at most, since SSTs in a level do not overlap.
+    fn ssts_containing<'a>(
+        snapshot: &'a LsmStorageInner,
+        key: &'a [u8],
+    ) -> impl Iterator<Item = &'a Arc<SsTable>> {
+        let l0_ssts = snapshot.l0_sstables.iter().rev();
+        let level_ssts = snapshot.levels.iter().filter_map(move |level| {
+            let idx = level
+                .partition_point(|table| table.first_key() <= key)
+                .saturating_sub(1);
+            level.get(idx)
+        });
+        l0_ssts
+            .chain(level_ssts)
+            .filter(move |table| table.overlaps(key, key))
+    }
+
+    /// The sequence number of the latest range tombstone in `snapshot` visible at `read_seq` that
+    /// contains `key`, or 0 if there is none.
+    pub(crate) fn latest_covering_seq(
+        snapshot: &LsmStorageInner,
+        key: &[u8],
+        read_seq: u64,
+    ) -> u64 {
+        let memtables = iter::once(&snapshot.memtable).chain(&snapshot.imm_memtables);
+        let memtable_seq = memtables
+            .map(|memtable| memtable.latest_covering_seq(key, read_seq))
+            .max()
+            .unwrap_or(0);
+        let sst_seq = Self::ssts_containing(snapshot, key)
+            .map(|table| table.latest_covering_seq(key, read_seq))
+            .max()
+            .unwrap_or(0);
+        memtable_seq.max(sst_seq)
+    }
+
+    /// The range tombstones in `snapshot` visible at `read_seq`.
+    fn range_tombstones(snapshot: &LsmStorageInner, read_seq: u64) -> Vec<RangeTombstone> {
+        let mut tombstones = Vec::new();
+        for memtable in iter::once(&snapshot.memtable).chain(&snapshot.imm_memtables) {
+            tombstones.extend(memtable.range_tombstones());
+        }
+        for table in snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flatten())
+        {
+            tombstones.extend(table.range_tombstones().iter().cloned());
+        }
+        tombstones.retain(|t| t.seq <= read_seq);
+        tombstones
+    }
+
+    /// Get the version of `key` visible at `read_seq` in a single SST, with its sequence number
+    /// and value type, checking the bloom filter first.
+    fn get_from_sst(
+        &self,
+        table: &Arc<SsTable>,
+        key: &[u8],
+        read_seq: u64,
+    ) -> Result<Option<(u64, ValueType, Bytes)>> {
+        let has_bloom_filter = table.has_bloom_filter();
+        let counters = &self.bloom_filter_counters;
+        if has_bloom_filter {
+            counters.checked.fetch_add(1, Ordering::Relaxed);
+            if !table.may_contain(key) {
+                counters.useful.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+        }
+        let (version, has_key) = table.lookup(key, read_seq)?;
+        // The filter rightly passes an SST with only versions of the key newer than `read_seq`.
+        if has_bloom_filter && !has_key {
+            counters.false_positive.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(version)
+    }
+
+    fn verify_checksums(&self) -> Result<()> {
+        let snapshot = {
+            let guard = self.inner.read();
+            Arc::clone(&guard)
+        };
+        for table in snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flatten())
+        {
+            table.verify_checksums()?;
+        }
         Ok(())
     }
 
-    fn path_of_sst(&self, id: usize) -> PathBuf {
-        self.path.join(format!("{:05}.sst", id))
+    fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write(&batch)
     }
 
-    /// Persist data to disk.
-    ///
-    /// In day 3: flush the current memtable to disk as L0 SST.
-    /// In day 6: call `fsync` on WAL.
-    pub fn sync(&self) -> Result<()> {
-        let _flush_lock = self.flush_lock.lock();
+    fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put_with_ttl(key, value, ttl);
+        self.write(&batch)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(&batch)
+    }
+
+    fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        if lower >= upper {
+            bail!("range cannot be empty");
+        }
+        let mut batch = WriteBatch::new();
+        batch.delete_range(lower, upper);
+        self.write(&batch)
+    }
+
+    fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.merge(key, operand);
+        self.write(&batch)
+    }
+
+    /// Fail with the error of a background task, if one failed.
+    pub(crate) fn check_background_error(&self) -> Result<()> {
+        match &*self.background_error.lock() {
+            Some(e) => bail!("{}", e),
+            None => Ok(()),
+        }
+    }
+
+    /// Record the error of a background task, unless one is recorded already, and wake up the
+    /// writers waiting for a flush.
+    pub(crate) fn set_background_error(&self, task: &str, e: anyhow::Error) {
+        let mut background_error = self.background_error.lock();
+        background_error.get_or_insert_with(|| format!("{} failed: {:#}", task, e));
+        self.memtable_flushed.notify_all();
+    }
+
+    fn write(&self, batch: &WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let has_merges = batch
+            .records()
+            .iter()
+            .any(|record| matches!(record, WriteBatchRecord::Merge(..)));
+        if has_merges {
+            let records = merge_operator::merge_batch_records(
+                self.options.merge_operator.as_deref(),
+                batch.records(),
+            )?;
+            return self.write_entries(&records);
+        }
+        self.write_entries(batch.records())
+    }
 
-        let flush_memtable;
-        let sst_id;
+    /// Write the entries to the memtable with the next sequence number, and make them visible to
+    /// reads once all of them are in.
+    fn write_entries(&self, entries: &[WriteBatchRecord]) -> Result<()> {
+        self.write_entries_locked(self.write_lock.lock(), entries)
+    }
+
+    /// Like [`LsmStorageCore::write_entries`], with the write lock already held. The lock is
+    /// released once the entries are visible.
+    pub(crate) fn write_entries_locked(
+        &self,
+        write_lock: MutexGuard<()>,
+        entries: &[WriteBatchRecord],
+    ) -> Result<()> {
+        self.write_entries_inner(write_lock, entries, true)
+    }
+
+    /// Like [`LsmStorageCore::write_entries_locked`], for entries that rewrite the visible values
+    /// of their keys without changing them, like the values written back by blob garbage
+    /// collection. They are not recorded for the transactions, so that they do not conflict.
+    pub(crate) fn rewrite_entries_locked(
+        &self,
+        write_lock: MutexGuard<()>,
+        entries: &[WriteBatchRecord],
+    ) -> Result<()> {
+        self.write_entries_inner(write_lock, entries, false)
+    }
+
+    fn write_entries_inner(
+        &self,
+        write_lock: MutexGuard<()>,
+        entries: &[WriteBatchRecord],
+        record_for_txns: bool,
+    ) -> Result<()> {
+        self.check_background_error()?;
+
+        let memtable_size = {
+            let seq = self.latest_seq.load(Ordering::SeqCst) + 1;
+            // The memtable cannot be frozen while the state is read-locked, so all entries go into
+            // the same memtable.
+            let guard = self.inner.read();
+            guard.memtable.put_batch(seq, entries)?;
+            if self.options.sync_writes {
+                guard.memtable.sync_wal()?;
+            }
+            // Transactions start at the latest sequence number with this lock held, so a live
+            // transaction either sees the write or finds it in the log at commit.
+            let mut committed_writes = self.committed_writes.lock();
+            if record_for_txns {
+                committed_writes.record(seq, entries);
+            }
+            self.latest_seq.store(seq, Ordering::SeqCst);
+            guard.memtable.approximate_size()
+        };
+        drop(write_lock);
+        self.try_freeze(memtable_size)
+    }
+
+    fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.sst", id))
+    }
+
+    pub(crate) fn path_of_sst(&self, id: usize) -> PathBuf {
+        Self::path_of_sst_static(&self.path, id)
+    }
 
-        // Move mutable memtable to immutable memtables.
+    fn path_of_blob_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.blob", id))
+    }
+
+    pub(crate) fn path_of_blob(&self, id: usize) -> PathBuf {
+        Self::path_of_blob_static(&self.path, id)
+    }
+
+    fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.wal", id))
+    }
+
+    fn path_of_wal(&self, id: usize) -> PathBuf {
+        Self::path_of_wal_static(&self.path, id)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.check_background_error()?;
+        let _flush_lock = self.flush_lock.lock();
         {
-            let mut guard = self.inner.write();
-            // Swap the current memtable with a new one.
-            let mut snapshot = guard.as_ref().clone();
-            let memtable = std::mem::replace(&mut snapshot.memtable, Arc::new(MemTable::create()));
-            flush_memtable = memtable.clone();
-            sst_id = snapshot.next_sst_id;
-            // Add the memtable to the immutable memtables.
-            snapshot.imm_memtables.push(memtable);
-            // Update the snapshot.
-            *guard = Arc::new(snapshot);
-        }
-
-        // At this point, the old memtable should be disabled for write, and all write threads
-        // should be operating on the new memtable. We can safely flush the old memtable to
-        // disk.
-
-        let mut builder = SsTableBuilder::new(4096);
-        flush_memtable.flush(&mut builder)?;
-        let sst = Arc::new(builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
+            let state_lock = self.state_lock.lock();
+            self.force_freeze_memtable(&state_lock)?;
+        }
+        self.flush_imm_memtables(&_flush_lock)
+    }
+
+    /// Freeze the memtable if it has reached `write_buffer_size`, and wake up the flush thread.
+    /// Waits first while there are `max_imm_memtables` immutable memtables.
+    fn try_freeze(&self, memtable_size: usize) -> Result<()> {
+        if memtable_size < self.options.write_buffer_size {
+            return Ok(());
+        }
+        self.wait_for_flush()?;
+        let state_lock = self.state_lock.lock();
+        // Another writer may have frozen the memtable while we were waiting for the lock.
+        let memtable_size = self.inner.read().memtable.approximate_size();
+        if memtable_size >= self.options.write_buffer_size {
+            self.force_freeze_memtable(&state_lock)?;
+            // The flush thread is already woken up if the channel is full.
+            self.flush_notifier.try_send(()).ok();
+        }
+        Ok(())
+    }
+
+    /// Wait until there are fewer than `max_imm_memtables` immutable memtables, or a flush fails.
+    fn wait_for_flush(&self) -> Result<()> {
+        let max_imm_memtables = self.options.max_imm_memtables.max(1);
+        let mut background_error = self.background_error.lock();
+        loop {
+            if let Some(e) = &*background_error {
+                bail!("{}", e);
+            }
+            if self.inner.read().imm_memtables.len() < max_imm_memtables {
+                return Ok(());
+            }
+            self.memtable_flushed.wait(&mut background_error);
+        }
+    }
+
+    /// Move the mutable memtable to the immutable memtables, and create a new one with its WAL.
+    fn force_freeze_memtable(&self, _state_lock_observer: &MutexGuard<()>) -> Result<()> {
+        let memtable_id = self.next_sst_id();
+        self.manifest
+            .add_record(&ManifestRecord::NewMemtable(memtable_id))?;
+        let new_memtable = Arc::new(MemTable::create_with_wal(
+            memtable_id,
+            self.path_of_wal(memtable_id),
         )?);
+        let mut guard = self.inner.write();
+        // Swap the current memtable with a new one.
+        let mut snapshot = guard.as_ref().clone();
+        let memtable = std::mem::replace(&mut snapshot.memtable, new_memtable);
+        // Add the memtable to the immutable memtables.
+        snapshot.imm_memtables.push(memtable);
+        // Update the snapshot.
+        *guard = Arc::new(snapshot);
+        Ok(())
+    }
 
-        // Add the flushed L0 table to the list.
-        {
-            let mut guard = self.inner.write();
-            let mut snapshot = guard.as_ref().clone();
-            // Remove the memtable from the immutable memtables.
-            snapshot.imm_memtables.pop();
-            // Add L0 table
-            snapshot.l0_sstables.push(sst);
-            // Update SST ID
-            snapshot.next_sst_id += 1;
-            // Update the snapshot.
-            *guard = Arc::new(snapshot);
+    /// Flush all immutable memtables to L0 SSTs, from earliest to latest.
+    fn flush_imm_memtables(&self, _flush_lock_observer: &MutexGuard<()>) -> Result<()> {
+        // Frozen memtables are disabled for write, and all write threads are operating on the
+        // current memtable, so they can be safely flushed to disk.
+        loop {
+            let flush_memtable = {
+                let guard = self.inner.read();
+                match guard.imm_memtables.first() {
+                    Some(memtable) => memtable.clone(),
+                    None => break,
+                }
+            };
+
+            // An SST is named after the memtable it is flushed from.
+            let sst_id = flush_memtable.id();
+            let (sst, blob_file) = if flush_memtable.is_empty() {
+                (None, None)
+            } else {
+                let mut builder = self.new_sst_builder(0);
+                if let Some(min_blob_size) = self.options.min_blob_size {
+                    let blob_file = BlobFileBuilder::new(self.next_sst_id());
+                    builder = builder.with_blob_file(blob_file, min_blob_size);
+                }
+                self.flush_memtable(&flush_memtable, &mut builder)?;
+                // The blob file is written first, so that the SST never points to a missing file.
+                let blob_file = match builder.take_blob_file() {
+                    Some(blob_file) if !blob_file.is_empty() => {
+                        let path = self.path_of_blob(blob_file.id());
+                        Some(Arc::new(blob_file.build(path)?))
+                    }
+                    _ => None,
+                };
+                let sst = builder.build(
+                    sst_id,
+                    Some(self.block_cache.clone()),
+                    self.path_of_sst(sst_id),
+                )?;
+                (Some(Arc::new(sst)), blob_file)
+            };
+
+            // Add the flushed L0 table to the list.
+            {
+                let _state_lock = self.state_lock.lock();
+                if let Some(blob_file) = &blob_file {
+                    self.manifest
+                        .add_record(&ManifestRecord::NewBlobFile(blob_file.id()))?;
+                }
+                if sst.is_some() {
+                    self.manifest.add_record(&ManifestRecord::Flush(sst_id))?;
+                }
+                let mut guard = self.inner.write();
+                let mut snapshot = guard.as_ref().clone();
+                // Remove the memtable from the immutable memtables.
+                snapshot.imm_memtables.remove(0);
+                // Add L0 table
+                if let Some(sst) = sst {
+                    snapshot.l0_sstables.push(sst);
+                }
+                if let Some(blob_file) = blob_file {
+                    snapshot.blob_files.insert(blob_file.id(), blob_file);
+                }
+                // Update the snapshot.
+                *guard = Arc::new(snapshot);
+            }
+            {
+                let _background_error = self.background_error.lock();
+                self.memtable_flushed.notify_all();
+            }
+
+            // The memtable is now persisted in the SST, so its WAL is no longer needed.
+            std::fs::remove_file(self.path_of_wal(sst_id))?;
         }
 
         Ok(())
     }
 
-    /// Create an iterator over a range of keys.
-    pub fn scan(
+    /// Write a memtable to an L0 SST. Like compaction, only the latest version of each key at or
+    /// below the watermark is kept, with the merge operands before it folded into it. Values go to
+    /// the blob file of `builder`, if it has one.
+    fn flush_memtable(&self, memtable: &MemTable, builder: &mut SsTableBuilder) -> Result<()> {
+        let watermark = self.compaction_watermark();
+        let range_tombstones = memtable.range_tombstones();
+        let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        while iter.is_valid() {
+            // Memtables hold values, never blob pointers.
+            self.add_versions(
+                &mut iter,
+                builder,
+                watermark,
+                &range_tombstones,
+                &BTreeMap::new(),
+                0,
+                false,
+            )?;
+        }
+        for tombstone in range_tombstones {
+            builder.add_range_tombstone(tombstone);
+        }
+        Ok(())
+    }
+
+    fn spawn_flush_thread(self: &Arc<Self>, rx: Receiver<()>) -> Result<JoinHandle<()>> {
+        let this = self.clone();
+        let flush_rx = self.flush_rx.clone();
+        let handle = std::thread::Builder::new()
+            .name("flush".to_string())
+            .spawn(move || loop {
+                crossbeam_channel::select! {
+                    recv(flush_rx) -> _ => {
+                        let flush_lock = this.flush_lock.lock();
+                        if let Err(e) = this.flush_imm_memtables(&flush_lock) {
+                            this.set_background_error("flush", e);
+                        }
+                    },
+                    recv(rx) -> _ => return,
+                }
+            })?;
+        Ok(handle)
+    }
+
+    /// Create an iterator over a range of keys in `snapshot`, as of `read_seq`.
+    pub(crate) fn scan(
         &self,
+        snapshot: &LsmStorageInner,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
+        read_seq: u64,
     ) -> Result<FusedIterator<LsmIterator>> {
-        let snapshot = {
-            let guard = self.inner.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
-
-        let mut memtable_iters = Vec::new();
-        memtable_iters.reserve(snapshot.imm_memtables.len() + 1);
+        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
         memtable_iters.push(Box::new(snapshot.memtable.scan(lower, upper)));
         for memtable in snapshot.imm_memtables.iter().rev() {
             memtable_iters.push(Box::new(memtable.scan(lower, upper)));
         }
         let memtable_iter = MergeIterator::create(memtable_iters);
 
-        let mut table_iters = Vec::new();
-        table_iters.reserve(snapshot.l0_sstables.len());
+        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
         for table in snapshot.l0_sstables.iter().rev() {
             let iter = match lower {
                 Bound::Included(key) => {
@@ -207,7 +1107,7 @@ impl LsmStorage {
                 }
                 Bound::Excluded(key) => {
                     let mut iter = SsTableIterator::create_and_seek_to_key(table.clone(), key)?;
-                    if iter.is_valid() && iter.key() == key {
+                    while iter.is_valid() && iter.key() == key {
                         iter.next()?;
                     }
                     iter
@@ -219,11 +1119,111 @@ impl LsmStorage {
         }
         let table_iter = MergeIterator::create(table_iters);
 
-        let iter = TwoMergeIterator::create(memtable_iter, table_iter)?;
+        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
+        for level in &snapshot.levels {
+            let iter = match lower {
+                Bound::Included(key) => {
+                    SstConcatIterator::create_and_seek_to_key(level.clone(), key)?
+                }
+                Bound::Excluded(key) => {
+                    let mut iter = SstConcatIterator::create_and_seek_to_key(level.clone(), key)?;
+                    while iter.is_valid() && iter.key() == key {
+                        iter.next()?;
+                    }
+                    iter
+                }
+                Bound::Unbounded => SstConcatIterator::create_and_seek_to_first(level.clone())?,
+            };
+            level_iters.push(Box::new(iter));
+        }
+        let level_iter = MergeIterator::create(level_iters);
+
+        let iter = TwoMergeIterator::create(
+            TwoMergeIterator::create(memtable_iter, table_iter)?,
+            level_iter,
+        )?;
 
         Ok(FusedIterator::new(LsmIterator::new(
             iter,
             map_bound(upper),
+            read_seq,
+            Self::range_tombstones(snapshot, read_seq),
+            self.options.merge_operator.clone(),
+            snapshot.blob_files.clone(),
         )?))
     }
+
+    /// Create an iterator over a range of keys going backward, starting from the last key within
+    /// `upper`.
+    pub(crate) fn scan_rev(
+        &self,
+        snapshot: &LsmStorageInner,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_seq: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
+        for memtable in iter::once(&snapshot.memtable).chain(snapshot.imm_memtables.iter().rev()) {
+            let mut iter = memtable.scan(lower, upper);
+            iter.seek_to_last()?;
+            memtable_iters.push(Box::new(iter));
+        }
+        let memtable_iter = MergeIterator::create_rev(memtable_iters);
+
+        let mut table_iters = Vec::with_capacity(snapshot.l0_sstables.len());
+        for table in snapshot.l0_sstables.iter().rev() {
+            let iter = match upper {
+                Bound::Included(key) | Bound::Excluded(key) => Self::move_back_to_upper(
+                    SsTableIterator::create_and_seek_to_key(table.clone(), key)?,
+                    upper,
+                )?,
+                Bound::Unbounded => SsTableIterator::create_and_seek_to_last(table.clone())?,
+            };
+            table_iters.push(Box::new(iter));
+        }
+        let table_iter = MergeIterator::create_rev(table_iters);
+
+        let mut level_iters = Vec::with_capacity(snapshot.levels.len());
+        for level in &snapshot.levels {
+            let iter = match upper {
+                Bound::Included(key) | Bound::Excluded(key) => Self::move_back_to_upper(
+                    SstConcatIterator::create_and_seek_to_key(level.clone(), key)?,
+                    upper,
+                )?,
+                Bound::Unbounded => SstConcatIterator::create_and_seek_to_last(level.clone())?,
+            };
+            level_iters.push(Box::new(iter));
+        }
+        let level_iter = MergeIterator::create_rev(level_iters);
+
+        let iter = TwoMergeIterator::create_rev(
+            TwoMergeIterator::create_rev(memtable_iter, table_iter)?,
+            level_iter,
+        )?;
+
+        Ok(FusedIterator::new(LsmIterator::new_rev(
+            iter,
+            map_bound(lower),
+            read_seq,
+            Self::range_tombstones(snapshot, read_seq),
+            self.options.merge_operator.clone(),
+            snapshot.blob_files.clone(),
+        )?))
+    }
+
+    /// Move an iterator seeked to the key of `upper` back to the last entry within `upper`.
+    fn move_back_to_upper<I: BidirectionalIterator>(mut iter: I, upper: Bound<&[u8]>) -> Result<I> {
+        if let Bound::Included(key) = upper {
+            while iter.is_valid() && iter.key() == key {
+                iter.next()?;
+            }
+        }
+        if iter.is_valid() {
+            iter.prev()?;
+        } else {
+            // All keys in the iterator are within `upper`.
+            iter.seek_to_last()?;
+        }
+        Ok(iter)
+    }
 }
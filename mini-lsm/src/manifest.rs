@@ -0,0 +1,173 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut};
+use parking_lot::Mutex;
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// A change to the structure of the LSM tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestRecord {
+    /// A memtable with the given id is created, along with its WAL.
+    NewMemtable(usize),
+    /// The memtable with the given id is flushed to an L0 SST with the same id.
+    Flush(usize),
+    /// SSTs in `removed` are deleted from the tree, and SSTs in `added` are placed in the tree as
+    /// `(level, sst_id)`, where level 0 is the L0 list. Moving an SST to another level without
+    /// rewriting it appears in both lists.
+    Compaction {
+        removed: Vec<usize>,
+        added: Vec<(usize, usize)>,
+    },
+    /// A blob file with the given id is written, before the SST pointing to it is added.
+    NewBlobFile(usize),
+    /// The blob file with the given id is deleted after garbage collection.
+    DeleteBlobFile(usize),
+}
+
+impl ManifestRecord {
+    const NEW_MEMTABLE: u8 = 0;
+    const FLUSH: u8 = 1;
+    const COMPACTION: u8 = 2;
+    const NEW_BLOB_FILE: u8 = 3;
+    const DELETE_BLOB_FILE: u8 = 4;
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            ManifestRecord::NewMemtable(id) => {
+                buf.put_u8(Self::NEW_MEMTABLE);
+                buf.put_u64(*id as u64);
+            }
+            ManifestRecord::Flush(id) => {
+                buf.put_u8(Self::FLUSH);
+                buf.put_u64(*id as u64);
+            }
+            ManifestRecord::Compaction { removed, added } => {
+                buf.put_u8(Self::COMPACTION);
+                buf.put_u32(removed.len() as u32);
+                for id in removed {
+                    buf.put_u64(*id as u64);
+                }
+                buf.put_u32(added.len() as u32);
+                for (level, id) in added {
+                    buf.put_u32(*level as u32);
+                    buf.put_u64(*id as u64);
+                }
+            }
+            ManifestRecord::NewBlobFile(id) => {
+                buf.put_u8(Self::NEW_BLOB_FILE);
+                buf.put_u64(*id as u64);
+            }
+            ManifestRecord::DeleteBlobFile(id) => {
+                buf.put_u8(Self::DELETE_BLOB_FILE);
+                buf.put_u64(*id as u64);
+            }
+        }
+    }
+
+    fn decode(mut buf: &[u8]) -> Result<Self> {
+        let record = match buf.get_u8() {
+            Self::NEW_MEMTABLE => ManifestRecord::NewMemtable(buf.get_u64() as usize),
+            Self::FLUSH => ManifestRecord::Flush(buf.get_u64() as usize),
+            Self::COMPACTION => {
+                let removed_len = buf.get_u32() as usize;
+                let removed = (0..removed_len).map(|_| buf.get_u64() as usize).collect();
+                let added_len = buf.get_u32() as usize;
+                let added = (0..added_len)
+                    .map(|_| (buf.get_u32() as usize, buf.get_u64() as usize))
+                    .collect();
+                ManifestRecord::Compaction { removed, added }
+            }
+            Self::NEW_BLOB_FILE => ManifestRecord::NewBlobFile(buf.get_u64() as usize),
+            Self::DELETE_BLOB_FILE => ManifestRecord::DeleteBlobFile(buf.get_u64() as usize),
+            tag => bail!("unknown manifest record type {}", tag),
+        };
+        if buf.has_remaining() {
+            bail!("manifest record has trailing bytes");
+        }
+        Ok(record)
+    }
+}
+
+/// An append-only log of [`ManifestRecord`]s. Replaying it from the beginning gives the set of
+/// SSTs in each level, the blob files, and the memtables that are not flushed yet.
+///
+/// Each record is stored as:
+///
+/// ```text
+/// | len (u32) | record (len bytes) | checksum (u32) |
+/// ```
+pub struct Manifest {
+    file: Arc<Mutex<File>>,
+}
+
+impl Manifest {
+    /// Create a new, empty manifest at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .create_new(true)
+            .write(true)
+            .open(path.as_ref())
+            .with_context(|| format!("failed to create manifest {}", path.as_ref().display()))?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Open the manifest at `path` for appending, and return all records in it.
+    ///
+    /// A torn record at the end of the file (left by a crash in the middle of a write) is ignored.
+    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to recover manifest {}", path.display()))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let mut rbuf = &buf[..];
+        let mut records = Vec::new();
+        while rbuf.remaining() >= SIZEOF_U32 {
+            let len = (&rbuf[..SIZEOF_U32]).get_u32() as usize;
+            if rbuf.remaining() < SIZEOF_U32 + len + SIZEOF_U32 {
+                break;
+            }
+            rbuf.advance(SIZEOF_U32);
+            let record = &rbuf[..len];
+            rbuf.advance(len);
+            if rbuf.get_u32() != crc32fast::hash(record) {
+                bail!("manifest checksum mismatched");
+            }
+            records.push(ManifestRecord::decode(record)?);
+        }
+        Ok((
+            Self {
+                file: Arc::new(Mutex::new(file)),
+            },
+            records,
+        ))
+    }
+
+    /// Append a record to the manifest and persist it to disk.
+    pub fn add_record(&self, record: &ManifestRecord) -> Result<()> {
+        let mut record_buf = Vec::new();
+        record.encode(&mut record_buf);
+        let mut buf = Vec::with_capacity(record_buf.len() + SIZEOF_U32 * 2);
+        buf.put_u32(record_buf.len() as u32);
+        buf.put_slice(&record_buf);
+        buf.put_u32(crc32fast::hash(&record_buf));
+        let mut file = self.file.lock();
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
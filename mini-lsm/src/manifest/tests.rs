@@ -0,0 +1,57 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use tempfile::tempdir;
+
+use super::{Manifest, ManifestRecord};
+
+#[test]
+fn test_manifest_recover() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    let records = vec![
+        ManifestRecord::NewMemtable(1),
+        ManifestRecord::Flush(1),
+        ManifestRecord::NewMemtable(2),
+        ManifestRecord::Compaction {
+            removed: vec![1],
+            added: vec![(1, 3), (1, 4)],
+        },
+        ManifestRecord::NewBlobFile(5),
+        ManifestRecord::DeleteBlobFile(5),
+    ];
+    {
+        let manifest = Manifest::create(&path).unwrap();
+        for record in &records[..2] {
+            manifest.add_record(record).unwrap();
+        }
+    }
+    {
+        let (manifest, recovered) = Manifest::recover(&path).unwrap();
+        assert_eq!(recovered, records[..2]);
+        for record in &records[2..] {
+            manifest.add_record(record).unwrap();
+        }
+    }
+    let (_, recovered) = Manifest::recover(&path).unwrap();
+    assert_eq!(recovered, records);
+}
+
+#[test]
+fn test_manifest_torn_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    {
+        let manifest = Manifest::create(&path).unwrap();
+        manifest.add_record(&ManifestRecord::Flush(1)).unwrap();
+    }
+    // Simulate a crash in the middle of writing a record.
+    OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(&[0, 0, 0, 9, 1, 0])
+        .unwrap();
+    let (_, recovered) = Manifest::recover(&path).unwrap();
+    assert_eq!(recovered, vec![ManifestRecord::Flush(1)]);
+}
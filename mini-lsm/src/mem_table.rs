@@ -1,4 +1,7 @@
+use std::cmp::{self, Reverse};
 use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -6,13 +9,48 @@ use bytes::Bytes;
 use crossbeam_skiplist::map::Entry;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
+use parking_lot::RwLock;
 
-use crate::iterators::StorageIterator;
-use crate::table::SsTableBuilder;
+use crate::iterators::{BidirectionalIterator, SeekableIterator, StorageIterator, ValueType};
+use crate::range_tombstone::{self, RangeTombstone};
+use crate::ttl;
+use crate::wal::Wal;
+use crate::write_batch::WriteBatchRecord;
+
+/// A key together with the sequence number of the write that produced it. Versions of the same
+/// key are ordered from the latest to the earliest, so a lookup finds the latest version first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InternalKey {
+    pub key: Bytes,
+    pub seq: u64,
+}
+
+impl InternalKey {
+    pub fn new(key: Bytes, seq: u64) -> Self {
+        Self { key, seq }
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (&self.key, Reverse(self.seq)).cmp(&(&other.key, Reverse(other.seq)))
+    }
+}
 
 /// A basic mem-table based on crossbeam-skiplist
 pub struct MemTable {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<InternalKey, (ValueType, Bytes)>>,
+    range_tombstones: RwLock<Vec<RangeTombstone>>,
+    wal: Option<Wal>,
+    id: usize,
+    /// The total size of the keys and values put into the mem-table, including overwritten ones.
+    approximate_size: AtomicUsize,
 }
 
 pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
@@ -23,32 +61,174 @@ pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
     }
 }
 
+/// Map a lower bound on keys to one on internal keys, which covers every version of the key.
+fn map_lower_bound(bound: Bound<&[u8]>) -> Bound<InternalKey> {
+    match bound {
+        Bound::Included(x) => {
+            Bound::Included(InternalKey::new(Bytes::copy_from_slice(x), u64::MAX))
+        }
+        Bound::Excluded(x) => Bound::Excluded(InternalKey::new(Bytes::copy_from_slice(x), 0)),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Map an upper bound on keys to one on internal keys, which covers every version of the key.
+fn map_upper_bound(bound: Bound<&[u8]>) -> Bound<InternalKey> {
+    match bound {
+        Bound::Included(x) => Bound::Included(InternalKey::new(Bytes::copy_from_slice(x), 0)),
+        Bound::Excluded(x) => {
+            Bound::Excluded(InternalKey::new(Bytes::copy_from_slice(x), u64::MAX))
+        }
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
 impl MemTable {
     /// Create a new mem-table.
-    pub fn create() -> Self {
+    pub fn create(id: usize) -> Self {
         Self {
             map: Arc::new(SkipMap::new()),
+            range_tombstones: RwLock::new(Vec::new()),
+            wal: None,
+            id,
+            approximate_size: AtomicUsize::new(0),
         }
     }
 
-    /// Get a value by key.
-    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.map.get(key).map(|e| e.value().clone())
+    /// Create a new mem-table that logs every write to a WAL at `path`.
+    pub fn create_with_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            map: Arc::new(SkipMap::new()),
+            range_tombstones: RwLock::new(Vec::new()),
+            wal: Some(Wal::create(path)?),
+            id,
+            approximate_size: AtomicUsize::new(0),
+        })
     }
 
-    /// Put a key-value pair into the mem-table.
-    pub fn put(&self, key: &[u8], value: &[u8]) {
-        self.map
-            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+    /// Rebuild a mem-table from the WAL at `path`.
+    pub fn recover_from_wal(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let memtable = Self::create(id);
+        let wal = Wal::recover(path, |seq, record| memtable.apply(seq, record))?;
+        Ok(Self {
+            wal: Some(wal),
+            ..memtable
+        })
+    }
+
+    /// Get the latest version of a key that is visible at `read_seq`, i.e. written with a
+    /// sequence number no larger than it, together with its sequence number and value type. Range
+    /// tombstones are not taken into account.
+    pub fn get(&self, key: &[u8], read_seq: u64) -> Option<(u64, ValueType, Bytes)> {
+        let lower = InternalKey::new(Bytes::copy_from_slice(key), read_seq);
+        let entry = self
+            .map
+            .range((Bound::Included(lower), Bound::Unbounded))
+            .next()?;
+        let (value_type, value) = entry.value().clone();
+        (entry.key().key == key).then_some((entry.key().seq, value_type, value))
+    }
+
+    /// Put a key-value pair written with sequence number `seq` into the mem-table. The write goes
+    /// to the WAL first, if there is one.
+    pub fn put(&self, key: &[u8], seq: u64, value: &[u8]) -> Result<()> {
+        let record =
+            WriteBatchRecord::Put(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        self.put_batch(seq, &[record])
+    }
+
+    /// Put a tombstone of a key written with sequence number `seq` into the mem-table.
+    pub fn delete(&self, key: &[u8], seq: u64) -> Result<()> {
+        self.put_batch(
+            seq,
+            &[WriteBatchRecord::Delete(Bytes::copy_from_slice(key))],
+        )
+    }
+
+    /// Put the writes of a batch written with sequence number `seq` into the mem-table. They are
+    /// logged to the WAL as a single record, so that they are recovered together.
+    pub fn put_batch(&self, seq: u64, records: &[WriteBatchRecord]) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.put_batch(seq, records)?;
+        }
+        for record in records {
+            self.apply(seq, record.clone());
+        }
+        Ok(())
     }
 
-    /// Get an iterator over a range of keys.
+    /// Persist the WAL of the mem-table to disk, if it has one.
+    pub fn sync_wal(&self) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.sync()?;
+        }
+        Ok(())
+    }
+
+    fn apply(&self, seq: u64, record: WriteBatchRecord) {
+        let size = match record {
+            WriteBatchRecord::Put(key, value) => {
+                let size = key.len() + value.len();
+                self.map
+                    .insert(InternalKey::new(key, seq), (ValueType::Put, value));
+                size
+            }
+            WriteBatchRecord::PutWithExpiry(key, value, expires_at) => {
+                let size = key.len() + value.len();
+                self.map.insert(
+                    InternalKey::new(key, seq),
+                    (ValueType::PutWithExpiry, ttl::encode(expires_at, &value)),
+                );
+                size
+            }
+            WriteBatchRecord::Delete(key) => {
+                let size = key.len();
+                self.map.insert(
+                    InternalKey::new(key, seq),
+                    (ValueType::Delete, Bytes::new()),
+                );
+                size
+            }
+            WriteBatchRecord::Merge(key, operand) => {
+                let size = key.len() + operand.len();
+                self.map
+                    .insert(InternalKey::new(key, seq), (ValueType::Merge, operand));
+                size
+            }
+            WriteBatchRecord::DeleteRange(lower, upper) => {
+                let size = lower.len() + upper.len();
+                self.range_tombstones
+                    .write()
+                    .push(RangeTombstone::new(lower, upper, seq));
+                size
+            }
+        };
+        self.approximate_size.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// The range tombstones in the mem-table.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.read().clone()
+    }
+
+    /// The sequence number of the latest range tombstone visible at `read_seq` that contains
+    /// `key`, or 0 if there is none.
+    pub fn latest_covering_seq(&self, key: &[u8], read_seq: u64) -> u64 {
+        range_tombstone::latest_covering_seq(self.range_tombstones.read().iter(), key, read_seq)
+    }
+
+    /// Get an iterator over every version of a range of keys.
     pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableIterator {
-        let (lower, upper) = (map_bound(lower), map_bound(upper));
+        let bounds = (map_lower_bound(lower), map_upper_bound(upper));
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
-            item: (Bytes::from_static(&[]), Bytes::from_static(&[])),
+            bounds: bounds.clone(),
+            iter_builder: |map| map.range(bounds),
+            item: (
+                InternalKey::new(Bytes::new(), 0),
+                (ValueType::Put, Bytes::new()),
+            ),
+            backward: false,
         }
         .build();
         let entry = iter.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
@@ -56,52 +236,144 @@ impl MemTable {
         iter
     }
 
-    /// Flush the mem-table to SSTable.
-    pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
-        for entry in self.map.iter() {
-            builder.add(&entry.key()[..], &entry.value()[..]);
-        }
-        Ok(())
+    /// The largest sequence number of the writes in the mem-table, or 0 if it is empty.
+    pub fn max_seq(&self) -> u64 {
+        let max_tombstone_seq = self.range_tombstones.read().iter().map(|t| t.seq).max();
+        self.map
+            .iter()
+            .map(|e| e.key().seq)
+            .chain(max_tombstone_seq)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The approximate size of the mem-table in bytes.
+    pub fn approximate_size(&self) -> usize {
+        self.approximate_size.load(Ordering::Relaxed)
+    }
+
+    /// The id of this mem-table, which is also the id of the SST it will be flushed to.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Check if the mem-table has no entries and no range tombstones.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty() && self.range_tombstones.read().is_empty()
     }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
+type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
+    'a,
+    InternalKey,
+    (Bound<InternalKey>, Bound<InternalKey>),
+    InternalKey,
+    (ValueType, Bytes),
+>;
 
 /// An iterator over a range of `SkipMap`.
 #[self_referencing]
 pub struct MemTableIterator {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<InternalKey, (ValueType, Bytes)>>,
+    bounds: (Bound<InternalKey>, Bound<InternalKey>),
     #[borrows(map)]
     #[not_covariant]
     iter: SkipMapRangeIter<'this>,
-    item: (Bytes, Bytes),
+    item: (InternalKey, (ValueType, Bytes)),
+    /// Whether `iter` goes backward from `item`. Otherwise it goes forward from it, and it is
+    /// restarted from `item` when the direction changes.
+    backward: bool,
 }
 
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, Bytes, Bytes>>) -> (Bytes, Bytes) {
+    fn entry_to_item(
+        entry: Option<Entry<'_, InternalKey, (ValueType, Bytes)>>,
+    ) -> (InternalKey, (ValueType, Bytes)) {
         entry
             .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (Bytes::from_static(&[]), Bytes::from_static(&[])))
+            .unwrap_or_else(|| {
+                (
+                    InternalKey::new(Bytes::new(), 0),
+                    (ValueType::Put, Bytes::new()),
+                )
+            })
     }
 }
 
 impl StorageIterator for MemTableIterator {
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        &self.borrow_item().1 .1[..]
     }
 
     fn key(&self) -> &[u8] {
-        &self.borrow_item().0[..]
+        &self.borrow_item().0.key[..]
+    }
+
+    fn seq(&self) -> u64 {
+        self.borrow_item().0.seq
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.borrow_item().1 .0
     }
 
     fn is_valid(&self) -> bool {
-        !self.borrow_item().0.is_empty()
+        !self.borrow_item().0.key.is_empty()
     }
 
     fn next(&mut self) -> Result<()> {
-        let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
-        self.with_mut(|x| *x.item = entry);
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.with_mut(|x| {
+            if *x.backward {
+                let lower = Bound::Excluded(x.item.0.clone());
+                *x.iter = x.map.range((lower, x.bounds.1.clone()));
+                *x.backward = false;
+            }
+            *x.item = MemTableIterator::entry_to_item(x.iter.next());
+        });
+        Ok(())
+    }
+}
+
+impl BidirectionalIterator for MemTableIterator {
+    fn prev(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Ok(());
+        }
+        self.with_mut(|x| {
+            if !*x.backward {
+                let upper = Bound::Excluded(x.item.0.clone());
+                *x.iter = x.map.range((x.bounds.0.clone(), upper));
+                *x.backward = true;
+            }
+            *x.item = MemTableIterator::entry_to_item(x.iter.next_back());
+        });
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.with_mut(|x| {
+            *x.iter = x.map.range(x.bounds.clone());
+            *x.backward = true;
+            *x.item = MemTableIterator::entry_to_item(x.iter.next_back());
+        });
+        Ok(())
+    }
+}
+
+impl SeekableIterator for MemTableIterator {
+    /// Seek to the latest version of the first key which >= `key` and is within the upper bound of
+    /// the scan. Like [`SsTableIterator::seek_to_key`](crate::table::SsTableIterator::seek_to_key),
+    /// it may move before the lower bound.
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        self.with_mut(|x| {
+            let lower = Bound::Included(InternalKey::new(Bytes::copy_from_slice(key), u64::MAX));
+            *x.iter = x.map.range((lower, x.bounds.1.clone()));
+            *x.backward = false;
+            *x.item = MemTableIterator::entry_to_item(x.iter.next());
+        });
         Ok(())
     }
 }
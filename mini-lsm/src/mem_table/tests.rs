@@ -1,64 +1,72 @@
-use tempfile::tempdir;
+use bytes::Bytes;
 
 use super::MemTable;
-use crate::iterators::StorageIterator;
-use crate::table::{SsTableBuilder, SsTableIterator};
+use crate::iterators::{BidirectionalIterator, SeekableIterator, StorageIterator, ValueType};
+use crate::write_batch::WriteBatchRecord;
 
 #[test]
 fn test_memtable_get() {
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
-    assert_eq!(&memtable.get(b"key1").unwrap()[..], b"value1");
-    assert_eq!(&memtable.get(b"key2").unwrap()[..], b"value2");
-    assert_eq!(&memtable.get(b"key3").unwrap()[..], b"value3");
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
+    assert_eq!(&memtable.get(b"key1", u64::MAX).unwrap().2[..], b"value1");
+    assert_eq!(&memtable.get(b"key2", u64::MAX).unwrap().2[..], b"value2");
+    assert_eq!(&memtable.get(b"key3", u64::MAX).unwrap().2[..], b"value3");
 }
 
 #[test]
 fn test_memtable_overwrite() {
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
-    memtable.put(b"key1", b"value11");
-    memtable.put(b"key2", b"value22");
-    memtable.put(b"key3", b"value33");
-    assert_eq!(&memtable.get(b"key1").unwrap()[..], b"value11");
-    assert_eq!(&memtable.get(b"key2").unwrap()[..], b"value22");
-    assert_eq!(&memtable.get(b"key3").unwrap()[..], b"value33");
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
+    memtable.put(b"key1", 4, b"value11").unwrap();
+    memtable.put(b"key2", 5, b"value22").unwrap();
+    memtable.put(b"key3", 6, b"value33").unwrap();
+    assert_eq!(&memtable.get(b"key1", u64::MAX).unwrap().2[..], b"value11");
+    assert_eq!(&memtable.get(b"key2", u64::MAX).unwrap().2[..], b"value22");
+    assert_eq!(&memtable.get(b"key3", u64::MAX).unwrap().2[..], b"value33");
+    // The earlier versions are still there.
+    assert_eq!(&memtable.get(b"key1", 3).unwrap().2[..], b"value1");
+    assert_eq!(&memtable.get(b"key2", 4).unwrap().2[..], b"value2");
+    assert!(memtable.get(b"key3", 2).is_none());
+    assert_eq!(memtable.max_seq(), 6);
+    assert_eq!(memtable.get(b"key1", u64::MAX).unwrap().0, 4);
 }
 
 #[test]
-fn test_memtable_flush() {
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
-    let mut builder = SsTableBuilder::new(128);
-    memtable.flush(&mut builder).unwrap();
-    let dir = tempdir().unwrap();
-    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
-    let mut iter = SsTableIterator::create_and_seek_to_first(sst.into()).unwrap();
-    assert_eq!(iter.key(), b"key1");
-    assert_eq!(iter.value(), b"value1");
-    iter.next().unwrap();
-    assert_eq!(iter.key(), b"key2");
-    assert_eq!(iter.value(), b"value2");
-    iter.next().unwrap();
-    assert_eq!(iter.key(), b"key3");
-    assert_eq!(iter.value(), b"value3");
-    iter.next().unwrap();
-    assert!(!iter.is_valid());
+fn test_memtable_range_tombstones() {
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable
+        .put_batch(
+            2,
+            &[WriteBatchRecord::DeleteRange(
+                Bytes::from("key1"),
+                Bytes::from("key3"),
+            )],
+        )
+        .unwrap();
+    assert!(!memtable.is_empty());
+    assert_eq!(memtable.max_seq(), 2);
+    assert_eq!(memtable.approximate_size(), 18);
+    // The end of the range is excluded, and the tombstone is invisible to earlier reads.
+    assert_eq!(memtable.latest_covering_seq(b"key1", u64::MAX), 2);
+    assert_eq!(memtable.latest_covering_seq(b"key2", u64::MAX), 2);
+    assert_eq!(memtable.latest_covering_seq(b"key3", u64::MAX), 0);
+    assert_eq!(memtable.latest_covering_seq(b"key1", 1), 0);
+    // The tombstone does not hide the key from the mem-table itself.
+    assert_eq!(memtable.get(b"key1", u64::MAX).unwrap().0, 1);
 }
 
 #[test]
 fn test_memtable_iter() {
     use std::ops::Bound;
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
 
     {
         let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
@@ -93,3 +101,128 @@ fn test_memtable_iter() {
         assert!(!iter.is_valid());
     }
 }
+
+#[test]
+fn test_memtable_iter_prev() {
+    use std::ops::Bound;
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key1", 3, b"value11").unwrap();
+    memtable.put(b"key3", 4, b"value3").unwrap();
+
+    // Going backward, versions of a key come from the earliest to the latest.
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    iter.seek_to_last().unwrap();
+    for (key, seq) in [(b"key3", 4), (b"key2", 2), (b"key1", 1), (b"key1", 3)] {
+        assert_eq!((iter.key(), iter.seq()), (&key[..], seq));
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+
+    // The bounds of the scan are kept, and the iterator can switch directions.
+    let mut iter = memtable.scan(Bound::Excluded(b"key1"), Bound::Excluded(b"key3"));
+    iter.seek_to_last().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 2));
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key1"[..], 1));
+    iter.next().unwrap();
+    iter.prev().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key1"[..], 1));
+    iter.prev().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key1"[..], 3));
+    iter.next().unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"key2");
+}
+
+#[test]
+fn test_memtable_iter_seek() {
+    use std::ops::Bound;
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key2", 3, b"value22").unwrap();
+    memtable.put(b"key4", 4, b"value4").unwrap();
+
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Excluded(b"key4"));
+    // Seek to the latest version of a key.
+    iter.seek_to_key(b"key2").unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 3));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 2));
+    // The upper bound is kept.
+    iter.seek_to_key(b"key3").unwrap();
+    assert!(!iter.is_valid());
+    // Seek backward, even from an invalid position, and after going backward.
+    iter.seek_to_key(b"key0").unwrap();
+    assert_eq!(iter.key(), b"key1");
+    iter.seek_to_last().unwrap();
+    iter.seek_to_key(b"key1").unwrap();
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 3));
+}
+
+#[test]
+fn test_memtable_approximate_size() {
+    let memtable = MemTable::create(0);
+    assert_eq!(memtable.approximate_size(), 0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    assert_eq!(memtable.approximate_size(), 10);
+    memtable
+        .put_batch(
+            2,
+            &[
+                WriteBatchRecord::Put(Bytes::from("key2"), Bytes::from("value2")),
+                WriteBatchRecord::Delete(Bytes::from("key1")),
+            ],
+        )
+        .unwrap();
+    assert_eq!(memtable.approximate_size(), 24);
+}
+
+#[test]
+fn test_memtable_iter_versions() {
+    use std::ops::Bound;
+    let memtable = MemTable::create(0);
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key1", 3, b"value11").unwrap();
+    memtable.delete(b"key2", 4).unwrap();
+
+    // Versions of a key come from the latest to the earliest.
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    for (key, seq, value_type, value) in [
+        (b"key1", 3, ValueType::Put, &b"value11"[..]),
+        (b"key1", 1, ValueType::Put, b"value1"),
+        (b"key2", 4, ValueType::Delete, b""),
+        (b"key2", 2, ValueType::Put, b"value2"),
+    ] {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.seq(), seq);
+        assert_eq!(iter.value_type(), value_type);
+        assert_eq!(iter.value(), value);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // Bounds cover every version of a key.
+    let mut iter = memtable.scan(Bound::Excluded(b"key1"), Bound::Included(b"key2"));
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 4));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 2));
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    let mut iter = memtable.scan(Bound::Included(b"key1"), Bound::Excluded(b"key2"));
+    assert_eq!((iter.key(), iter.seq()), (&b"key1"[..], 3));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key1"[..], 1));
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
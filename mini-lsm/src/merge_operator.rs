@@ -0,0 +1,179 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::iterators::ValueType;
+use crate::ttl;
+use crate::write_batch::WriteBatchRecord;
+
+/// Merges the operands written by [`LsmStorage::merge`](crate::lsm_storage::LsmStorage::merge)
+/// into the value of a key, for updates like incrementing a counter or appending to a list, which
+/// would otherwise read the value before writing it back.
+///
+/// Operands are merged lazily: reads merge them onto the value before them, and flush and
+/// compaction fold consecutive operands together before the value is known. So the result must not
+/// depend on how the operands are grouped: merging the result of `merge(key, Some(a), b)` into a
+/// value, or into `None`, must give the same as merging `a` and then `b` into it.
+pub trait MergeOperator: Send + Sync {
+    /// Merge `operand` into `existing`, the value of `key` before it, which is `None` if the key
+    /// has no value. `existing` is an earlier operand when operands are folded together.
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Bytes;
+}
+
+impl fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MergeOperator")
+    }
+}
+
+fn merge_operator<'a>(
+    operator: Option<&'a dyn MergeOperator>,
+    key: &[u8],
+) -> Result<&'a dyn MergeOperator> {
+    match operator {
+        Some(operator) => Ok(operator),
+        None => bail!(
+            "key {:?} has merge operands, but no merge operator is set",
+            Bytes::copy_from_slice(key)
+        ),
+    }
+}
+
+/// Collapse the versions of `key`, from the latest to the earliest, into the one version a read
+/// after all of them sees. A merge operand is merged onto the versions before it, down to the
+/// first value or tombstone, or to the first version deleted by a range tombstone, as reported by
+/// `is_range_deleted`. Returns `None` if the latest version itself is range-deleted.
+///
+/// A blob pointer is only read, with `read_blob`, when operands are merged onto it. As the latest
+/// version, it is returned as it is, and so is a value with an expiry time. Operands merged onto a
+/// value with an expiry time expire with it, so the key is deleted once the value has expired,
+/// whether or not compaction merged them before that.
+///
+/// Unless `complete` is set, there may be earlier versions of the key that are not in `versions`.
+/// Merge operands that reach the earliest version are then folded into a single operand, to be
+/// merged onto the earlier versions later.
+pub(crate) fn collapse_versions(
+    operator: Option<&dyn MergeOperator>,
+    key: &[u8],
+    versions: &[(u64, ValueType, Bytes)],
+    is_range_deleted: impl Fn(u64) -> bool,
+    read_blob: impl Fn(&[u8]) -> Result<Bytes>,
+    complete: bool,
+) -> Result<Option<(u64, ValueType, Bytes)>> {
+    let Some((latest_seq, value_type, value)) = versions.first() else {
+        return Ok(None);
+    };
+    if is_range_deleted(*latest_seq) {
+        return Ok(None);
+    }
+    if *value_type != ValueType::Merge {
+        return Ok(Some((*latest_seq, *value_type, value.clone())));
+    }
+
+    let operator = merge_operator(operator, key)?;
+    // The operands from the latest to the earliest, and whether the value they are merged onto is
+    // known.
+    let mut operands = Vec::new();
+    let mut existing = None;
+    let mut expires_at = None;
+    let mut is_complete = complete;
+    for (seq, value_type, value) in versions {
+        if is_range_deleted(*seq) {
+            is_complete = true;
+            break;
+        }
+        match value_type {
+            ValueType::Merge => operands.push(value),
+            ValueType::Put => {
+                existing = Some(value.clone());
+                is_complete = true;
+                break;
+            }
+            ValueType::Blob => {
+                existing = Some(read_blob(value)?);
+                is_complete = true;
+                break;
+            }
+            ValueType::PutWithExpiry => {
+                let (value_expires_at, value) = ttl::decode(value);
+                if ttl::is_expired(value_expires_at) {
+                    return Ok(Some((*latest_seq, ValueType::Delete, Bytes::new())));
+                }
+                existing = Some(Bytes::copy_from_slice(value));
+                expires_at = Some(value_expires_at);
+                is_complete = true;
+                break;
+            }
+            ValueType::Delete => {
+                is_complete = true;
+                break;
+            }
+        }
+    }
+    let mut operands = operands.into_iter().rev();
+    let (value_type, mut merged) = if is_complete {
+        let operand = operands.next().unwrap();
+        (
+            ValueType::Put,
+            operator.merge(key, existing.as_deref(), operand),
+        )
+    } else {
+        (ValueType::Merge, operands.next().unwrap().clone())
+    };
+    for operand in operands {
+        merged = operator.merge(key, Some(&merged), operand);
+    }
+    if let Some(expires_at) = expires_at {
+        return Ok(Some((
+            *latest_seq,
+            ValueType::PutWithExpiry,
+            ttl::encode(expires_at, &merged),
+        )));
+    }
+    Ok(Some((*latest_seq, value_type, merged)))
+}
+
+/// The writes of a batch share a sequence number, so a merge cannot go to the memtable next to an
+/// earlier write to the same key in the batch. It is merged into that write here instead.
+pub(crate) fn merge_batch_records(
+    operator: Option<&dyn MergeOperator>,
+    records: &[WriteBatchRecord],
+) -> Result<Vec<WriteBatchRecord>> {
+    let mut merged: Vec<WriteBatchRecord> = Vec::with_capacity(records.len());
+    for record in records {
+        let WriteBatchRecord::Merge(key, operand) = record else {
+            merged.push(record.clone());
+            continue;
+        };
+        let operator = merge_operator(operator, key)?;
+        let earlier = merged.iter().rposition(|record| match record {
+            WriteBatchRecord::Put(k, _)
+            | WriteBatchRecord::PutWithExpiry(k, ..)
+            | WriteBatchRecord::Delete(k)
+            | WriteBatchRecord::Merge(k, _) => k == key,
+            WriteBatchRecord::DeleteRange(..) => false,
+        });
+        let record = match earlier.map(|idx| merged.remove(idx)) {
+            Some(WriteBatchRecord::Put(_, value)) => {
+                WriteBatchRecord::Put(key.clone(), operator.merge(key, Some(&value), operand))
+            }
+            Some(WriteBatchRecord::PutWithExpiry(_, value, expires_at)) => {
+                let value = operator.merge(key, Some(&value), operand);
+                WriteBatchRecord::PutWithExpiry(key.clone(), value, expires_at)
+            }
+            Some(WriteBatchRecord::Delete(_)) => {
+                WriteBatchRecord::Put(key.clone(), operator.merge(key, None, operand))
+            }
+            Some(WriteBatchRecord::Merge(_, earlier)) => {
+                WriteBatchRecord::Merge(key.clone(), operator.merge(key, Some(&earlier), operand))
+            }
+            _ => record.clone(),
+        };
+        merged.push(record);
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests;
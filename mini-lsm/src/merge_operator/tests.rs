@@ -0,0 +1,138 @@
+use bytes::Bytes;
+
+use super::{collapse_versions, merge_batch_records, MergeOperator};
+use crate::iterators::ValueType;
+use crate::write_batch::WriteBatchRecord;
+
+/// Appends the operands to the value, separated by commas.
+struct AppendOperator;
+
+impl MergeOperator for AppendOperator {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Bytes {
+        match existing {
+            Some(existing) if !existing.is_empty() => {
+                Bytes::from([existing, b",", operand].concat())
+            }
+            _ => Bytes::copy_from_slice(operand),
+        }
+    }
+}
+
+fn put(seq: u64, value: &'static str) -> (u64, ValueType, Bytes) {
+    (seq, ValueType::Put, Bytes::from(value))
+}
+
+fn delete(seq: u64) -> (u64, ValueType, Bytes) {
+    (seq, ValueType::Delete, Bytes::new())
+}
+
+fn blob(seq: u64, pointer: &'static str) -> (u64, ValueType, Bytes) {
+    (seq, ValueType::Blob, Bytes::from(pointer))
+}
+
+fn merge(seq: u64, operand: &'static str) -> (u64, ValueType, Bytes) {
+    (seq, ValueType::Merge, Bytes::from(operand))
+}
+
+fn collapse(
+    versions: &[(u64, ValueType, Bytes)],
+    range_deleted_below: u64,
+    complete: bool,
+) -> Option<(u64, ValueType, Bytes)> {
+    collapse_versions(
+        Some(&AppendOperator),
+        b"key",
+        versions,
+        |seq| seq < range_deleted_below,
+        |pointer| Ok(Bytes::from([b"blob:", pointer].concat())),
+        complete,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_collapse_values() {
+    assert_eq!(collapse(&[], 0, true), None);
+    assert_eq!(
+        collapse(&[put(3, "c"), put(2, "b")], 0, true),
+        Some(put(3, "c"))
+    );
+    assert_eq!(collapse(&[put(3, "c")], 4, true), None);
+}
+
+#[test]
+fn test_collapse_merge_operands() {
+    let versions = [merge(5, "e"), merge(4, "d"), put(3, "c"), merge(2, "b")];
+    assert_eq!(collapse(&versions, 0, true), Some(put(5, "c,d,e")));
+    assert_eq!(collapse(&versions, 0, false), Some(put(5, "c,d,e")));
+    // The operands are merged onto nothing past a deletion or a range tombstone.
+    assert_eq!(
+        collapse(&[merge(5, "e"), delete(4), put(3, "c")], 0, true),
+        Some(put(5, "e"))
+    );
+    assert_eq!(collapse(&versions, 4, false), Some(put(5, "d,e")));
+    assert_eq!(collapse(&versions, 6, true), None);
+}
+
+#[test]
+fn test_collapse_blob() {
+    // A blob pointer is only read when operands are merged onto it.
+    assert_eq!(
+        collapse(&[blob(3, "c"), put(2, "b")], 0, true),
+        Some(blob(3, "c"))
+    );
+    assert_eq!(
+        collapse(&[merge(4, "d"), blob(3, "c")], 0, false),
+        Some(put(4, "blob:c,d"))
+    );
+}
+
+#[test]
+fn test_collapse_incomplete() {
+    let versions = [merge(5, "e"), merge(4, "d")];
+    assert_eq!(collapse(&versions, 0, true), Some(put(5, "d,e")));
+    // Without the earlier versions, the operands are folded into one.
+    assert_eq!(collapse(&versions, 0, false), Some(merge(5, "d,e")));
+}
+
+fn read_blob(_: &[u8]) -> anyhow::Result<Bytes> {
+    unreachable!("no blob pointer to read")
+}
+
+#[test]
+fn test_collapse_without_operator() {
+    let versions = [merge(2, "b"), put(1, "a")];
+    assert!(collapse_versions(None, b"key", &versions, |_| false, read_blob, true).is_err());
+    // Values do not need an operator.
+    let versions = [put(2, "b"), merge(1, "a")];
+    assert_eq!(
+        collapse_versions(None, b"key", &versions, |_| false, read_blob, true).unwrap(),
+        Some(put(2, "b"))
+    );
+}
+
+#[test]
+fn test_merge_batch_records() {
+    let key = |key: &'static str| Bytes::from(key);
+    let records = [
+        WriteBatchRecord::Put(key("key1"), key("a")),
+        WriteBatchRecord::Merge(key("key2"), key("x")),
+        WriteBatchRecord::Merge(key("key1"), key("b")),
+        WriteBatchRecord::Delete(key("key3")),
+        WriteBatchRecord::Merge(key("key3"), key("c")),
+        WriteBatchRecord::Merge(key("key2"), key("y")),
+        WriteBatchRecord::DeleteRange(key("key4"), key("key5")),
+        WriteBatchRecord::Merge(key("key4"), key("d")),
+    ];
+    assert_eq!(
+        merge_batch_records(Some(&AppendOperator), &records).unwrap(),
+        vec![
+            WriteBatchRecord::Put(key("key1"), key("a,b")),
+            WriteBatchRecord::Put(key("key3"), key("c")),
+            WriteBatchRecord::Merge(key("key2"), key("x,y")),
+            WriteBatchRecord::DeleteRange(key("key4"), key("key5")),
+            WriteBatchRecord::Merge(key("key4"), key("d")),
+        ]
+    );
+    assert!(merge_batch_records(None, &records).is_err());
+}
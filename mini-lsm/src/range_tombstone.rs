@@ -0,0 +1,102 @@
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::block::SIZEOF_U64;
+use crate::varint::{get_varint, put_varint, varint_len};
+
+/// A deletion of every key in `[start, end)`, written by
+/// [`LsmStorage::delete_range`](crate::lsm_storage::LsmStorage::delete_range). Only the versions
+/// written before the tombstone are deleted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub seq: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Bytes, end: Bytes, seq: u64) -> Self {
+        Self { start, end, seq }
+    }
+
+    /// Check if `key` is in the deleted range.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        &self.start[..] <= key && key < &self.end[..]
+    }
+
+    /// Check if the tombstone deletes the version of `key` written with sequence number `seq`.
+    pub fn covers(&self, key: &[u8], seq: u64) -> bool {
+        seq < self.seq && self.contains(key)
+    }
+
+    /// Check if the deleted range overlaps with `[first_key, last_key]`.
+    pub fn overlaps(&self, first_key: &[u8], last_key: &[u8]) -> bool {
+        &self.start[..] <= last_key && first_key < &self.end[..]
+    }
+
+    /// The part of the deleted range within `[lower, upper)`, where `None` is unbounded, if the
+    /// two overlap.
+    pub fn clip(&self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Option<RangeTombstone> {
+        let start = match lower {
+            Some(lower) if lower > &self.start[..] => Bytes::copy_from_slice(lower),
+            _ => self.start.clone(),
+        };
+        let end = match upper {
+            Some(upper) if upper < &self.end[..] => Bytes::copy_from_slice(upper),
+            _ => self.end.clone(),
+        };
+        (start < end).then(|| RangeTombstone::new(start, end, self.seq))
+    }
+
+    /// Encode range tombstones to a buffer, each as
+    /// `| start_len (varint) | start | end_len (varint) | end | seq (u64) |`.
+    pub fn encode_range_tombstones(tombstones: &[RangeTombstone], buf: &mut Vec<u8>) {
+        buf.reserve(
+            tombstones
+                .iter()
+                .map(|t| {
+                    varint_len(t.start.len() as u64)
+                        + t.start.len()
+                        + varint_len(t.end.len() as u64)
+                        + t.end.len()
+                        + SIZEOF_U64
+                })
+                .sum(),
+        );
+        for tombstone in tombstones {
+            put_varint(buf, tombstone.start.len() as u64);
+            buf.put_slice(&tombstone.start);
+            put_varint(buf, tombstone.end.len() as u64);
+            buf.put_slice(&tombstone.end);
+            buf.put_u64(tombstone.seq);
+        }
+    }
+
+    /// Decode range tombstones from a buffer.
+    pub fn decode_range_tombstones(mut buf: impl Buf) -> Vec<RangeTombstone> {
+        let mut tombstones = Vec::new();
+        while buf.has_remaining() {
+            let start_len = get_varint(&mut buf) as usize;
+            let start = buf.copy_to_bytes(start_len);
+            let end_len = get_varint(&mut buf) as usize;
+            let end = buf.copy_to_bytes(end_len);
+            let seq = buf.get_u64();
+            tombstones.push(RangeTombstone { start, end, seq });
+        }
+        tombstones
+    }
+}
+
+/// The sequence number of the latest tombstone in `tombstones` that is visible at `read_seq` and
+/// contains `key`, or 0 if there is none. A version of `key` older than it is deleted.
+pub fn latest_covering_seq<'a>(
+    tombstones: impl IntoIterator<Item = &'a RangeTombstone>,
+    key: &[u8],
+    read_seq: u64,
+) -> u64 {
+    tombstones
+        .into_iter()
+        .filter(|t| t.seq <= read_seq && t.contains(key))
+        .map(|t| t.seq)
+        .max()
+        .unwrap_or(0)
+}
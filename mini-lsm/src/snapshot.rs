@@ -0,0 +1,64 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::LsmStorageCore;
+
+/// A read-only view of the storage as of the moment it was taken, created by
+/// [`LsmStorage::snapshot`](crate::lsm_storage::LsmStorage::snapshot).
+///
+/// Reads through a snapshot see the writes done before it was taken and none after. Compaction
+/// keeps the versions of keys a live snapshot may read, so snapshots should not be held for longer
+/// than needed.
+pub struct Snapshot {
+    core: Arc<LsmStorageCore>,
+    seq: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(core: Arc<LsmStorageCore>) -> Self {
+        let seq = core.acquire_snapshot();
+        Self { core, seq }
+    }
+
+    /// The sequence number of the latest write visible to the snapshot.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Get a key as of the snapshot.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let (snapshot, _) = self.core.read_view();
+        self.core.get(&snapshot, key, self.seq)
+    }
+
+    /// Create an iterator over a range of keys as of the snapshot.
+    pub fn scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let (snapshot, _) = self.core.read_view();
+        self.core.scan(&snapshot, lower, upper, self.seq)
+    }
+
+    /// Create an iterator over a range of keys as of the snapshot, going from the largest key to
+    /// the smallest.
+    pub fn scan_rev(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let (snapshot, _) = self.core.read_view();
+        self.core.scan_rev(&snapshot, lower, upper, self.seq)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.core.release_snapshot(self.seq);
+    }
+}
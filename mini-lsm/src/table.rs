@@ -1,17 +1,83 @@
+pub(crate) mod bloom;
 mod builder;
+mod compression;
 mod iterator;
 
+use std::fmt;
 use std::fs::File;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::{anyhow, Result};
+pub use bloom::Bloom;
 pub use builder::SsTableBuilder;
 use bytes::{Buf, BufMut, Bytes};
+pub use compression::{BlockCompressor, CompressionType, SnappyCompressor};
 pub use iterator::SsTableIterator;
 
-use crate::block::Block;
+use crate::blob;
+use crate::block::{Block, BlockIterator};
+use crate::iterators::ValueType;
 use crate::lsm_storage::BlockCache;
+use crate::range_tombstone::{self, RangeTombstone};
+use crate::varint::{get_varint, put_varint, varint_len};
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// The part of an SST that failed its checksum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CorruptedSection {
+    /// The data block with the given index.
+    Block(usize),
+    BlockMeta,
+    BloomFilter,
+    RangeTombstones,
+    BlobRefs,
+    Footer,
+}
+
+/// An SST file does not match its checksums. Read errors of SSTs can be downcast to this type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CorruptionError {
+    pub sst_id: usize,
+    pub section: CorruptedSection,
+}
+
+impl fmt::Display for CorruptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.section {
+            CorruptedSection::Block(block_idx) => {
+                write!(f, "SST {} is corrupted at block {}", self.sst_id, block_idx)
+            }
+            CorruptedSection::BlockMeta => {
+                write!(f, "SST {} has corrupted block meta", self.sst_id)
+            }
+            CorruptedSection::BloomFilter => {
+                write!(f, "SST {} has a corrupted bloom filter", self.sst_id)
+            }
+            CorruptedSection::RangeTombstones => {
+                write!(f, "SST {} has corrupted range tombstones", self.sst_id)
+            }
+            CorruptedSection::BlobRefs => {
+                write!(f, "SST {} has corrupted blob references", self.sst_id)
+            }
+            CorruptedSection::Footer => write!(f, "SST {} has a corrupted footer", self.sst_id),
+        }
+    }
+}
+
+impl std::error::Error for CorruptionError {}
+
+/// Split the checksum off the end of `data`, and check it.
+fn verify_checksum(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < SIZEOF_U32 {
+        return None;
+    }
+    let (data, mut checksum) = data.split_at(data.len() - SIZEOF_U32);
+    (checksum.get_u32() == crc32fast::hash(data)).then_some(data)
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
@@ -19,23 +85,30 @@ pub struct BlockMeta {
     pub offset: usize,
     /// The first key of the data block.
     pub first_key: Bytes,
+    /// The last key of the data block.
+    pub last_key: Bytes,
 }
 
 impl BlockMeta {
-    /// Encode block meta to a buffer.
+    /// Encode block meta to a buffer, each as
+    /// `| offset (u64) | first_key_len (varint) | first_key | last_key_len (varint) | last_key |`.
     pub fn encode_block_meta(block_meta: &[BlockMeta], buf: &mut Vec<u8>) {
         let mut estimated_size = 0;
         for meta in block_meta {
-            estimated_size += std::mem::size_of::<u32>();
-            estimated_size += std::mem::size_of::<u16>();
+            estimated_size += SIZEOF_U64;
+            estimated_size += varint_len(meta.first_key.len() as u64);
             estimated_size += meta.first_key.len();
+            estimated_size += varint_len(meta.last_key.len() as u64);
+            estimated_size += meta.last_key.len();
         }
         buf.reserve(estimated_size);
         let original_len = buf.len();
         for meta in block_meta {
-            buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.len() as u16);
+            buf.put_u64(meta.offset as u64);
+            put_varint(buf, meta.first_key.len() as u64);
             buf.put_slice(&meta.first_key);
+            put_varint(buf, meta.last_key.len() as u64);
+            buf.put_slice(&meta.last_key);
         }
         assert_eq!(estimated_size, buf.len() - original_len);
     }
@@ -44,10 +117,16 @@ impl BlockMeta {
     pub fn decode_block_meta(mut buf: impl Buf) -> Vec<BlockMeta> {
         let mut block_meta = Vec::new();
         while buf.has_remaining() {
-            let offset = buf.get_u32() as usize;
-            let first_key_len = buf.get_u16() as usize;
+            let offset = buf.get_u64() as usize;
+            let first_key_len = get_varint(&mut buf) as usize;
             let first_key = buf.copy_to_bytes(first_key_len);
-            block_meta.push(BlockMeta { offset, first_key });
+            let last_key_len = get_varint(&mut buf) as usize;
+            let last_key = buf.copy_to_bytes(last_key_len);
+            block_meta.push(BlockMeta {
+                offset,
+                first_key,
+                last_key,
+            });
         }
         block_meta
     }
@@ -91,50 +170,162 @@ impl FileObject {
         self.1
     }
 
+    /// The time the file was last written. Files are never modified after they are created.
+    pub fn modified(&self) -> Result<SystemTime> {
+        Ok(self.0.metadata()?.modified()?)
+    }
+
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
         std::fs::write(path, &data)?;
+        File::open(path)?.sync_all()?;
         Ok(FileObject(
             File::options().read(true).write(false).open(path)?,
             data.len() as u64,
         ))
     }
 
-    pub fn open(_path: &Path) -> Result<Self> {
-        unimplemented!()
+    /// Open an existing file object from the disk.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::options().read(true).write(false).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(FileObject(file, size))
     }
 }
 
+/// An SSTable, with the following layout:
+///
+/// ```text
+/// | data blocks | block metas | checksum | bloom filter | checksum | range tombstones | checksum | blob refs | checksum | footer | checksum |
+/// ```
+///
+/// Each data block is followed by the ID of its compressor (u8, 0 if not compressed) and checksum, and the footer is
+/// the meta offset (u64), the bloom filter offset (u64), the range tombstone offset (u64), the blob refs offset (u64)
+/// and the largest sequence number in the SST (u64). All checksums are the crc32 (u32) of the section before them. The
+/// blob refs are the number of bytes of each blob file the SST points to.
+///
+/// An SST may hold only range tombstones and no data blocks. Its key range covers the range tombstones, so that they
+/// are compacted together with the data they delete.
 pub struct SsTable {
     file: FileObject,
     block_metas: Vec<BlockMeta>,
     block_meta_offset: usize,
     id: usize,
     block_cache: Option<Arc<BlockCache>>,
+    /// The custom codecs the blocks may be compressed with.
+    compressors: Vec<Arc<dyn BlockCompressor>>,
+    first_key: Bytes,
+    last_key: Bytes,
+    bloom: Bloom,
+    range_tombstones: Vec<RangeTombstone>,
+    blob_refs: Vec<(usize, u64)>,
+    max_seq: u64,
+    created_at: SystemTime,
 }
 
+/// The smallest and the largest key of an SST with the given blocks and range tombstones. The end
+/// of a range tombstone counts as a key of the SST, although the tombstone does not cover it.
+fn key_range(block_metas: &[BlockMeta], range_tombstones: &[RangeTombstone]) -> (Bytes, Bytes) {
+    let first_key = block_metas
+        .first()
+        .map(|x| &x.first_key)
+        .into_iter()
+        .chain(range_tombstones.iter().map(|x| &x.start))
+        .min()
+        .expect("SST is empty");
+    let last_key = block_metas
+        .last()
+        .map(|x| &x.last_key)
+        .into_iter()
+        .chain(range_tombstones.iter().map(|x| &x.end))
+        .max()
+        .unwrap();
+    (first_key.clone(), last_key.clone())
+}
+
+/// A version of a key in an SST: its sequence number, value type and value.
+type KeyVersion = (u64, ValueType, Bytes);
+
 impl SsTable {
     #[cfg(test)]
     pub(crate) fn open_for_test(file: FileObject) -> Result<Self> {
-        Self::open(0, None, file)
+        Self::open(0, None, Vec::new(), file)
     }
 
-    /// Open SSTable from a file.
-    pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
+    /// Open SSTable from a file. Its blocks may be compressed with the built-in codecs or with
+    /// `compressors`.
+    pub fn open(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        compressors: Vec<Arc<dyn BlockCompressor>>,
+        file: FileObject,
+    ) -> Result<Self> {
+        let corruption = |section| CorruptionError {
+            sst_id: id,
+            section,
+        };
         let len = file.size();
-        let raw_meta_offset = file.read(len - 4, 4)?;
-        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, len - 4 - block_meta_offset)?;
+        const FOOTER_SIZE: u64 = (SIZEOF_U64 * 5 + SIZEOF_U32) as u64;
+        if len < FOOTER_SIZE {
+            return Err(corruption(CorruptedSection::Footer).into());
+        }
+        let footer_offset = len - FOOTER_SIZE;
+        let raw_footer = file.read(footer_offset, FOOTER_SIZE)?;
+        let mut footer =
+            verify_checksum(&raw_footer).ok_or(corruption(CorruptedSection::Footer))?;
+        let block_meta_offset = footer.get_u64();
+        let bloom_offset = footer.get_u64();
+        let range_tombstone_offset = footer.get_u64();
+        let blob_refs_offset = footer.get_u64();
+        let max_seq = footer.get_u64();
+        if block_meta_offset > bloom_offset
+            || bloom_offset > range_tombstone_offset
+            || range_tombstone_offset > blob_refs_offset
+            || blob_refs_offset > footer_offset
+        {
+            return Err(corruption(CorruptedSection::Footer).into());
+        }
+
+        let raw_blob_refs = file.read(blob_refs_offset, footer_offset - blob_refs_offset)?;
+        let raw_blob_refs =
+            verify_checksum(&raw_blob_refs).ok_or(corruption(CorruptedSection::BlobRefs))?;
+        let blob_refs = blob::decode_blob_refs(raw_blob_refs);
+        let raw_range_tombstones = file.read(
+            range_tombstone_offset,
+            blob_refs_offset - range_tombstone_offset,
+        )?;
+        let raw_range_tombstones = verify_checksum(&raw_range_tombstones)
+            .ok_or(corruption(CorruptedSection::RangeTombstones))?;
+        let range_tombstones = RangeTombstone::decode_range_tombstones(raw_range_tombstones);
+        let raw_bloom = file.read(bloom_offset, range_tombstone_offset - bloom_offset)?;
+        let bloom = verify_checksum(&raw_bloom).ok_or(corruption(CorruptedSection::BloomFilter))?;
+        let bloom = Bloom::decode(bloom)?;
+        let raw_meta = file.read(block_meta_offset, bloom_offset - block_meta_offset)?;
+        let raw_meta = verify_checksum(&raw_meta).ok_or(corruption(CorruptedSection::BlockMeta))?;
+        let block_metas = BlockMeta::decode_block_meta(raw_meta);
+        if block_metas.is_empty() && range_tombstones.is_empty() {
+            return Err(corruption(CorruptedSection::BlockMeta).into());
+        }
+        let (first_key, last_key) = key_range(&block_metas, &range_tombstones);
+        let created_at = file.modified()?;
         Ok(Self {
             file,
-            block_metas: BlockMeta::decode_block_meta(&raw_meta[..]),
+            first_key,
+            last_key,
+            block_metas,
             block_meta_offset: block_meta_offset as usize,
             id,
             block_cache,
+            compressors,
+            bloom,
+            range_tombstones,
+            blob_refs,
+            max_seq,
+            created_at,
         })
     }
 
-    /// Read a block from the disk.
+    /// Read a block from the disk, check its checksum and decompress it.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
         let offset = self.block_metas[block_idx].offset;
         let offset_end = self
@@ -144,32 +335,161 @@ impl SsTable {
         let block_data = self
             .file
             .read(offset as u64, (offset_end - offset) as u64)?;
-        Ok(Arc::new(Block::decode(&block_data[..])))
+        let corruption = CorruptionError {
+            sst_id: self.id,
+            section: CorruptedSection::Block(block_idx),
+        };
+        let block_data = verify_checksum(&block_data).ok_or(corruption)?;
+        let (compression_id, block_data) = block_data.split_last().ok_or(corruption)?;
+        let block = match compression::find_compressor(*compression_id, &self.compressors)? {
+            Some(compressor) => Block::decode(&compressor.decompress(block_data)?),
+            None => Block::decode(block_data),
+        };
+        Ok(Arc::new(block.ok_or(corruption)?))
     }
 
-    /// Read a block from disk, with block cache.
+    /// Read a block from disk, with block cache. The cache keeps decompressed blocks.
     pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
         if let Some(ref block_cache) = self.block_cache {
             let blk = block_cache
                 .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
+                .map_err(|e| match e.downcast_ref::<CorruptionError>() {
+                    Some(corruption) => anyhow::Error::from(*corruption),
+                    None => anyhow!("{}", e),
+                })?;
             Ok(blk)
         } else {
             self.read_block(block_idx)
         }
     }
 
-    /// Find the block that may contain `key`.
+    /// Find the first block that may contain `key`, which is the first one ending at or after it.
+    /// The versions of a key may span several blocks.
     pub fn find_block_idx(&self, key: &[u8]) -> usize {
         self.block_metas
-            .partition_point(|meta| meta.first_key <= key)
-            .saturating_sub(1)
+            .partition_point(|meta| meta.last_key < key)
+            .min(self.block_metas.len().saturating_sub(1))
+    }
+
+    /// Look up the latest version of `key` visible at `read_seq` in the SST, together with its
+    /// sequence number and value type. Only an exact match is returned, which may be a tombstone.
+    /// Range tombstones are not taken into account.
+    pub fn get(&self, key: &[u8], read_seq: u64) -> Result<Option<KeyVersion>> {
+        Ok(self.lookup(key, read_seq)?.0)
+    }
+
+    /// Like [`SsTable::get`], but also returns whether the SST has any version of `key`, so that a
+    /// miss can tell a key missing from the SST from one with only versions newer than `read_seq`.
+    pub fn lookup(&self, key: &[u8], read_seq: u64) -> Result<(Option<KeyVersion>, bool)> {
+        if !self.overlaps(key, key) || self.block_metas.is_empty() {
+            return Ok((None, false));
+        }
+        let mut block_idx = self.find_block_idx(key);
+        let mut iter =
+            BlockIterator::create_and_seek_to_key(self.read_block_cached(block_idx)?, key);
+        let mut has_key = false;
+        loop {
+            if !iter.is_valid() {
+                // The versions of the key may go on in the next block.
+                block_idx += 1;
+                if block_idx >= self.num_of_blocks() || self.block_metas[block_idx].first_key != key
+                {
+                    return Ok((None, has_key));
+                }
+                iter = BlockIterator::create_and_seek_to_first(self.read_block_cached(block_idx)?);
+                continue;
+            }
+            if iter.key() != key {
+                return Ok((None, has_key));
+            }
+            if iter.seq() <= read_seq {
+                return Ok((
+                    Some((
+                        iter.seq(),
+                        iter.value_type(),
+                        Bytes::copy_from_slice(iter.value()),
+                    )),
+                    true,
+                ));
+            }
+            has_key = true;
+            iter.next();
+        }
+    }
+
+    /// Read every block of the SST from the disk, bypassing the block cache, and check their
+    /// checksums. The other sections are checked when the SST is opened.
+    pub fn verify_checksums(&self) -> Result<()> {
+        for block_idx in 0..self.num_of_blocks() {
+            self.read_block(block_idx)?;
+        }
+        Ok(())
     }
 
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
         self.block_metas.len()
     }
+
+    /// The smallest key in the SST.
+    pub fn first_key(&self) -> &Bytes {
+        &self.first_key
+    }
+
+    /// The largest key in the SST.
+    pub fn last_key(&self) -> &Bytes {
+        &self.last_key
+    }
+
+    /// Check if the key range of the SST overlaps with `[first_key, last_key]`.
+    pub fn overlaps(&self, first_key: &[u8], last_key: &[u8]) -> bool {
+        self.first_key() <= last_key && first_key <= self.last_key()
+    }
+
+    /// The size of the SST file in bytes.
+    pub fn table_size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Check the bloom filter for `key`. Returns `false` only if the SST does not contain the key.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bloom.may_contain(bloom::hash(key))
+    }
+
+    /// Whether the SST has a bloom filter that can rule out keys.
+    pub fn has_bloom_filter(&self) -> bool {
+        !self.bloom.matches_all()
+    }
+
+    /// The range tombstones in the SST.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// The sequence number of the latest range tombstone in the SST visible at `read_seq` that
+    /// contains `key`, or 0 if there is none.
+    pub fn latest_covering_seq(&self, key: &[u8], read_seq: u64) -> u64 {
+        range_tombstone::latest_covering_seq(&self.range_tombstones, key, read_seq)
+    }
+
+    /// The number of bytes of each blob file the SST points to, as `(blob_file_id, bytes)`.
+    pub fn blob_refs(&self) -> &[(usize, u64)] {
+        &self.blob_refs
+    }
+
+    /// The largest sequence number in the SST.
+    pub fn max_seq(&self) -> u64 {
+        self.max_seq
+    }
+
+    /// The time the SST file was written.
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    pub fn sst_id(&self) -> usize {
+        self.id
+    }
 }
 
 #[cfg(test)]
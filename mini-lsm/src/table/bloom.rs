@@ -0,0 +1,108 @@
+use anyhow::{bail, Result};
+use bytes::{BufMut, Bytes};
+
+/// A bloom filter over the keys of an SST, in the same layout as the one used by LevelDB.
+///
+/// The encoded filter is the bit array followed by the number of probes `k` (u8).
+pub struct Bloom {
+    /// The bit array of the filter.
+    pub(crate) filter: Bytes,
+    /// The number of bits set for each key.
+    pub(crate) k: u8,
+}
+
+/// Hash a key for the bloom filter. This is the hash function of LevelDB.
+pub fn hash(key: &[u8]) -> u32 {
+    const SEED: u32 = 0xbc9f1d34;
+    const M: u32 = 0xc6a4a793;
+    let mut h = SEED ^ (key.len() as u32).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(4);
+    for chunk in &mut chunks {
+        let w = u32::from_le_bytes(chunk.try_into().unwrap());
+        h = h.wrapping_add(w).wrapping_mul(M);
+        h ^= h >> 16;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, b) in rest.iter().enumerate() {
+            h = h.wrapping_add((*b as u32) << (8 * i));
+        }
+        h = h.wrapping_mul(M);
+        h ^= h >> 24;
+    }
+    h
+}
+
+impl Bloom {
+    /// Decode a bloom filter from a buffer.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        let Some((&k, filter)) = buf.split_last() else {
+            bail!("bloom filter is empty");
+        };
+        Ok(Self {
+            filter: Bytes::copy_from_slice(filter),
+            k,
+        })
+    }
+
+    /// Encode the bloom filter to a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.filter);
+        buf.put_u8(self.k);
+    }
+
+    /// Build a bloom filter from the key hashes with `bits_per_key` bits for each key. A filter
+    /// with 0 bits per key is empty and matches every key.
+    pub fn build_from_key_hashes(keys: &[u32], bits_per_key: usize) -> Self {
+        if bits_per_key == 0 || keys.is_empty() {
+            return Self {
+                filter: Bytes::new(),
+                k: 0,
+            };
+        }
+        // k = ln(2) * bits_per_key minimizes the false positive rate.
+        let k = ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30);
+        // A tiny filter has a high false positive rate, so use at least 64 bits.
+        let nbits = (keys.len() * bits_per_key).max(64);
+        let nbytes = (nbits + 7) / 8;
+        let nbits = nbytes * 8;
+        let mut filter = vec![0u8; nbytes];
+        for h in keys {
+            // Double hashing, which is as good as k independent hash functions.
+            let mut h = *h;
+            let delta = h.rotate_left(15);
+            for _ in 0..k {
+                let bit_pos = h as usize % nbits;
+                filter[bit_pos / 8] |= 1 << (bit_pos % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+        Self {
+            filter: filter.into(),
+            k: k as u8,
+        }
+    }
+
+    /// Whether the filter matches every key: it is empty, or of an encoding we do not understand.
+    pub fn matches_all(&self) -> bool {
+        self.filter.is_empty() || self.k > 30
+    }
+
+    /// Check if a key with hash `h` may be in the filter. False positives are possible, but
+    /// false negatives are not.
+    pub fn may_contain(&self, mut h: u32) -> bool {
+        if self.matches_all() {
+            return true;
+        }
+        let nbits = self.filter.len() * 8;
+        let delta = h.rotate_left(15);
+        for _ in 0..self.k {
+            let bit_pos = h as usize % nbits;
+            if self.filter[bit_pos / 8] & (1 << (bit_pos % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+}
@@ -1,20 +1,38 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::Result;
 use bytes::BufMut;
 
-use super::{BlockMeta, FileObject, SsTable};
+use super::bloom::{self, Bloom};
+use super::compression::CompressionType;
+use super::{key_range, BlockMeta, FileObject, SsTable};
+use crate::blob::{self, BlobFileBuilder, BlobPointer};
 use crate::block::BlockBuilder;
+use crate::iterators::ValueType;
 use crate::lsm_storage::BlockCache;
+use crate::range_tombstone::RangeTombstone;
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
     builder: BlockBuilder,
     first_key: Vec<u8>,
+    last_key: Vec<u8>,
     data: Vec<u8>,
     pub(super) meta: Vec<BlockMeta>,
     block_size: usize,
+    /// Hashes of all keys added, for the bloom filter.
+    key_hashes: Vec<u32>,
+    range_tombstones: Vec<RangeTombstone>,
+    /// The largest sequence number added.
+    max_seq: u64,
+    bloom_bits_per_key: usize,
+    compression: CompressionType,
+    /// The blob file large values go to, with the smallest size of a value that goes to it.
+    blob_file: Option<(BlobFileBuilder, usize)>,
+    /// The number of bytes of each blob file the SST points to.
+    blob_refs: BTreeMap<usize, u64>,
 }
 
 impl SsTableBuilder {
@@ -24,26 +42,99 @@ impl SsTableBuilder {
             data: Vec::new(),
             meta: Vec::new(),
             first_key: Vec::new(),
+            last_key: Vec::new(),
             block_size,
             builder: BlockBuilder::new(block_size),
+            key_hashes: Vec::new(),
+            range_tombstones: Vec::new(),
+            max_seq: 0,
+            bloom_bits_per_key: 10,
+            compression: CompressionType::None,
+            blob_file: None,
+            blob_refs: BTreeMap::new(),
         }
     }
 
-    /// Adds a key-value pair to SSTable
-    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+    /// Set the number of bloom filter bits for each key. The default is 10, which gives a false
+    /// positive rate of about 1%. 0 disables the filter.
+    pub fn with_bloom_bits_per_key(mut self, bloom_bits_per_key: usize) -> Self {
+        self.bloom_bits_per_key = bloom_bits_per_key;
+        self
+    }
+
+    /// Set the compression of the data blocks. Blocks are not compressed by default.
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Write the values of at least `min_blob_size` bytes to `blob_file`, and only keep pointers to
+    /// them in the SST. The blob file must be taken out with [`SsTableBuilder::take_blob_file`] and
+    /// written before the SST.
+    pub fn with_blob_file(mut self, blob_file: BlobFileBuilder, min_blob_size: usize) -> Self {
+        self.blob_file = Some((blob_file, min_blob_size));
+        self
+    }
+
+    /// Take out the blob file set by [`SsTableBuilder::with_blob_file`]. No more values go to a
+    /// blob file afterwards.
+    pub fn take_blob_file(&mut self) -> Option<BlobFileBuilder> {
+        self.blob_file.take().map(|(blob_file, _)| blob_file)
+    }
+
+    /// Adds a key-value pair written with sequence number `seq` to SSTable. The versions of a key
+    /// must be added from the latest to the earliest.
+    pub fn add(&mut self, key: &[u8], seq: u64, value: &[u8]) {
+        self.add_with_type(key, seq, ValueType::Put, value)
+    }
+
+    /// Like [`SsTableBuilder::add`], for an entry written by any kind of write.
+    pub fn add_with_type(&mut self, key: &[u8], seq: u64, value_type: ValueType, value: &[u8]) {
+        let pointer;
+        let (value_type, value) = match &mut self.blob_file {
+            Some((blob_file, min_blob_size))
+                if value_type == ValueType::Put && value.len() >= *min_blob_size =>
+            {
+                pointer = blob_file.add(key, value).encode();
+                (ValueType::Blob, &pointer[..])
+            }
+            _ => (value_type, value),
+        };
+        if value_type == ValueType::Blob {
+            let pointer = BlobPointer::decode(value).expect("invalid blob pointer");
+            *self.blob_refs.entry(pointer.file_id).or_default() += pointer.len;
+        }
         if self.first_key.is_empty() {
             self.first_key = key.to_vec();
         }
+        // `last_key` is the key added before this one.
+        if self.last_key != key {
+            self.key_hashes.push(bloom::hash(key));
+        }
+        self.max_seq = self.max_seq.max(seq);
 
-        if self.builder.add(key, value) {
+        if self.builder.add_with_type(key, seq, value_type, value) {
+            self.last_key = key.to_vec();
             return;
         }
         // create a new block builder and append block data
         self.finish_block();
 
         // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
+        assert!(self.builder.add_with_type(key, seq, value_type, value));
         self.first_key = key.to_vec();
+        self.last_key = key.to_vec();
+    }
+
+    /// Adds a range tombstone to the SSTable.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.max_seq = self.max_seq.max(tombstone.seq);
+        self.range_tombstones.push(tombstone);
+    }
+
+    /// Check if nothing has been added to the SSTable.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty() && self.range_tombstones.is_empty()
     }
 
     /// Get the estimated size of the SSTable.
@@ -57,8 +148,23 @@ impl SsTableBuilder {
         self.meta.push(BlockMeta {
             offset: self.data.len(),
             first_key: std::mem::take(&mut self.first_key).into(),
+            last_key: std::mem::take(&mut self.last_key).into(),
         });
-        self.data.extend(encoded_block);
+        // Keep the block as it is if compression does not make it smaller.
+        let (block_data, compression_id) = match self.compression.compressor() {
+            Some(compressor) => match compressor.compress(&encoded_block) {
+                Ok(compressed) if compressed.len() < encoded_block.len() => {
+                    (compressed, compressor.id())
+                }
+                _ => (encoded_block.to_vec(), CompressionType::None.id()),
+            },
+            None => (encoded_block.to_vec(), CompressionType::None.id()),
+        };
+        let block_offset = self.data.len();
+        self.data.extend(block_data);
+        self.data.put_u8(compression_id);
+        let checksum = crc32fast::hash(&self.data[block_offset..]);
+        self.data.put_u32(checksum);
     }
 
     /// Builds the SSTable and writes it to the given path. No need to actually write to disk until
@@ -69,18 +175,52 @@ impl SsTableBuilder {
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        self.finish_block();
+        if !self.builder.is_empty() {
+            self.finish_block();
+        }
         let mut buf = self.data;
         let meta_offset = buf.len();
         BlockMeta::encode_block_meta(&self.meta, &mut buf);
-        buf.put_u32(meta_offset as u32);
+        buf.put_u32(crc32fast::hash(&buf[meta_offset..]));
+        let bloom = Bloom::build_from_key_hashes(&self.key_hashes, self.bloom_bits_per_key);
+        let bloom_offset = buf.len();
+        bloom.encode(&mut buf);
+        buf.put_u32(crc32fast::hash(&buf[bloom_offset..]));
+        let range_tombstone_offset = buf.len();
+        RangeTombstone::encode_range_tombstones(&self.range_tombstones, &mut buf);
+        buf.put_u32(crc32fast::hash(&buf[range_tombstone_offset..]));
+        let blob_refs_offset = buf.len();
+        let blob_refs = self.blob_refs.into_iter().collect::<Vec<_>>();
+        blob::encode_blob_refs(&blob_refs, &mut buf);
+        buf.put_u32(crc32fast::hash(&buf[blob_refs_offset..]));
+        let footer_offset = buf.len();
+        buf.put_u64(meta_offset as u64);
+        buf.put_u64(bloom_offset as u64);
+        buf.put_u64(range_tombstone_offset as u64);
+        buf.put_u64(blob_refs_offset as u64);
+        buf.put_u64(self.max_seq);
+        buf.put_u32(crc32fast::hash(&buf[footer_offset..]));
+        let (first_key, last_key) = key_range(&self.meta, &self.range_tombstones);
         let file = FileObject::create(path.as_ref(), buf)?;
+        let compressors = match self.compression {
+            CompressionType::Custom(compressor) => vec![compressor],
+            _ => Vec::new(),
+        };
+        let created_at = file.modified()?;
         Ok(SsTable {
             id,
             file,
+            first_key,
+            last_key,
             block_metas: self.meta,
             block_meta_offset: meta_offset,
             block_cache,
+            compressors,
+            bloom,
+            range_tombstones: self.range_tombstones,
+            blob_refs,
+            max_seq: self.max_seq,
+            created_at,
         })
     }
 
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+/// Compresses and decompresses the data blocks of SSTs.
+pub trait BlockCompressor: Send + Sync {
+    /// The ID stored after each block compressed by this codec, to find the codec to decompress
+    /// it with. 0 marks an uncompressed block, and 1 is taken by [`SnappyCompressor`].
+    fn id(&self) -> u8;
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// The Snappy codec, which is fast and gives a moderate compression ratio.
+pub struct SnappyCompressor;
+
+impl BlockCompressor for SnappyCompressor {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(snap::raw::Encoder::new().compress_vec(data)?)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(snap::raw::Decoder::new().decompress_vec(data)?)
+    }
+}
+
+/// The compression of the data blocks of an SST.
+#[derive(Clone)]
+pub enum CompressionType {
+    None,
+    Snappy,
+    /// A codec other than the built-in ones. Its ID must not be 0 or 1.
+    Custom(Arc<dyn BlockCompressor>),
+}
+
+impl fmt::Debug for CompressionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionType::None => f.write_str("None"),
+            CompressionType::Snappy => f.write_str("Snappy"),
+            CompressionType::Custom(compressor) => write!(f, "Custom({})", compressor.id()),
+        }
+    }
+}
+
+impl CompressionType {
+    /// The ID stored after the blocks of this compression type.
+    pub fn id(&self) -> u8 {
+        self.compressor().map_or(0, |compressor| compressor.id())
+    }
+
+    /// The codec of this compression type, or `None` if blocks are stored as they are.
+    pub fn compressor(&self) -> Option<&dyn BlockCompressor> {
+        match self {
+            CompressionType::None => None,
+            CompressionType::Snappy => Some(&SnappyCompressor),
+            CompressionType::Custom(compressor) => Some(compressor.as_ref()),
+        }
+    }
+}
+
+/// The codec to decompress a block stored with compression ID `id`, out of the built-in codecs
+/// and `custom`, or `None` if the block is not compressed.
+pub(crate) fn find_compressor(
+    id: u8,
+    custom: &[Arc<dyn BlockCompressor>],
+) -> Result<Option<&dyn BlockCompressor>> {
+    Ok(match id {
+        0 => None,
+        1 => Some(&SnappyCompressor),
+        _ => match custom.iter().find(|compressor| compressor.id() == id) {
+            Some(compressor) => Some(compressor.as_ref()),
+            None => bail!("unknown compression type {}", id),
+        },
+    })
+}
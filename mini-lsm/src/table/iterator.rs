@@ -3,8 +3,8 @@ use std::sync::Arc;
 use anyhow::Result;
 
 use super::SsTable;
-use crate::block::BlockIterator;
-use crate::iterators::StorageIterator;
+use crate::block::{Block, BlockIterator};
+use crate::iterators::{BidirectionalIterator, SeekableIterator, StorageIterator, ValueType};
 
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
@@ -14,7 +14,15 @@ pub struct SsTableIterator {
 }
 
 impl SsTableIterator {
+    /// An iterator that is never valid, for an SST with only range tombstones.
+    fn empty_block_iter() -> BlockIterator {
+        BlockIterator::create_and_seek_to_first(Arc::new(Block::empty()))
+    }
+
     fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
         Ok((
             0,
             BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
@@ -40,7 +48,32 @@ impl SsTableIterator {
         Ok(())
     }
 
+    fn seek_to_last_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
+        let blk_idx = table.num_of_blocks() - 1;
+        Ok((
+            blk_idx,
+            BlockIterator::create_and_seek_to_last(table.read_block_cached(blk_idx)?),
+        ))
+    }
+
+    /// Create a new iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&table)?;
+        let iter = Self {
+            blk_iter,
+            table,
+            blk_idx,
+        };
+        Ok(iter)
+    }
+
     fn seek_to_key_inner(table: &Arc<SsTable>, key: &[u8]) -> Result<(usize, BlockIterator)> {
+        if table.num_of_blocks() == 0 {
+            return Ok((0, Self::empty_block_iter()));
+        }
         let mut blk_idx = table.find_block_idx(key);
         let mut blk_iter =
             BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
@@ -74,7 +107,17 @@ impl SsTableIterator {
     }
 }
 
+impl SeekableIterator for SsTableIterator {
+    fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
+        SsTableIterator::seek_to_key(self, key)
+    }
+}
+
 impl StorageIterator for SsTableIterator {
+    fn value_type(&self) -> ValueType {
+        self.blk_iter.value_type()
+    }
+
     fn value(&self) -> &[u8] {
         self.blk_iter.value()
     }
@@ -83,11 +126,18 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.key()
     }
 
+    fn seq(&self) -> u64 {
+        self.blk_iter.seq()
+    }
+
     fn is_valid(&self) -> bool {
         self.blk_iter.is_valid()
     }
 
     fn next(&mut self) -> Result<()> {
+        if !self.blk_iter.is_valid() {
+            return Ok(());
+        }
         self.blk_iter.next();
         if !self.blk_iter.is_valid() {
             self.blk_idx += 1;
@@ -100,3 +150,25 @@ impl StorageIterator for SsTableIterator {
         Ok(())
     }
 }
+
+impl BidirectionalIterator for SsTableIterator {
+    fn prev(&mut self) -> Result<()> {
+        if !self.blk_iter.is_valid() {
+            return Ok(());
+        }
+        self.blk_iter.prev();
+        if !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.blk_idx -= 1;
+            self.blk_iter =
+                BlockIterator::create_and_seek_to_last(self.table.read_block_cached(self.blk_idx)?);
+        }
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        let (blk_idx, blk_iter) = Self::seek_to_last_inner(&self.table)?;
+        self.blk_idx = blk_idx;
+        self.blk_iter = blk_iter;
+        Ok(())
+    }
+}
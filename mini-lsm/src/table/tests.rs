@@ -1,16 +1,18 @@
 use std::sync::Arc;
 
+use anyhow::Result;
 use bytes::Bytes;
 use tempfile::{tempdir, TempDir};
 
 use super::*;
-use crate::iterators::StorageIterator;
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::lsm_storage::BlockCache;
 use crate::table::SsTableBuilder;
 
 #[test]
 fn test_sst_build_single_key() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(b"233", b"233333");
+    builder.add(b"233", 0, b"233333");
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
 }
@@ -18,12 +20,12 @@ fn test_sst_build_single_key() {
 #[test]
 fn test_sst_build_two_blocks() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(b"11", b"11");
-    builder.add(b"22", b"22");
-    builder.add(b"33", b"11");
-    builder.add(b"44", b"22");
-    builder.add(b"55", b"11");
-    builder.add(b"66", b"22");
+    builder.add(b"11", 0, b"11");
+    builder.add(b"22", 0, b"22");
+    builder.add(b"33", 0, b"11");
+    builder.add(b"44", 0, b"22");
+    builder.add(b"55", 0, b"11");
+    builder.add(b"66", 0, b"22");
     assert!(builder.meta.len() >= 2);
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
@@ -46,7 +48,7 @@ fn generate_sst() -> (TempDir, SsTable) {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        builder.add(&key[..], &value[..]);
+        builder.add(&key[..], idx as u64, &value[..]);
     }
     let dir = tempdir().unwrap();
     let path = dir.path().join("1.sst");
@@ -66,6 +68,22 @@ fn test_sst_decode() {
     assert_eq!(new_sst.block_metas, meta);
 }
 
+#[test]
+fn test_sst_reopen() {
+    let (dir, sst) = generate_sst();
+    let file = FileObject::open(&dir.path().join("1.sst")).unwrap();
+    assert_eq!(file.size(), sst.file.size());
+    let new_sst = SsTable::open_for_test(file).unwrap();
+    assert_eq!(new_sst.block_metas, sst.block_metas);
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(new_sst)).unwrap();
+    for i in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
 fn as_bytes(x: &[u8]) -> Bytes {
     Bytes::copy_from_slice(x)
 }
@@ -128,3 +146,477 @@ fn test_sst_seek_key() {
         iter.seek_to_key(b"k").unwrap();
     }
 }
+
+#[test]
+fn test_sst_iterator_prev() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    assert!(sst.num_of_blocks() > 1);
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst).unwrap();
+    for _ in 0..2 {
+        for i in (0..num_of_keys()).rev() {
+            assert_eq!(
+                iter.key(),
+                key_of(i),
+                "expected key: {:?}, actual key: {:?}",
+                as_bytes(&key_of(i)),
+                as_bytes(iter.key())
+            );
+            assert_eq!(iter.value(), value_of(i));
+            iter.prev().unwrap();
+        }
+        assert!(!iter.is_valid());
+        iter.seek_to_last().unwrap();
+    }
+
+    // Switch directions across the blocks.
+    for i in 1..num_of_keys() - 1 {
+        iter.seek_to_key(&key_of(i)).unwrap();
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), key_of(i - 1));
+        iter.next().unwrap();
+        iter.next().unwrap();
+        assert_eq!(iter.key(), key_of(i + 1));
+    }
+}
+
+#[test]
+fn test_sst_bloom_filter() {
+    let (dir, sst) = generate_sst();
+    for i in 0..num_of_keys() {
+        assert!(sst.may_contain(&key_of(i)));
+    }
+    let false_positives = (0..1000)
+        .filter(|i| sst.may_contain(format!("missing_{}", i).as_bytes()))
+        .count();
+    assert!(false_positives < 50, "{} false positives", false_positives);
+
+    // The filter is read back from the file.
+    let file = FileObject::open(&dir.path().join("1.sst")).unwrap();
+    let new_sst = SsTable::open_for_test(file).unwrap();
+    for i in 0..num_of_keys() {
+        assert!(new_sst.may_contain(&key_of(i)));
+    }
+}
+
+#[test]
+fn test_sst_bloom_filter_disabled() {
+    let mut builder = SsTableBuilder::new(128).with_bloom_bits_per_key(0);
+    builder.add(b"11", 0, b"11");
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert!(sst.may_contain(b"11"));
+    assert!(sst.may_contain(b"22"));
+}
+
+#[test]
+fn test_sst_get() {
+    let (_dir, sst) = generate_sst();
+    for i in 0..num_of_keys() {
+        assert_eq!(
+            sst.get(&key_of(i), u64::MAX).unwrap().unwrap(),
+            (i as u64, ValueType::Put, Bytes::from(value_of(i)))
+        );
+        // Keys between two keys in the SST.
+        let key = format!("key_{:03}", i * 5 + 1).into_bytes();
+        assert!(sst.get(&key, u64::MAX).unwrap().is_none());
+    }
+    assert!(sst.get(b"k", u64::MAX).unwrap().is_none());
+    assert!(sst.get(b"z", u64::MAX).unwrap().is_none());
+    assert_eq!(sst.max_seq(), num_of_keys() as u64 - 1);
+}
+
+#[test]
+fn test_sst_versions() {
+    // Each block only fits a few entries, so the versions of a key span several blocks.
+    let mut builder = SsTableBuilder::new(64);
+    for idx in 0..10 {
+        for seq in (1..=10).rev() {
+            builder.add(
+                &key_of(idx),
+                idx as u64 * 100 + seq,
+                &value_of(seq as usize),
+            );
+        }
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert!(sst.num_of_blocks() > 20);
+    assert_eq!(sst.max_seq(), 910);
+    for idx in 0..10 {
+        let base = idx as u64 * 100;
+        assert!(sst.get(&key_of(idx), base).unwrap().is_none());
+        for seq in 1..=10 {
+            assert_eq!(
+                sst.get(&key_of(idx), base + seq).unwrap().unwrap().2,
+                value_of(seq as usize)
+            );
+        }
+        assert_eq!(
+            sst.get(&key_of(idx), u64::MAX).unwrap().unwrap().2,
+            value_of(10)
+        );
+    }
+
+    // Seeking to a key finds its latest version.
+    let sst = Arc::new(sst);
+    for idx in 0..10 {
+        let iter = SsTableIterator::create_and_seek_to_key(sst.clone(), &key_of(idx)).unwrap();
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.seq(), idx as u64 * 100 + 10);
+    }
+}
+
+/// Flip a byte of the SST file at `offset`, and reopen it.
+fn corrupt_sst(dir: &TempDir, offset: usize) -> Result<SsTable> {
+    let path = dir.path().join("1.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    data[offset] ^= 1;
+    std::fs::write(&path, data).unwrap();
+    SsTable::open_for_test(FileObject::open(&path).unwrap())
+}
+
+fn corruption_of(err: anyhow::Error) -> CorruptionError {
+    *err.downcast_ref::<CorruptionError>().unwrap()
+}
+
+#[test]
+fn test_sst_checksum() {
+    let (_dir, sst) = generate_sst();
+    sst.verify_checksums().unwrap();
+}
+
+#[test]
+fn test_sst_corrupted_block() {
+    let (dir, sst) = generate_sst();
+    let offset = sst.block_metas[1].offset + 1;
+    let sst = corrupt_sst(&dir, offset).unwrap();
+    sst.read_block(0).unwrap();
+    let err = sst.read_block(1).err().unwrap();
+    assert_eq!(err.to_string(), "SST 0 is corrupted at block 1");
+    assert_eq!(
+        corruption_of(err),
+        CorruptionError {
+            sst_id: 0,
+            section: CorruptedSection::Block(1)
+        }
+    );
+    assert_eq!(
+        corruption_of(sst.verify_checksums().err().unwrap()).section,
+        CorruptedSection::Block(1)
+    );
+}
+
+#[test]
+fn test_sst_unknown_block_format_version() {
+    let (dir, sst) = generate_sst();
+    let (start, end) = (sst.block_metas[1].offset, sst.block_metas[2].offset);
+    let path = dir.path().join("1.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    // The format version is followed by the format marker, the compression ID and the checksum.
+    // Keep the checksum valid, so that only decoding the block fails.
+    data[end - SIZEOF_U32 - 4] = 0xff;
+    let checksum = crc32fast::hash(&data[start..end - SIZEOF_U32]);
+    data[end - SIZEOF_U32..end].copy_from_slice(&checksum.to_be_bytes());
+    std::fs::write(&path, data).unwrap();
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    sst.read_block(0).unwrap();
+    assert_eq!(
+        corruption_of(sst.read_block(1).err().unwrap()),
+        CorruptionError {
+            sst_id: 0,
+            section: CorruptedSection::Block(1)
+        }
+    );
+}
+
+#[test]
+fn test_sst_corrupted_block_cached() {
+    let (dir, sst) = generate_sst();
+    let offset = sst.block_metas[0].offset;
+    let mut sst = corrupt_sst(&dir, offset).unwrap();
+    sst.block_cache = Some(Arc::new(BlockCache::new(16)));
+    let err = SsTableIterator::create_and_seek_to_first(Arc::new(sst))
+        .err()
+        .unwrap();
+    assert_eq!(corruption_of(err).section, CorruptedSection::Block(0));
+}
+
+#[test]
+fn test_sst_corrupted_meta_and_footer() {
+    let (dir, sst) = generate_sst();
+    let len = sst.file.size() as usize;
+    let err = corrupt_sst(&dir, sst.block_meta_offset + 1).err().unwrap();
+    assert_eq!(corruption_of(err).section, CorruptedSection::BlockMeta);
+
+    let (dir, _) = generate_sst();
+    let err = corrupt_sst(&dir, len - 10).err().unwrap();
+    assert_eq!(corruption_of(err).section, CorruptedSection::Footer);
+
+    let (dir, _) = generate_sst();
+    let err = corrupt_sst(&dir, len - 46).err().unwrap();
+    assert_eq!(corruption_of(err).section, CorruptedSection::BlobRefs);
+
+    let (dir, _) = generate_sst();
+    let err = corrupt_sst(&dir, len - 50).err().unwrap();
+    assert_eq!(
+        corruption_of(err).section,
+        CorruptedSection::RangeTombstones
+    );
+
+    let (dir, _) = generate_sst();
+    let err = corrupt_sst(&dir, len - 54).err().unwrap();
+    assert_eq!(corruption_of(err).section, CorruptedSection::BloomFilter);
+}
+
+/// The compression ID stored after a block.
+fn block_compression(sst: &SsTable, block_idx: usize) -> u8 {
+    let block_end = sst
+        .block_metas
+        .get(block_idx + 1)
+        .map_or(sst.block_meta_offset, |x| x.offset);
+    let raw = sst.file.read(block_end as u64 - 5, 1).unwrap();
+    raw[0]
+}
+
+#[test]
+fn test_sst_compression() {
+    let build = |compression| {
+        let mut builder = SsTableBuilder::new(4096).with_compression(compression);
+        for idx in 0..num_of_keys() {
+            builder.add(&key_of(idx), 0, &[b'a' + (idx % 4) as u8; 200]);
+        }
+        let dir = tempdir().unwrap();
+        let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+        (dir, sst)
+    };
+    let (_dir, uncompressed) = build(CompressionType::None);
+    let (dir, compressed) = build(CompressionType::Snappy);
+    assert!(compressed.table_size() * 2 < uncompressed.table_size());
+    for block_idx in 0..compressed.num_of_blocks() {
+        assert_eq!(
+            block_compression(&compressed, block_idx),
+            CompressionType::Snappy.id()
+        );
+        assert_eq!(
+            block_compression(&uncompressed, block_idx),
+            CompressionType::None.id()
+        );
+    }
+
+    let file = FileObject::open(&dir.path().join("1.sst")).unwrap();
+    let mut sst = SsTable::open_for_test(file).unwrap();
+    sst.block_cache = Some(Arc::new(BlockCache::new(16)));
+    sst.verify_checksums().unwrap();
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), [b'a' + (idx % 4) as u8; 200]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+/// Stores each run of a repeated byte as its length and the byte.
+struct RunLengthCompressor;
+
+impl BlockCompressor for RunLengthCompressor {
+    fn id(&self) -> u8 {
+        7
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        let mut rest = data;
+        while let Some(&byte) = rest.first() {
+            let run = rest
+                .iter()
+                .take(u8::MAX as usize)
+                .take_while(|x| **x == byte)
+                .count();
+            compressed.extend([run as u8, byte]);
+            rest = &rest[run..];
+        }
+        Ok(compressed)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(data
+            .chunks(2)
+            .flat_map(|run| std::iter::repeat(run[1]).take(run[0] as usize))
+            .collect())
+    }
+}
+
+#[test]
+fn test_sst_custom_compression() {
+    let compressor: Arc<dyn BlockCompressor> = Arc::new(RunLengthCompressor);
+    let mut builder =
+        SsTableBuilder::new(4096).with_compression(CompressionType::Custom(compressor.clone()));
+    for idx in 0..num_of_keys() {
+        builder.add(&key_of(idx), 0, &[b'a' + (idx % 4) as u8; 200]);
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    for block_idx in 0..sst.num_of_blocks() {
+        assert_eq!(block_compression(&sst, block_idx), 7);
+    }
+
+    let file = FileObject::open(&dir.path().join("1.sst")).unwrap();
+    let sst = SsTable::open(0, None, vec![compressor], file).unwrap();
+    sst.verify_checksums().unwrap();
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), [b'a' + (idx % 4) as u8; 200]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // The blocks cannot be read without the codec they were compressed with.
+    let sst = SsTable::open_for_test(FileObject::open(&dir.path().join("1.sst")).unwrap()).unwrap();
+    assert!(sst.read_block(0).is_err());
+}
+
+#[test]
+fn test_sst_incompressible_block() {
+    let mut builder = SsTableBuilder::new(4096).with_compression(CompressionType::Snappy);
+    // A single short entry does not get smaller after compression.
+    builder.add(b"1", 0x0123456789abcdef, b"2");
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(block_compression(&sst, 0), CompressionType::None.id());
+    assert_eq!(sst.get(b"1", u64::MAX).unwrap().unwrap().2, b"2"[..]);
+}
+
+#[test]
+fn test_sst_range_tombstones() {
+    let mut builder = SsTableBuilder::new(64);
+    builder.add(b"key_5", 1, b"value");
+    builder.add_range_tombstone(RangeTombstone::new(
+        Bytes::from("key_3"),
+        Bytes::from("key_8"),
+        2,
+    ));
+    builder.add_range_tombstone(RangeTombstone::new(
+        Bytes::from("key_1"),
+        Bytes::from("key_4"),
+        3,
+    ));
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.range_tombstones().len(), 2);
+    assert_eq!(sst.max_seq(), 3);
+    // The key range of the SST covers its range tombstones.
+    assert_eq!(sst.first_key()[..], b"key_1"[..]);
+    assert_eq!(sst.last_key()[..], b"key_8"[..]);
+    assert_eq!(sst.latest_covering_seq(b"key_3", u64::MAX), 3);
+    assert_eq!(sst.latest_covering_seq(b"key_3", 2), 2);
+    assert_eq!(sst.latest_covering_seq(b"key_5", u64::MAX), 2);
+    assert_eq!(sst.latest_covering_seq(b"key_8", u64::MAX), 0);
+    assert_eq!(sst.get(b"key_5", u64::MAX).unwrap().unwrap().0, 1);
+}
+
+#[test]
+fn test_sst_only_range_tombstones() {
+    let mut builder = SsTableBuilder::new(64);
+    builder.add_range_tombstone(RangeTombstone::new(
+        Bytes::from("key_1"),
+        Bytes::from("key_4"),
+        1,
+    ));
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert!(sst.get(b"key_2", u64::MAX).unwrap().is_none());
+    assert!(!SsTableIterator::create_and_seek_to_first(sst.clone())
+        .unwrap()
+        .is_valid());
+    assert!(!SsTableIterator::create_and_seek_to_key(sst, b"key_2")
+        .unwrap()
+        .is_valid());
+}
+
+#[test]
+fn test_sst_large_entries() {
+    // Entries larger than the block size, between small ones, each in a block of its own.
+    let large_key = |idx: usize| [key_of(idx), b"_".repeat(80000)].concat();
+    let mut builder = SsTableBuilder::new(256);
+    let mut entries = Vec::new();
+    for idx in 0..20 {
+        let (key, value) = if idx % 4 == 1 {
+            (large_key(idx), value_of(idx).repeat(10000))
+        } else {
+            (key_of(idx), value_of(idx))
+        };
+        builder.add(&key, idx as u64, &value);
+        entries.push((key, value));
+    }
+    // A range tombstone with bounds larger than 64 KiB.
+    builder.add_range_tombstone(RangeTombstone::new(
+        Bytes::from(large_key(100)),
+        Bytes::from(large_key(200)),
+        100,
+    ));
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    // Read the block metas and range tombstones back from the file.
+    let sst = Arc::new(SsTable::open_for_test(sst.file).unwrap());
+    assert_eq!(sst.range_tombstones()[0].start, large_key(100));
+    for (idx, (key, value)) in entries.iter().enumerate() {
+        assert_eq!(
+            sst.get(key, u64::MAX).unwrap().unwrap(),
+            (idx as u64, ValueType::Put, Bytes::from(value.clone()))
+        );
+        if idx % 4 == 1 {
+            let block_idx = sst.find_block_idx(key);
+            assert_eq!(sst.block_metas[block_idx].first_key, key[..]);
+            assert_eq!(sst.block_metas[block_idx].last_key, key[..]);
+        }
+    }
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for (key, value) in &entries {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.value(), value);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_blob_file() {
+    let dir = tempdir().unwrap();
+    let mut builder =
+        SsTableBuilder::new(128).with_blob_file(crate::blob::BlobFileBuilder::new(7), 16);
+    builder.add(b"key1", 3, b"small");
+    builder.add(b"key2", 2, b"a value of at least 16 bytes");
+    builder.add_with_type(b"key3", 1, ValueType::Merge, b"a large merge operand");
+    let blob_file = builder
+        .take_blob_file()
+        .unwrap()
+        .build(dir.path().join("7.blob"))
+        .unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(sst.blob_refs(), [(7, blob_file.size())]);
+
+    // Only the large value is moved to the blob file.
+    let (_, value_type, value) = sst.get(b"key1", 3).unwrap().unwrap();
+    assert_eq!((value_type, &value[..]), (ValueType::Put, &b"small"[..]));
+    let (_, value_type, pointer) = sst.get(b"key2", 3).unwrap().unwrap();
+    assert_eq!(value_type, ValueType::Blob);
+    let pointer = crate::blob::BlobPointer::decode(&pointer).unwrap();
+    assert_eq!(
+        blob_file.read(&pointer).unwrap(),
+        "a value of at least 16 bytes"
+    );
+    let (_, value_type, _) = sst.get(b"key3", 3).unwrap().unwrap();
+    assert_eq!(value_type, ValueType::Merge);
+
+    // The blob references are kept in the file.
+    let sst = SsTable::open_for_test(FileObject::open(&dir.path().join("1.sst")).unwrap()).unwrap();
+    assert_eq!(sst.blob_refs(), [(7, blob_file.size())]);
+}
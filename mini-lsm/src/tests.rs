@@ -1 +1,22 @@
+pub mod auto_flush_tests;
+pub mod blob_tests;
+pub mod compact_range_tests;
+pub mod compaction_filter_tests;
+pub mod corruption_tests;
 pub mod day4_tests;
+pub mod day5_tests;
+pub mod day6_tests;
+pub mod day7_tests;
+pub mod empty_value_tests;
+pub mod fifo_compaction_tests;
+mod harness;
+pub mod large_value_tests;
+pub mod merge_tests;
+pub mod range_deletion_tests;
+pub mod scan_rev_tests;
+pub mod seek_tests;
+pub mod snapshot_tests;
+pub mod tiered_compaction_tests;
+pub mod transaction_tests;
+pub mod ttl_tests;
+pub mod write_batch_tests;
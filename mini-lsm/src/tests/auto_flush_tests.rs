@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+use super::harness::{key_of, value_of};
+
+fn small_write_buffer_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        write_buffer_size: 1024,
+        // Keep the SSTs in L0, so that the flushed SSTs can be counted.
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 1000,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Wait for the flush thread to flush all immutable memtables.
+fn wait_for_flush(storage: &LsmStorage) {
+    let start = Instant::now();
+    while !storage.core.inner.read().imm_memtables.is_empty() {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "memtables are not flushed"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_auto_flush() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, small_write_buffer_options()).unwrap();
+    for idx in 0..1000 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    wait_for_flush(&storage);
+    {
+        let snapshot = storage.core.inner.read().clone();
+        // Each entry takes 24 bytes, so each memtable is frozen after 43 entries.
+        assert_eq!(snapshot.l0_sstables.len(), 1000 / 43);
+        assert!(snapshot.memtable.approximate_size() < 1024);
+    }
+    for idx in 0..1000 {
+        assert_eq!(
+            &storage.get(&key_of(idx)).unwrap().unwrap()[..],
+            &value_of(idx, 0)[..]
+        );
+    }
+
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, small_write_buffer_options()).unwrap();
+    for idx in 0..1000 {
+        assert_eq!(
+            &storage.get(&key_of(idx)).unwrap().unwrap()[..],
+            &value_of(idx, 0)[..]
+        );
+    }
+}
+
+#[test]
+fn test_auto_flush_recovered_memtables() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        write_buffer_size: 1 << 20,
+        ..small_write_buffer_options()
+    };
+    {
+        let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        }
+    }
+    // The memtable recovered from the WAL is flushed in the background.
+    let storage = LsmStorage::open_with_options(&dir, small_write_buffer_options()).unwrap();
+    wait_for_flush(&storage);
+    assert_eq!(storage.core.inner.read().l0_sstables.len(), 1);
+    for idx in 0..100 {
+        assert_eq!(
+            &storage.get(&key_of(idx)).unwrap().unwrap()[..],
+            &value_of(idx, 0)[..]
+        );
+    }
+}
+
+#[test]
+fn test_auto_flush_waits_for_flush() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        max_imm_memtables: 1,
+        ..small_write_buffer_options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    for idx in 0..1000 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        assert!(storage.core.inner.read().imm_memtables.len() <= 1);
+    }
+    wait_for_flush(&storage);
+    for idx in 0..1000 {
+        assert_eq!(
+            &storage.get(&key_of(idx)).unwrap().unwrap()[..],
+            &value_of(idx, 0)[..]
+        );
+    }
+}
+
+#[test]
+fn test_auto_flush_error() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        max_imm_memtables: 1,
+        ..small_write_buffer_options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    // The SST of the current memtable cannot be created where a directory is in the way.
+    let memtable_id = storage.core.inner.read().memtable.id();
+    std::fs::create_dir(storage.core.path_of_sst(memtable_id)).unwrap();
+
+    // The writes fail once the flush has failed, instead of filling up the memory.
+    let error = (0..1000)
+        .find_map(|idx| storage.put(&key_of(idx), &value_of(idx, 0)).err())
+        .expect("writes do not fail");
+    assert!(error.to_string().starts_with("flush failed"), "{:#}", error);
+    assert!(storage.put(b"key", b"value").is_err());
+    assert!(storage.sync().is_err());
+    assert_eq!(storage.core.inner.read().imm_memtables.len(), 1);
+}
@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionStrategy, CompactionTask, LeveledCompactionOptions};
+use crate::compaction_filter::{CompactionFilter, FilterDecision};
+use crate::iterators::ValueType;
+use crate::lsm_storage::{LsmStorage, LsmStorageCore, LsmStorageInner, LsmStorageOptions};
+use crate::table::CompressionType;
+
+use super::harness::{check_storage, compact_until_done, count_in_ssts, key_of, AppendOperator};
+
+/// A value that goes to a blob file.
+fn large_value_of(idx: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{}_", idx, round)
+        .repeat(10)
+        .into_bytes()
+}
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 256,
+        target_sst_size: 1 << 20,
+        write_buffer_size: 1 << 20,
+        bloom_bits_per_key: 10,
+        compression_per_level: vec![CompressionType::None],
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 1,
+            ..Default::default()
+        },
+        merge_operator: Some(Arc::new(AppendOperator)),
+        min_blob_size: Some(64),
+        ..Default::default()
+    }
+}
+
+/// Flush the memtable, and compact every SST into the bottom level, which drops the versions
+/// hidden by newer ones. The value of `key_of(9)` is written again, so that there are enough L0
+/// SSTs to compact.
+fn compact_all(storage: &LsmStorage) {
+    storage.sync().unwrap();
+    let value = storage.get(&key_of(9)).unwrap().unwrap();
+    storage.put(&key_of(9), &value).unwrap();
+    storage.sync().unwrap();
+    compact_until_done(storage);
+    assert!(storage.core.inner.read().l0_sstables.is_empty());
+}
+
+fn blob_file_ids(storage: &LsmStorage) -> Vec<usize> {
+    storage
+        .core
+        .inner
+        .read()
+        .blob_files
+        .keys()
+        .copied()
+        .collect()
+}
+
+#[test]
+fn test_blob_values() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    let mut expected = BTreeMap::new();
+    for idx in 0..20 {
+        let value = if idx % 2 == 0 {
+            large_value_of(idx, 0)
+        } else {
+            b"small".to_vec()
+        };
+        storage.put(&key_of(idx), &value).unwrap();
+        expected.insert(key_of(idx), value);
+    }
+    check_storage(&storage, &expected, 20);
+
+    // Only the large values are moved to a blob file.
+    storage.sync().unwrap();
+    assert_eq!(blob_file_ids(&storage).len(), 1);
+    assert_eq!(count_in_ssts(&storage, ValueType::Blob), 10);
+    assert_eq!(count_in_ssts(&storage, ValueType::Put), 10);
+    check_storage(&storage, &expected, 20);
+
+    // Compaction keeps the pointers.
+    storage.delete(&key_of(0)).unwrap();
+    expected.remove(&key_of(0));
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+    assert_eq!(count_in_ssts(&storage, ValueType::Blob), 9);
+    check_storage(&storage, &expected, 20);
+
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    check_storage(&storage, &expected, 20);
+}
+
+#[test]
+fn test_blob_merge() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    storage.put(&key_of(0), &large_value_of(0, 0)).unwrap();
+    storage.sync().unwrap();
+    storage.merge(&key_of(0), b",a").unwrap();
+    let value = [large_value_of(0, 0), b",a".to_vec()].concat();
+    let expected = BTreeMap::from([(key_of(0), value.clone())]);
+    check_storage(&storage, &expected, 1);
+    storage.sync().unwrap();
+    check_storage(&storage, &expected, 1);
+
+    // Compacting into the bottom level merges the operand into the value from the blob file.
+    compact_until_done(&storage);
+    assert_eq!(count_in_ssts(&storage, ValueType::Blob), 0);
+    check_storage(&storage, &expected, 1);
+}
+
+#[test]
+fn test_blob_gc() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    let mut expected = BTreeMap::new();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &large_value_of(idx, 0)).unwrap();
+        expected.insert(key_of(idx), large_value_of(idx, 0));
+    }
+    storage.sync().unwrap();
+    let old_blob_file = blob_file_ids(&storage)[0];
+
+    // Overwrite most of the values, so that compaction drops the pointers to them.
+    for idx in 0..6 {
+        storage.put(&key_of(idx), &large_value_of(idx, 1)).unwrap();
+        expected.insert(key_of(idx), large_value_of(idx, 1));
+    }
+    storage.merge(&key_of(6), b",a").unwrap();
+    expected.insert(key_of(6), [large_value_of(6, 0), b",a".to_vec()].concat());
+    storage.delete_range(&key_of(7), &key_of(8)).unwrap();
+    expected.remove(&key_of(7));
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+    let snapshot = storage.core.inner.read().clone();
+    let dead_ratio = LsmStorageCore::blob_dead_ratios(&snapshot)[&old_blob_file];
+    assert!(dead_ratio >= 0.5, "dead ratio is {}", dead_ratio);
+
+    // The live values are written back, and the old blob file is deleted once compaction drops
+    // the versions pointing to it.
+    while storage.core.trigger_blob_gc().unwrap() {}
+    assert!(blob_file_ids(&storage).contains(&old_blob_file));
+    check_storage(&storage, &expected, 10);
+    compact_all(&storage);
+    while storage.core.trigger_blob_gc().unwrap() {}
+    assert!(!blob_file_ids(&storage).contains(&old_blob_file));
+    assert!(!storage.core.path_of_blob(old_blob_file).exists());
+    check_storage(&storage, &expected, 10);
+
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    check_storage(&storage, &expected, 10);
+}
+
+#[test]
+fn test_blob_gc_with_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    let mut expected = BTreeMap::new();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &large_value_of(idx, 0)).unwrap();
+        expected.insert(key_of(idx), large_value_of(idx, 0));
+    }
+    storage.sync().unwrap();
+    let old_blob_file = blob_file_ids(&storage)[0];
+    for idx in 0..6 {
+        storage.put(&key_of(idx), b"small").unwrap();
+        expected.insert(key_of(idx), b"small".to_vec());
+    }
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+
+    // The snapshot reads the live values from the old blob file, so it is only deleted after the
+    // snapshot is released.
+    let snapshot = storage.snapshot();
+    storage.core.trigger_blob_gc().unwrap();
+    assert!(blob_file_ids(&storage).contains(&old_blob_file));
+    for idx in 6..10 {
+        assert_eq!(
+            snapshot.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(large_value_of(idx, 0)))
+        );
+    }
+    drop(snapshot);
+    compact_all(&storage);
+    while storage.core.trigger_blob_gc().unwrap() {}
+    assert!(!blob_file_ids(&storage).contains(&old_blob_file));
+    assert!(!storage.core.path_of_blob(old_blob_file).exists());
+    check_storage(&storage, &expected, 10);
+}
+
+#[test]
+fn test_blob_gc_does_not_conflict_with_txns() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        blob_gc_dead_ratio: 0.0,
+        ..options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &large_value_of(idx, 0)).unwrap();
+    }
+    storage.sync().unwrap();
+    let old_blob_file = blob_file_ids(&storage)[0];
+
+    // Writing back the values the transaction read does not change them, so it still commits.
+    let txn = storage.new_txn();
+    assert_eq!(
+        txn.get(&key_of(0)).unwrap(),
+        Some(Bytes::from(large_value_of(0, 0)))
+    );
+    txn.put(&key_of(1), b"small");
+    let seq = storage.snapshot().seq();
+    while storage.core.trigger_blob_gc().unwrap() {}
+    assert!(storage.snapshot().seq() > seq);
+    assert_eq!(storage.core.collected_blob_files.lock()[0].0, old_blob_file);
+    txn.commit().unwrap();
+    assert_eq!(storage.get(&key_of(1)).unwrap(), Some(Bytes::from("small")));
+}
+
+/// Keeps every value, so that compaction reads the values in the blob files.
+struct KeepAllFilter;
+
+impl CompactionFilter for KeepAllFilter {
+    fn filter(&self, _level: usize, _key: &[u8], _value: &[u8], _: bool) -> FilterDecision {
+        FilterDecision::Keep
+    }
+}
+
+/// Compacts the oldest L0 SST alone into L1 once enabled.
+#[derive(Default)]
+struct OldestL0Strategy {
+    enabled: AtomicBool,
+}
+
+impl CompactionStrategy for OldestL0Strategy {
+    fn generate_compaction_task(&self, snapshot: &LsmStorageInner) -> Option<CompactionTask> {
+        let oldest = snapshot.l0_sstables().first()?.sst_id();
+        if !self.enabled.swap(false, Ordering::SeqCst) {
+            return None;
+        }
+        Some(CompactionTask {
+            l0_sst_ids: vec![oldest],
+            level_sst_ids: vec![(1, snapshot.levels()[0].iter().map(|x| x.sst_id()).collect())],
+            output_level: 1,
+            is_output_bottom_level: true,
+            drop_inputs: false,
+        })
+    }
+
+    fn num_levels(&self) -> usize {
+        1
+    }
+}
+
+#[test]
+fn test_blob_gc_keeps_files_referenced_by_ssts() {
+    let dir = tempdir().unwrap();
+    let strategy = Arc::new(OldestL0Strategy::default());
+    let options = LsmStorageOptions {
+        compaction_strategy: Some(strategy.clone()),
+        compaction_filter: Some(Arc::new(KeepAllFilter)),
+        blob_gc_dead_ratio: 0.0,
+        ..options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    let mut expected = BTreeMap::new();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &large_value_of(idx, 0)).unwrap();
+        expected.insert(key_of(idx), large_value_of(idx, 0));
+    }
+    storage.sync().unwrap();
+    let old_blob_file = blob_file_ids(&storage)[0];
+
+    // The values are written back, but the SST in L0 still points to the old blob file.
+    while storage.core.trigger_blob_gc().unwrap() {}
+    storage.sync().unwrap();
+    assert!(storage.core.path_of_blob(old_blob_file).exists());
+    check_storage(&storage, &expected, 10);
+
+    // The compaction of that SST without the newer one reads the values from the old blob file.
+    strategy.enabled.store(true, Ordering::SeqCst);
+    storage.core.trigger_compaction().unwrap();
+    assert!(!strategy.enabled.load(Ordering::SeqCst));
+    assert_eq!(storage.core.inner.read().levels()[0].len(), 1);
+    assert!(storage.core.path_of_blob(old_blob_file).exists());
+    check_storage(&storage, &expected, 10);
+
+    // The old blob file is deleted once no SST points to it.
+    strategy.enabled.store(true, Ordering::SeqCst);
+    storage.core.trigger_compaction().unwrap();
+    while storage.core.trigger_blob_gc().unwrap() {}
+    assert!(!blob_file_ids(&storage).contains(&old_blob_file));
+    assert!(!storage.core.path_of_blob(old_blob_file).exists());
+    check_storage(&storage, &expected, 10);
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use super::harness::{check_storage, count_in_ssts, key_of, value_of};
+use crate::compact::LeveledCompactionOptions;
+use crate::iterators::ValueType;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 256,
+        target_sst_size: 4096,
+        // Leave the SSTs in L0 unless they are compacted by `compact_range`.
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 100,
+            max_levels: 3,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_compact_range_drops_deletions() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    let mut expected = BTreeMap::new();
+    for idx in 0..1000 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        expected.insert(key_of(idx), value_of(idx, 0));
+    }
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    for idx in 0..300 {
+        storage.delete(&key_of(idx)).unwrap();
+        expected.remove(&key_of(idx));
+    }
+    storage.delete_range(&key_of(300), &key_of(500)).unwrap();
+    for idx in 300..500 {
+        expected.remove(&key_of(idx));
+    }
+    storage.sync().unwrap();
+
+    // The snapshot still sees the deleted keys, so their versions are kept.
+    storage
+        .compact_range(Bound::Unbounded, Bound::Excluded(&key_of(500)))
+        .unwrap();
+    assert_eq!(
+        snapshot.get(&key_of(0)).unwrap().unwrap(),
+        value_of(0, 0).as_slice()
+    );
+    assert_eq!(count_in_ssts(&storage, ValueType::Put), 1000);
+    drop(snapshot);
+
+    storage
+        .compact_range(Bound::Unbounded, Bound::Excluded(&key_of(500)))
+        .unwrap();
+    let snapshot = storage.core.inner.read().clone();
+    assert!(snapshot.l0_sstables().is_empty());
+    assert!(snapshot.levels()[..2].iter().all(|x| x.is_empty()));
+    assert!(snapshot.levels()[2]
+        .iter()
+        .all(|x| x.range_tombstones().is_empty()));
+    assert_eq!(count_in_ssts(&storage, ValueType::Put), 500);
+    assert_eq!(count_in_ssts(&storage, ValueType::Delete), 0);
+    check_storage(&storage, &expected, 1000);
+
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    check_storage(&storage, &expected, 1000);
+}
+
+#[test]
+fn test_compact_range_includes_overlapping_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    let mut expected = BTreeMap::new();
+    for idx in 900..1100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        expected.insert(key_of(idx), value_of(idx, 0));
+    }
+    storage.sync().unwrap();
+    for idx in (0..1000).step_by(10) {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+        expected.insert(key_of(idx), value_of(idx, 1));
+    }
+    storage.sync().unwrap();
+    for idx in 2000..2100 {
+        storage.put(&key_of(idx), &value_of(idx, 2)).unwrap();
+        expected.insert(key_of(idx), value_of(idx, 2));
+    }
+    storage.sync().unwrap();
+
+    // Only the second SST has keys in the range, but the first one overlaps with it, so it has to
+    // be compacted too, or it would shadow the newer versions in the last level.
+    storage
+        .compact_range(Bound::Included(&key_of(10)), Bound::Included(&key_of(20)))
+        .unwrap();
+    let snapshot = storage.core.inner.read().clone();
+    assert_eq!(snapshot.l0_sstables().len(), 1);
+    assert!(!snapshot.levels()[2].is_empty());
+    check_storage(&storage, &expected, 2100);
+
+    // Nothing is in the range.
+    assert!(!storage
+        .core
+        .compact_range(
+            Bound::Excluded(&key_of(1099)),
+            Bound::Excluded(&key_of(2000))
+        )
+        .unwrap());
+}
@@ -0,0 +1,155 @@
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use super::harness::{compact_until_done, count_in_ssts, key_of};
+use crate::compact::LeveledCompactionOptions;
+use crate::compaction_filter::{CompactionFilter, FilterDecision};
+use crate::iterators::ValueType;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+/// Removes the values of the form `expiry:data` whose expiry is at or before `now`, and renames
+/// the data starting with `old-` to `new-`.
+#[derive(Default)]
+struct ExpiryFilter {
+    now: AtomicU64,
+    /// The `(level, is_bottom_level)` of each call.
+    calls: Mutex<Vec<(usize, bool)>>,
+}
+
+impl CompactionFilter for ExpiryFilter {
+    fn filter(
+        &self,
+        level: usize,
+        _key: &[u8],
+        value: &[u8],
+        is_bottom_level: bool,
+    ) -> FilterDecision {
+        self.calls.lock().push((level, is_bottom_level));
+        let value = std::str::from_utf8(value).unwrap();
+        let (expiry, data) = value.split_once(':').unwrap();
+        if expiry.parse::<u64>().unwrap() <= self.now.load(Ordering::SeqCst) {
+            return FilterDecision::Remove;
+        }
+        match data.strip_prefix("old-") {
+            Some(name) => {
+                FilterDecision::ChangeValue(Bytes::from(format!("{}:new-{}", expiry, name)))
+            }
+            None => FilterDecision::Keep,
+        }
+    }
+}
+
+fn options(filter: Arc<ExpiryFilter>) -> LsmStorageOptions {
+    LsmStorageOptions {
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+            ..Default::default()
+        },
+        compaction_filter: Some(filter),
+        ..Default::default()
+    }
+}
+
+fn get(storage: &LsmStorage, idx: usize) -> Option<String> {
+    let value = storage.get(&key_of(idx)).unwrap()?;
+    Some(String::from_utf8(value.to_vec()).unwrap())
+}
+
+#[test]
+fn test_compaction_filter() {
+    let dir = tempdir().unwrap();
+    let filter = Arc::new(ExpiryFilter::default());
+    let storage = LsmStorage::open_with_options(&dir, options(filter.clone())).unwrap();
+    for idx in 0..100 {
+        let value = format!("100:value_{}", idx);
+        storage.put(&key_of(idx), value.as_bytes()).unwrap();
+    }
+    storage.sync().unwrap();
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert!(filter.calls.lock().iter().all(|x| *x == (2, true)));
+    assert_eq!(filter.calls.lock().len(), 100);
+    // Flushes do not call the filter.
+    filter.calls.lock().clear();
+
+    // Newer values of the first keys, which have already expired, and values to rename.
+    filter.now.store(50, Ordering::SeqCst);
+    for idx in 0..50 {
+        let value = format!("10:value_{}", idx);
+        storage.put(&key_of(idx), value.as_bytes()).unwrap();
+    }
+    storage.sync().unwrap();
+    for idx in 100..110 {
+        let value = format!("1000:old-{}", idx);
+        storage.put(&key_of(idx), value.as_bytes()).unwrap();
+    }
+    storage.sync().unwrap();
+    assert!(filter.calls.lock().is_empty());
+
+    // The expired values are replaced with tombstones, so the older values in L2 do not come back.
+    compact_until_done(&storage);
+    assert!(filter.calls.lock().iter().all(|x| *x == (1, false)));
+    assert_eq!(count_in_ssts(&storage, ValueType::Delete), 50);
+    for idx in 0..50 {
+        assert_eq!(get(&storage, idx), None);
+    }
+    for idx in 50..100 {
+        assert_eq!(get(&storage, idx), Some(format!("100:value_{}", idx)));
+    }
+    for idx in 100..110 {
+        assert_eq!(get(&storage, idx), Some(format!("1000:new-{}", idx)));
+    }
+
+    // At the bottom level, the tombstones and the removed values are dropped.
+    filter.now.store(100, Ordering::SeqCst);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(count_in_ssts(&storage, ValueType::Delete), 0);
+    assert_eq!(count_in_ssts(&storage, ValueType::Put), 10);
+    for idx in 0..100 {
+        assert_eq!(get(&storage, idx), None);
+    }
+
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, options(filter)).unwrap();
+    for idx in 100..110 {
+        assert_eq!(get(&storage, idx), Some(format!("1000:new-{}", idx)));
+    }
+}
+
+#[test]
+fn test_compaction_filter_keeps_versions_for_snapshot() {
+    let dir = tempdir().unwrap();
+    let filter = Arc::new(ExpiryFilter::default());
+    filter.now.store(50, Ordering::SeqCst);
+    let storage = LsmStorage::open_with_options(&dir, options(filter.clone())).unwrap();
+    storage.put(&key_of(0), b"100:value").unwrap();
+    let snapshot = storage.snapshot();
+    storage.put(&key_of(0), b"10:expired").unwrap();
+    storage.sync().unwrap();
+
+    // Only the version the snapshot sees goes through the filter.
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(filter.calls.lock().len(), 1);
+    assert_eq!(get(&storage, 0), Some("10:expired".to_string()));
+    assert_eq!(
+        snapshot.get(&key_of(0)).unwrap(),
+        Some(Bytes::from("100:value"))
+    );
+    drop(snapshot);
+
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(get(&storage, 0), None);
+}
@@ -0,0 +1,36 @@
+use tempfile::tempdir;
+
+use crate::lsm_storage::LsmStorage;
+use crate::table::{CorruptedSection, CorruptionError};
+
+#[test]
+fn test_storage_verify_checksums() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    for idx in 0..100 {
+        storage
+            .put(format!("key_{:03}", idx).as_bytes(), b"value")
+            .unwrap();
+    }
+    storage.sync().unwrap();
+    storage.verify_checksums().unwrap();
+
+    let sst_id = storage.core.inner.read().l0_sstables[0].sst_id();
+    let path = storage.core.path_of_sst(sst_id);
+    let mut data = std::fs::read(&path).unwrap();
+    data[1] ^= 1;
+    std::fs::write(&path, data).unwrap();
+    let err = storage.verify_checksums().err().unwrap();
+    assert_eq!(
+        *err.downcast_ref::<CorruptionError>().unwrap(),
+        CorruptionError {
+            sst_id,
+            section: CorruptedSection::Block(0)
+        }
+    );
+
+    // Data blocks are not read on open, so the corruption is only found by a check.
+    drop(storage);
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert!(storage.verify_checksums().is_err());
+}
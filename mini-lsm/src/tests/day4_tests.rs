@@ -185,3 +185,42 @@ fn test_storage_scan_memtable_2_after_sync() {
         vec![(Bytes::from("2"), Bytes::from("2333"))],
     );
 }
+
+#[test]
+fn test_storage_get_between_keys_after_sync() {
+    use crate::lsm_storage::LsmStorage;
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"3", b"23333").unwrap();
+    storage.put(b"5", b"2333333").unwrap();
+    storage.sync().unwrap();
+    // Keys that fall between, before and after the keys in the SST are not found.
+    assert!(storage.get(b"0").unwrap().is_none());
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert!(storage.get(b"4").unwrap().is_none());
+    assert!(storage.get(b"6").unwrap().is_none());
+    assert!(storage.get(b"11").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+}
+
+#[test]
+fn test_storage_get_tombstone_after_sync() {
+    use crate::lsm_storage::LsmStorage;
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.put(b"3", b"23333").unwrap();
+    storage.sync().unwrap();
+    storage.delete(b"2").unwrap();
+    storage.put(b"3", b"3").unwrap();
+    storage.sync().unwrap();
+    // The newest SST with the key wins, even if it deletes the key.
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"3");
+    storage.put(b"2", b"2").unwrap();
+    storage.sync().unwrap();
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2");
+}
@@ -0,0 +1,224 @@
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::table::{BlockCompressor, CompressionType, SnappyCompressor, SsTableIterator};
+
+use super::harness::{check_iter, compact_until_done, key_of, value_of};
+
+fn compaction_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 512,
+        write_buffer_size: 1 << 20,
+        bloom_bits_per_key: 10,
+        // L0 is not compressed, so that reads of SSTs with and without compression are tested.
+        compression_per_level: vec![CompressionType::None, CompressionType::Snappy],
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size: 1024,
+            level_size_multiplier: 2,
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_leveled_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    for round in 0..5 {
+        for idx in (round..200).step_by(2) {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.sync().unwrap();
+        compact_until_done(&storage);
+    }
+
+    {
+        let snapshot = storage.core.inner.read().clone();
+        assert!(snapshot.l0_sstables.len() < 2);
+        assert!(snapshot.levels.iter().any(|x| x.len() > 1));
+        for level in &snapshot.levels {
+            for ssts in level.windows(2) {
+                assert!(ssts[0].last_key() < ssts[1].first_key());
+            }
+        }
+    }
+
+    let expected = (0..200)
+        .map(|idx| {
+            // The last round that wrote this key.
+            let round = (0..5)
+                .rev()
+                .find(|r| r % 2 == idx % 2 && *r <= idx)
+                .unwrap();
+            (key_of(idx), value_of(idx, round))
+        })
+        .collect::<Vec<_>>();
+    for (key, value) in &expected {
+        assert_eq!(&storage.get(key).unwrap().unwrap()[..], &value[..]);
+    }
+    assert!(storage.get(b"key_00200").unwrap().is_none());
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected.clone(),
+    );
+
+    // The compacted tree is restored from the manifest.
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    for (key, value) in &expected {
+        assert_eq!(&storage.get(key).unwrap().unwrap()[..], &value[..]);
+    }
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected,
+    );
+}
+
+#[test]
+fn test_compaction_drops_tombstones_at_bottom_level() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.sync().unwrap();
+    for idx in (0..100).step_by(2) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+
+    let snapshot = storage.core.inner.read().clone();
+    assert!(snapshot.l0_sstables.is_empty());
+    for sst in snapshot.levels.iter().flatten() {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            assert!(!iter.value().is_empty(), "tombstone is not dropped");
+            iter.next().unwrap();
+        }
+    }
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        (1..100)
+            .step_by(2)
+            .map(|idx| (key_of(idx), value_of(idx, 0))),
+    );
+}
+
+#[test]
+fn test_background_compaction_error() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.sync().unwrap();
+    // The compaction triggered by the next SST cannot create its output files.
+    let memtable_id = storage.core.inner.read().memtable.id();
+    for sst_id in memtable_id + 1..memtable_id + 10 {
+        std::fs::create_dir(storage.core.path_of_sst(sst_id)).unwrap();
+    }
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage.sync().unwrap();
+
+    let start = Instant::now();
+    let err = loop {
+        if let Err(err) = storage.put(&key_of(0), &value_of(0, 2)) {
+            break err;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "compaction does not fail"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert!(
+        err.to_string().starts_with("compaction failed"),
+        "{:#}",
+        err
+    );
+    assert!(storage.delete(&key_of(0)).is_err());
+    assert!(storage.sync().is_err());
+    // Range compactions fail with the error even once they could write their output.
+    for sst_id in memtable_id + 1..memtable_id + 10 {
+        std::fs::remove_dir(storage.core.path_of_sst(sst_id)).unwrap();
+    }
+    assert!(storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .is_err());
+    assert!(storage
+        .core
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .is_err());
+    assert_eq!(storage.core.inner.read().l0_sstables.len(), 2);
+}
+
+#[test]
+fn test_open_rejects_zero_levels() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        compaction_options: LeveledCompactionOptions {
+            max_levels: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(LsmStorage::open_with_options(&dir, options).is_err());
+}
+
+/// Snappy under another compression ID.
+struct CustomSnappyCompressor(u8);
+
+impl BlockCompressor for CustomSnappyCompressor {
+    fn id(&self) -> u8 {
+        self.0
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        SnappyCompressor.compress(data)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        SnappyCompressor.decompress(data)
+    }
+}
+
+#[test]
+fn test_custom_compression() {
+    let dir = tempdir().unwrap();
+    let options = |id| LsmStorageOptions {
+        compression_per_level: vec![CompressionType::Custom(Arc::new(CustomSnappyCompressor(
+            id,
+        )))],
+        ..compaction_options()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options(7)).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+    drop(storage);
+
+    let storage = LsmStorage::open_with_options(&dir, options(7)).unwrap();
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        (0..100).map(|idx| (key_of(idx), value_of(idx, 0))),
+    );
+    drop(storage);
+
+    // The IDs of the built-in codecs cannot be taken.
+    assert!(LsmStorage::open_with_options(&dir, options(1)).is_err());
+}
@@ -0,0 +1,136 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+use super::harness::check_iter;
+
+#[test]
+fn test_storage_recover_from_wal() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.put(b"3", b"23333").unwrap();
+        storage.delete(b"2").unwrap();
+    }
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+
+    // Writes after recovery go to a new WAL, and survive another restart.
+    storage.put(b"4", b"233333").unwrap();
+    drop(storage);
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert_eq!(&storage.get(b"4").unwrap().unwrap()[..], b"233333");
+}
+
+#[test]
+fn test_storage_recover_from_synced_wal() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        sync_writes: true,
+        ..Default::default()
+    };
+    {
+        let storage = LsmStorage::open_with_options(&dir, options.clone()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.delete(b"1").unwrap();
+    }
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    assert!(storage.get(b"1").unwrap().is_none());
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+}
+
+#[test]
+fn test_storage_sync_removes_wal() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"1", b"233").unwrap();
+    }
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.sync().unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+    let wals = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|x| x.as_ref().unwrap().path().extension() == Some("wal".as_ref()))
+        .count();
+    // Only the WAL of the new mutable memtable is left.
+    assert_eq!(wals, 1);
+}
+
+#[test]
+fn test_storage_recover_from_manifest() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.sync().unwrap();
+        storage.put(b"3", b"23333").unwrap();
+        storage.sync().unwrap();
+        storage.put(b"4", b"233333").unwrap();
+        storage.delete(b"1").unwrap();
+    }
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert!(storage.get(b"1").unwrap().is_none());
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+    assert_eq!(&storage.get(b"4").unwrap().unwrap()[..], b"233333");
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("2"), Bytes::from("2333")),
+            (Bytes::from("3"), Bytes::from("23333")),
+            (Bytes::from("4"), Bytes::from("233333")),
+        ],
+    );
+}
+
+#[test]
+fn test_storage_sst_id_not_reused() {
+    let dir = tempdir().unwrap();
+    let list_files = || {
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|x| x.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    };
+    {
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.sync().unwrap();
+    }
+    let before = list_files();
+    {
+        let storage = LsmStorage::open(&dir).unwrap();
+        storage.put(b"1", b"2333").unwrap();
+        storage.sync().unwrap();
+        assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"2333");
+    }
+    let after = list_files();
+    let ssts = |files: &[String]| {
+        files
+            .iter()
+            .filter(|x| x.ends_with(".sst"))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    // The SST of the first run is still there, and a new one is created next to it.
+    assert_eq!(ssts(&before).len(), 1);
+    assert_eq!(ssts(&after).len(), 2);
+    assert!(after.contains(&ssts(&before)[0]));
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"2333");
+}
@@ -0,0 +1,81 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::lsm_storage::{BloomFilterStats, LsmStorage, LsmStorageOptions};
+
+use super::harness::key_of;
+
+#[test]
+fn test_storage_get_uses_bloom_filter() {
+    let dir = tempdir().unwrap();
+    // Keep the SSTs in L0, so that compaction does not merge them.
+    let options = LsmStorageOptions {
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 100,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    // Each SST covers the whole key range, so only the filters can rule them out.
+    for sst in 0..4 {
+        for idx in (sst..1000).step_by(4) {
+            storage.put(&key_of(idx), b"value").unwrap();
+        }
+        storage.sync().unwrap();
+    }
+
+    for idx in 0..1000 {
+        assert!(storage.get(&key_of(idx)).unwrap().is_some());
+    }
+    let stats = storage.bloom_filter_stats();
+    assert!(stats.checked > 0);
+    assert!(stats.useful > 0);
+    assert_eq!(
+        stats.checked - stats.useful - stats.false_positive,
+        1000,
+        "each key is found in exactly one SST"
+    );
+    // About 1% of the filters that do not contain the key should pass.
+    assert!(stats.false_positive < 100, "{:?}", stats);
+}
+
+#[test]
+fn test_storage_bloom_filter_disabled() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        bloom_bits_per_key: 0,
+        ..Default::default()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    for sst in 0..2 {
+        for idx in (sst..100).step_by(2) {
+            storage.put(&key_of(idx), b"value").unwrap();
+        }
+        storage.sync().unwrap();
+    }
+    for idx in 0..100 {
+        assert!(storage.get(&key_of(idx)).unwrap().is_some());
+    }
+    // No filter is checked, so nothing is counted.
+    assert_eq!(storage.bloom_filter_stats(), BloomFilterStats::default());
+}
+
+#[test]
+fn test_storage_bloom_filter_newer_versions() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"key", b"old").unwrap();
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    storage.put(b"key", b"new").unwrap();
+    storage.sync().unwrap();
+
+    // The newer SST has the key, so passing its filter is not a false positive, although the
+    // snapshot reads the older SST.
+    assert_eq!(snapshot.get(b"key").unwrap(), Some(Bytes::from("old")));
+    let stats = storage.bloom_filter_stats();
+    assert_eq!(stats.checked, 2);
+    assert_eq!(stats.false_positive, 0);
+}
@@ -0,0 +1,88 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::write_batch::WriteBatch;
+
+use super::harness::{check_iter, compact_until_done};
+
+/// Check that `key1` and `key3` have empty values, and `key2` is deleted.
+fn check_empty_values(storage: &LsmStorage) {
+    assert_eq!(storage.get(b"key1").unwrap(), Some(Bytes::new()));
+    assert_eq!(storage.get(b"key2").unwrap(), None);
+    assert_eq!(storage.get(b"key3").unwrap(), Some(Bytes::new()));
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        [(b"key1", b""), (b"key3", b"")],
+    );
+    check_iter(
+        storage
+            .scan_rev(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        [(b"key3", b""), (b"key1", b"")],
+    );
+}
+
+#[test]
+fn test_empty_values() {
+    let dir = tempdir().unwrap();
+    // Keep the SSTs in a single level, so that compaction into the bottom level drops tombstones.
+    let options = LsmStorageOptions {
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options.clone()).unwrap();
+    storage.put(b"key1", b"").unwrap();
+    storage.put(b"key2", b"value2").unwrap();
+    let mut batch = WriteBatch::new();
+    batch.delete(b"key2").put(b"key3", b"");
+    storage.write(&batch).unwrap();
+    check_empty_values(&storage);
+
+    // In an SST, and after compaction.
+    storage.sync().unwrap();
+    check_empty_values(&storage);
+    storage.put(b"key4", b"value4").unwrap();
+    storage.delete(b"key4").unwrap();
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+    check_empty_values(&storage);
+
+    // After recovery from the WAL.
+    storage.put(b"key2", b"value2").unwrap();
+    storage.delete(b"key2").unwrap();
+    storage.put(b"key1", b"").unwrap();
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    check_empty_values(&storage);
+}
+
+#[test]
+fn test_empty_values_in_transaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"key2", b"value2").unwrap();
+    let txn = storage.new_txn();
+    txn.put(b"key1", b"");
+    txn.delete(b"key2");
+    txn.put(b"key3", b"");
+    assert_eq!(txn.get(b"key1").unwrap(), Some(Bytes::new()));
+    assert_eq!(txn.get(b"key2").unwrap(), None);
+    let mut iter = txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for key in [b"key1", b"key3"] {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.value(), b"");
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    txn.commit().unwrap();
+    check_empty_values(&storage);
+}
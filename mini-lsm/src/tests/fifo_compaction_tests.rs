@@ -0,0 +1,109 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use super::harness::{compact_until_done, key_of, value_of};
+use crate::compact::{FifoCompactionController, FifoCompactionOptions};
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+fn options(fifo_options: FifoCompactionOptions) -> LsmStorageOptions {
+    LsmStorageOptions {
+        compaction_strategy: Some(Arc::new(FifoCompactionController::new(fifo_options))),
+        ..Default::default()
+    }
+}
+
+fn l0_sst_ids(storage: &LsmStorage) -> Vec<usize> {
+    let snapshot = storage.core.inner.read().clone();
+    snapshot.l0_sstables().iter().map(|x| x.sst_id()).collect()
+}
+
+/// Write the keys in `range` and flush them to an SST.
+fn flush_keys(storage: &LsmStorage, range: std::ops::Range<usize>) {
+    for idx in range {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.sync().unwrap();
+}
+
+#[test]
+fn test_fifo_compaction_size_limit() {
+    let dir = tempdir().unwrap();
+    let fifo_options = FifoCompactionOptions {
+        max_table_files_size: Some(0),
+        ttl: None,
+    };
+    // Find the size of an SST, and allow about three of them.
+    let sst_size = {
+        let storage = LsmStorage::open_with_options(&dir, options(fifo_options.clone())).unwrap();
+        flush_keys(&storage, 0..100);
+        let snapshot = storage.core.inner.read().clone();
+        snapshot.l0_sstables()[0].table_size()
+    };
+    let dir = tempdir().unwrap();
+    let fifo_options = FifoCompactionOptions {
+        max_table_files_size: Some(sst_size * 7 / 2),
+        ..fifo_options
+    };
+    let storage = LsmStorage::open_with_options(&dir, options(fifo_options.clone())).unwrap();
+    for round in 0..5 {
+        flush_keys(&storage, round * 100..(round + 1) * 100);
+        compact_until_done(&storage);
+    }
+    let sst_ids = l0_sst_ids(&storage);
+    assert_eq!(sst_ids.len(), 3);
+    let snapshot = storage.core.inner.read().clone();
+    assert!(snapshot.levels().iter().all(|x| x.is_empty()));
+    for idx in 0..200 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), None);
+    }
+    for idx in 200..500 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap().unwrap(),
+            value_of(idx, 0)
+        );
+    }
+
+    // The deleted SSTs stay deleted after recovery.
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, options(fifo_options)).unwrap();
+    assert_eq!(l0_sst_ids(&storage), sst_ids);
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+    assert_eq!(
+        storage.get(&key_of(499)).unwrap().unwrap(),
+        value_of(499, 0)
+    );
+}
+
+#[test]
+fn test_fifo_compaction_ttl() {
+    let dir = tempdir().unwrap();
+    let fifo_options = FifoCompactionOptions {
+        max_table_files_size: None,
+        ttl: Some(Duration::from_millis(500)),
+    };
+    let storage = LsmStorage::open_with_options(&dir, options(fifo_options)).unwrap();
+    flush_keys(&storage, 0..100);
+    flush_keys(&storage, 100..200);
+    compact_until_done(&storage);
+    let expired_sst_ids = l0_sst_ids(&storage);
+    assert_eq!(expired_sst_ids.len(), 2);
+
+    std::thread::sleep(Duration::from_millis(600));
+    flush_keys(&storage, 200..300);
+    compact_until_done(&storage);
+    assert_eq!(l0_sst_ids(&storage).len(), 1);
+    for idx in 0..200 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap(), None);
+    }
+    for idx in 200..300 {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap().unwrap(),
+            value_of(idx, 0)
+        );
+    }
+    for sst_id in expired_sst_ids {
+        assert!(!storage.core.path_of_sst(sst_id).exists());
+    }
+}
@@ -0,0 +1,97 @@
+//! Helpers shared by the storage tests.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use bytes::Bytes;
+
+use crate::iterators::{StorageIterator, ValueType};
+use crate::lsm_storage::LsmStorage;
+use crate::merge_operator::MergeOperator;
+use crate::table::SsTableIterator;
+
+/// Appends the operands to the value.
+pub struct AppendOperator;
+
+impl MergeOperator for AppendOperator {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Bytes {
+        Bytes::from([existing.unwrap_or_default(), operand].concat())
+    }
+}
+
+pub fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+pub fn value_of(idx: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{:03}", idx, round).into_bytes()
+}
+
+/// Run compaction tasks until the compaction strategy has nothing more to do.
+pub fn compact_until_done(storage: &LsmStorage) {
+    while storage.core.trigger_compaction().unwrap() {}
+}
+
+/// The number of entries of the given value type in all SSTs.
+pub fn count_in_ssts(storage: &LsmStorage, value_type: ValueType) -> usize {
+    let snapshot = storage.core.inner.read().clone();
+    let mut entries = 0;
+    for sst in snapshot
+        .l0_sstables
+        .iter()
+        .chain(snapshot.levels.iter().flatten())
+    {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            if iter.value_type() == value_type {
+                entries += 1;
+            }
+            iter.next().unwrap();
+        }
+    }
+    entries
+}
+
+/// Check that `iter` yields exactly the `expected` key-value pairs, in order.
+pub fn check_iter<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+    mut iter: impl StorageIterator,
+    expected: impl IntoIterator<Item = (K, V)>,
+) {
+    for (k, v) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(
+            k.as_ref(),
+            iter.key(),
+            "expected key: {:?}, actual key: {:?}",
+            Bytes::copy_from_slice(k.as_ref()),
+            Bytes::copy_from_slice(iter.key()),
+        );
+        assert_eq!(v.as_ref(), iter.value());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+/// Check `get` of the keys `key_of(0..num_keys)`, `scan` and `scan_rev` of `storage` against the
+/// expected contents.
+pub fn check_storage(storage: &LsmStorage, expected: &BTreeMap<Vec<u8>, Vec<u8>>, num_keys: usize) {
+    for idx in 0..num_keys {
+        let key = key_of(idx);
+        assert_eq!(
+            storage.get(&key).unwrap().map(|x| x.to_vec()),
+            expected.get(&key).cloned(),
+            "unexpected value of {:?}",
+            Bytes::from(key),
+        );
+    }
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        expected,
+    );
+    check_iter(
+        storage
+            .scan_rev(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        expected.iter().rev(),
+    );
+}
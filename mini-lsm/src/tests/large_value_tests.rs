@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::table::CompressionType;
+
+use super::harness::{check_storage, compact_until_done, key_of, value_of};
+
+/// A value of several hundred KiB for every tenth key, and a short one otherwise.
+fn large_value_of(idx: usize, round: usize) -> Vec<u8> {
+    let value = value_of(idx, round);
+    if idx % 10 == 0 {
+        value.repeat(20000)
+    } else {
+        value
+    }
+}
+
+/// The contents of the storage once every key is written in `round`.
+fn expected_of(num_keys: usize, round: usize) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    (0..num_keys)
+        .map(|idx| (key_of(idx), large_value_of(idx, round)))
+        .collect()
+}
+
+#[test]
+fn test_large_values() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        block_size: 4096,
+        target_sst_size: 1 << 20,
+        write_buffer_size: 4 << 20,
+        compression_per_level: vec![CompressionType::None, CompressionType::Snappy],
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+            base_level_size: 4 << 20,
+            level_size_multiplier: 2,
+        },
+        ..Default::default()
+    };
+    let num_keys = 50;
+    let storage = LsmStorage::open_with_options(&dir, options.clone()).unwrap();
+    for round in 0..3 {
+        for idx in 0..num_keys {
+            storage
+                .put(&key_of(idx), &large_value_of(idx, round))
+                .unwrap();
+        }
+        check_storage(&storage, &expected_of(num_keys, round), num_keys);
+        storage.sync().unwrap();
+        check_storage(&storage, &expected_of(num_keys, round), num_keys);
+    }
+    compact_until_done(&storage);
+    check_storage(&storage, &expected_of(num_keys, 2), num_keys);
+
+    // Recovered from the WAL.
+    for idx in 0..num_keys {
+        storage.put(&key_of(idx), &large_value_of(idx, 3)).unwrap();
+    }
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    check_storage(&storage, &expected_of(num_keys, 3), num_keys);
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::merge_operator::MergeOperator;
+use crate::table::{CompressionType, SsTableIterator};
+use crate::write_batch::WriteBatch;
+
+use super::harness::{check_storage, compact_until_done, key_of};
+
+/// Adds the operands to the value, as little-endian `u64`s.
+struct AddOperator;
+
+impl MergeOperator for AddOperator {
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operand: &[u8]) -> Bytes {
+        let existing = existing.map_or(0, decode);
+        Bytes::copy_from_slice(&(existing + decode(operand)).to_le_bytes())
+    }
+}
+
+fn decode(value: &[u8]) -> u64 {
+    if value.is_empty() {
+        return 0;
+    }
+    u64::from_le_bytes(value.try_into().unwrap())
+}
+
+fn num(x: u64) -> Vec<u8> {
+    x.to_le_bytes().to_vec()
+}
+
+fn options(max_levels: usize) -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 256,
+        write_buffer_size: 1 << 20,
+        bloom_bits_per_key: 10,
+        compression_per_level: vec![CompressionType::None],
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels,
+            base_level_size: 1024,
+            level_size_multiplier: 2,
+        },
+        merge_operator: Some(Arc::new(AddOperator)),
+        ..Default::default()
+    }
+}
+
+/// The number of entries in all SSTs.
+fn count_in_ssts(storage: &LsmStorage) -> usize {
+    let snapshot = storage.core.inner.read().clone();
+    let mut entries = 0;
+    for sst in snapshot
+        .l0_sstables
+        .iter()
+        .chain(snapshot.levels.iter().flatten())
+    {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            entries += 1;
+            iter.next().unwrap();
+        }
+    }
+    entries
+}
+
+#[test]
+fn test_merge() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options(3)).unwrap();
+    storage.merge(&key_of(0), &num(1)).unwrap();
+    storage.merge(&key_of(0), &num(2)).unwrap();
+    storage.put(&key_of(1), &num(10)).unwrap();
+    storage.merge(&key_of(1), &num(5)).unwrap();
+    storage.merge(&key_of(2), &num(7)).unwrap();
+    storage.put(&key_of(2), &num(1)).unwrap();
+    storage.put(&key_of(3), &num(4)).unwrap();
+    storage.delete(&key_of(3)).unwrap();
+    storage.merge(&key_of(3), &num(3)).unwrap();
+    let expected = BTreeMap::from([
+        (key_of(0), num(3)),
+        (key_of(1), num(15)),
+        (key_of(2), num(1)),
+        (key_of(3), num(3)),
+    ]);
+    check_storage(&storage, &expected, 5);
+}
+
+#[test]
+fn test_merge_across_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options(3)).unwrap();
+    let num_keys = 100;
+    let mut expected = BTreeMap::new();
+    for round in 0..8 {
+        for idx in (round..num_keys).step_by(2) {
+            if idx % 7 == round {
+                storage.put(&key_of(idx), &num(100)).unwrap();
+                expected.insert(key_of(idx), 100);
+            } else {
+                storage.merge(&key_of(idx), &num(idx as u64)).unwrap();
+                *expected.entry(key_of(idx)).or_default() += idx as u64;
+            }
+        }
+        if round == 3 {
+            storage.delete(&key_of(9)).unwrap();
+            expected.remove(&key_of(9));
+            storage.delete_range(&key_of(20), &key_of(30)).unwrap();
+            for idx in 20..30 {
+                expected.remove(&key_of(idx));
+            }
+        }
+        storage.sync().unwrap();
+        let expected = expected
+            .iter()
+            .map(|(key, value)| (key.clone(), num(*value)))
+            .collect();
+        check_storage(&storage, &expected, num_keys);
+    }
+    compact_until_done(&storage);
+    let expected = expected
+        .into_iter()
+        .map(|(key, value)| (key, num(value)))
+        .collect();
+    check_storage(&storage, &expected, num_keys);
+}
+
+#[test]
+fn test_merge_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options(3)).unwrap();
+    storage.merge(&key_of(0), &num(1)).unwrap();
+    let snapshot = storage.snapshot();
+    storage.merge(&key_of(0), &num(2)).unwrap();
+    storage.sync().unwrap();
+    storage.merge(&key_of(0), &num(4)).unwrap();
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+    assert_eq!(snapshot.get(&key_of(0)).unwrap(), Some(Bytes::from(num(1))));
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(Bytes::from(num(7))));
+}
+
+#[test]
+fn test_flush_and_compaction_fold_operands() {
+    let dir = tempdir().unwrap();
+    // All SSTs are compacted into L1, which is the bottom level.
+    let storage = LsmStorage::open_with_options(&dir, options(1)).unwrap();
+    for idx in 0..10 {
+        for _ in 0..5 {
+            storage.merge(&key_of(idx), &num(1)).unwrap();
+        }
+    }
+    // The operands of a key in the memtable are folded into one when it is flushed.
+    storage.sync().unwrap();
+    assert_eq!(count_in_ssts(&storage), 10);
+    for idx in 0..10 {
+        storage.merge(&key_of(idx), &num(1)).unwrap();
+    }
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+    // Compacting into the bottom level merges them into values.
+    assert_eq!(count_in_ssts(&storage), 10);
+    let snapshot = storage.core.inner.read().clone();
+    assert!(snapshot.l0_sstables.is_empty());
+    for sst in snapshot.levels.iter().flatten() {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            assert_eq!(iter.value(), num(6));
+            iter.next().unwrap();
+        }
+    }
+    let expected = (0..10).map(|idx| (key_of(idx), num(6))).collect();
+    check_storage(&storage, &expected, 10);
+}
+
+#[test]
+fn test_merge_write_batch() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options(3)).unwrap();
+    storage.put(&key_of(0), &num(1)).unwrap();
+    storage.put(&key_of(1), &num(1)).unwrap();
+    let mut batch = WriteBatch::new();
+    batch
+        .merge(&key_of(0), &num(2))
+        .merge(&key_of(0), &num(3))
+        .put(&key_of(1), &num(10))
+        .merge(&key_of(1), &num(5))
+        .delete(&key_of(2))
+        .merge(&key_of(2), &num(4));
+    storage.write(&batch).unwrap();
+    let expected = BTreeMap::from([
+        (key_of(0), num(6)),
+        (key_of(1), num(15)),
+        (key_of(2), num(4)),
+    ]);
+    check_storage(&storage, &expected, 3);
+}
+
+#[test]
+fn test_merge_recover() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open_with_options(&dir, options(3)).unwrap();
+        storage.merge(&key_of(0), &num(1)).unwrap();
+        storage.sync().unwrap();
+        storage.merge(&key_of(0), &num(2)).unwrap();
+        storage.merge(&key_of(1), &num(3)).unwrap();
+    }
+    let storage = LsmStorage::open_with_options(&dir, options(3)).unwrap();
+    let expected = BTreeMap::from([(key_of(0), num(3)), (key_of(1), num(3))]);
+    check_storage(&storage, &expected, 2);
+}
+
+#[test]
+fn test_merge_without_operator() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open_with_options(&dir, options(3)).unwrap();
+        storage.put(&key_of(0), &num(1)).unwrap();
+        storage.merge(&key_of(1), &num(1)).unwrap();
+    }
+    let options = LsmStorageOptions {
+        merge_operator: None,
+        ..options(3)
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(Bytes::from(num(1))));
+    assert!(storage.get(&key_of(1)).is_err());
+    assert!(storage.merge(&key_of(0), &num(1)).is_err());
+}
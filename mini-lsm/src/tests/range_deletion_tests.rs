@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::table::{CompressionType, SsTableIterator};
+use crate::transaction::ConflictError;
+use crate::write_batch::WriteBatch;
+
+use super::harness::{check_iter, check_storage, compact_until_done, key_of, value_of};
+
+#[test]
+fn test_delete_range() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    let snapshot = storage.snapshot();
+    storage.delete_range(&key_of(2), &key_of(5)).unwrap();
+    storage.put(&key_of(3), &value_of(3, 1)).unwrap();
+
+    let mut expected = (0..10)
+        .map(|idx| (key_of(idx), value_of(idx, 0)))
+        .collect::<BTreeMap<_, _>>();
+    expected.remove(&key_of(2));
+    expected.remove(&key_of(4));
+    expected.insert(key_of(3), value_of(3, 1));
+    for _ in 0..2 {
+        check_storage(&storage, &expected, 10);
+        // A scan starting in the deleted range.
+        check_iter(
+            storage
+                .scan(Bound::Included(&key_of(2)), Bound::Included(&key_of(5)))
+                .unwrap(),
+            vec![(key_of(3), value_of(3, 1)), (key_of(5), value_of(5, 0))],
+        );
+        // The snapshot was taken before the range deletion.
+        assert_eq!(
+            &snapshot.get(&key_of(2)).unwrap().unwrap()[..],
+            value_of(2, 0)
+        );
+        let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        let mut count = 0;
+        while iter.is_valid() {
+            count += 1;
+            iter.next().unwrap();
+        }
+        assert_eq!(count, 10);
+        // The range tombstone is flushed to an SST.
+        storage.sync().unwrap();
+    }
+
+    // The range tombstone is recovered from the SST.
+    drop(snapshot);
+    drop(storage);
+    let storage = LsmStorage::open(&dir).unwrap();
+    check_storage(&storage, &expected, 10);
+}
+
+#[test]
+fn test_delete_range_rejects_empty_range() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(&key_of(1), &value_of(1, 0)).unwrap();
+    assert!(storage.delete_range(&key_of(1), &key_of(1)).is_err());
+    assert!(storage.delete_range(&key_of(2), &key_of(0)).is_err());
+    let expected = BTreeMap::from([(key_of(1), value_of(1, 0))]);
+    check_storage(&storage, &expected, 3);
+}
+
+#[test]
+fn test_delete_range_recover_from_wal() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.sync().unwrap();
+    storage.delete_range(&key_of(0), &key_of(8)).unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir).unwrap();
+    let expected = (8..10)
+        .map(|idx| (key_of(idx), value_of(idx, 0)))
+        .collect::<BTreeMap<_, _>>();
+    check_storage(&storage, &expected, 10);
+}
+
+#[test]
+fn test_delete_range_in_write_batch() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    for idx in 0..5 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    // The range deletion only deletes the writes before it in the batch.
+    let mut batch = WriteBatch::new();
+    batch
+        .put(&key_of(1), &value_of(1, 1))
+        .delete_range(&key_of(1), &key_of(4))
+        .put(&key_of(2), &value_of(2, 1));
+    storage.write(&batch).unwrap();
+
+    let expected = [(0, 0), (2, 1), (4, 0)]
+        .into_iter()
+        .map(|(idx, round)| (key_of(idx), value_of(idx, round)))
+        .collect::<BTreeMap<_, _>>();
+    check_storage(&storage, &expected, 5);
+}
+
+#[test]
+fn test_delete_range_conflicts_with_txn() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    let txn1 = storage.new_txn();
+    let txn2 = storage.new_txn();
+    txn1.get(&key_of(3)).unwrap();
+    txn1.put(&key_of(8), b"1");
+    txn2.get(&key_of(6)).unwrap();
+    txn2.put(&key_of(9), b"2");
+    storage.delete_range(&key_of(2), &key_of(5)).unwrap();
+    let err = txn1.commit().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ConflictError>().unwrap().key,
+        Bytes::from(key_of(3))
+    );
+    txn2.commit().unwrap();
+}
+
+fn compaction_options(max_levels: usize) -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 256,
+        write_buffer_size: 1 << 20,
+        bloom_bits_per_key: 10,
+        compression_per_level: vec![CompressionType::None],
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels,
+            base_level_size: 1024,
+            level_size_multiplier: 2,
+        },
+        ..Default::default()
+    }
+}
+
+/// The number of entries and of range tombstones in all SSTs.
+fn count_in_ssts(storage: &LsmStorage) -> (usize, usize) {
+    let snapshot = storage.core.inner.read().clone();
+    let (mut entries, mut range_tombstones) = (0, 0);
+    for sst in snapshot
+        .l0_sstables
+        .iter()
+        .chain(snapshot.levels.iter().flatten())
+    {
+        range_tombstones += sst.range_tombstones().len();
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            entries += 1;
+            iter.next().unwrap();
+        }
+    }
+    (entries, range_tombstones)
+}
+
+#[test]
+fn test_compaction_drops_range_deleted_data() {
+    let dir = tempdir().unwrap();
+    // All SSTs are compacted into L1, which is the bottom level.
+    let storage = LsmStorage::open_with_options(&dir, compaction_options(1)).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    storage.delete_range(&key_of(10), &key_of(60)).unwrap();
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+
+    // The snapshot still needs the deleted data, and the tombstone to hide it from later reads.
+    // The output is split as usual, and each SST the deleted range spans gets its part of the
+    // tombstone.
+    let (entries, range_tombstones) = count_in_ssts(&storage);
+    assert_eq!(entries, 100);
+    assert!(range_tombstones > 1);
+    {
+        let snapshot = storage.core.inner.read().clone();
+        for sst in &snapshot.levels[0] {
+            let in_deleted_range =
+                sst.first_key()[..] < key_of(60)[..] && key_of(10)[..] <= sst.last_key()[..];
+            assert_eq!(sst.range_tombstones().len(), in_deleted_range as usize);
+        }
+        for ssts in snapshot.levels[0].windows(2) {
+            assert!(ssts[0].last_key() <= ssts[1].first_key());
+        }
+    }
+    let expected = (0..10)
+        .chain(60..100)
+        .map(|idx| (key_of(idx), value_of(idx, 0)))
+        .collect::<BTreeMap<_, _>>();
+    check_storage(&storage, &expected, 100);
+
+    // Once the snapshot is gone, the next compaction of the SST drops the deleted data, and the
+    // range tombstone along with it.
+    drop(snapshot);
+    storage.put(&key_of(0), &value_of(0, 1)).unwrap();
+    storage.sync().unwrap();
+    storage.put(&key_of(99), &value_of(99, 1)).unwrap();
+    storage.sync().unwrap();
+    compact_until_done(&storage);
+    assert_eq!(count_in_ssts(&storage), (50, 0));
+}
+
+#[test]
+fn test_range_deletion_across_levels() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, compaction_options(3)).unwrap();
+    let num_keys = 200;
+    let mut expected = BTreeMap::new();
+    for round in 0..6 {
+        for idx in (round..num_keys).step_by(3) {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+            expected.insert(key_of(idx), value_of(idx, round));
+        }
+        storage.sync().unwrap();
+        // Range tombstones in different places, so that some of them only cover data in lower
+        // levels, and are compacted into a level that is not the bottom.
+        let (lower, upper) = (round * 30 + 5, round * 30 + 25);
+        storage
+            .delete_range(&key_of(lower), &key_of(upper))
+            .unwrap();
+        for idx in lower..upper {
+            expected.remove(&key_of(idx));
+        }
+        storage.sync().unwrap();
+        compact_until_done(&storage);
+        check_storage(&storage, &expected, num_keys);
+    }
+
+    {
+        let snapshot = storage.core.inner.read().clone();
+        for level in &snapshot.levels {
+            // An SST may end at the end of a range tombstone, which is where the next one starts.
+            for ssts in level.windows(2) {
+                assert!(ssts[0].last_key() <= ssts[1].first_key());
+            }
+        }
+    }
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, compaction_options(3)).unwrap();
+    check_storage(&storage, &expected, num_keys);
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::table::CompressionType;
+
+use super::harness::{check_iter, compact_until_done, key_of, value_of};
+
+/// Check `scan_rev` of `storage` with different bounds against the expected contents.
+fn check_scan_rev(storage: &LsmStorage, expected: &BTreeMap<Vec<u8>, Vec<u8>>, num_keys: usize) {
+    let rev = |lower: Bound<&Vec<u8>>, upper: Bound<&Vec<u8>>| {
+        expected
+            .range::<Vec<u8>, _>((lower, upper))
+            .rev()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>()
+    };
+    check_iter(
+        storage
+            .scan_rev(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        rev(Bound::Unbounded, Bound::Unbounded),
+    );
+    for (lower, upper) in [(0, num_keys / 2), (num_keys / 3, num_keys), (7, 8)] {
+        let (lower, upper) = (key_of(lower), key_of(upper));
+        check_iter(
+            storage
+                .scan_rev(Bound::Included(&lower), Bound::Included(&upper))
+                .unwrap(),
+            rev(Bound::Included(&lower), Bound::Included(&upper)),
+        );
+        check_iter(
+            storage
+                .scan_rev(Bound::Excluded(&lower), Bound::Excluded(&upper))
+                .unwrap(),
+            rev(Bound::Excluded(&lower), Bound::Excluded(&upper)),
+        );
+    }
+}
+
+#[test]
+fn test_scan_rev_memtable() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.put(b"3", b"23333").unwrap();
+    storage.put(b"2", b"4").unwrap();
+    storage.delete(b"3").unwrap();
+    check_iter(
+        storage
+            .scan_rev(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![
+            (b"2".to_vec(), b"4".to_vec()),
+            (b"1".to_vec(), b"233".to_vec()),
+        ],
+    );
+    check_iter(
+        storage
+            .scan_rev(Bound::Excluded(b"1"), Bound::Included(b"3"))
+            .unwrap(),
+        vec![(b"2".to_vec(), b"4".to_vec())],
+    );
+}
+
+#[test]
+fn test_scan_rev_across_levels() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 256,
+        write_buffer_size: 1 << 20,
+        bloom_bits_per_key: 10,
+        compression_per_level: vec![CompressionType::None],
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size: 1024,
+            level_size_multiplier: 2,
+        },
+        ..Default::default()
+    };
+    let storage = LsmStorage::open_with_options(&dir, options).unwrap();
+    let num_keys = 100;
+    let mut expected = BTreeMap::new();
+    for round in 0..5 {
+        for idx in (round..num_keys).step_by(4) {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+            expected.insert(key_of(idx), value_of(idx, round));
+        }
+        for idx in (round..num_keys).step_by(9) {
+            storage.delete(&key_of(idx)).unwrap();
+            expected.remove(&key_of(idx));
+        }
+        storage.sync().unwrap();
+        compact_until_done(&storage);
+    }
+    // Some of the data is in the memtable, and some is deleted by a range tombstone.
+    for idx in (0..num_keys).step_by(5) {
+        storage.put(&key_of(idx), &value_of(idx, 5)).unwrap();
+        expected.insert(key_of(idx), value_of(idx, 5));
+    }
+    storage.delete_range(&key_of(40), &key_of(60)).unwrap();
+    for idx in 40..60 {
+        expected.remove(&key_of(idx));
+    }
+    storage.put(&key_of(50), &value_of(50, 6)).unwrap();
+    expected.insert(key_of(50), value_of(50, 6));
+    check_scan_rev(&storage, &expected, num_keys);
+
+    // The range tombstone and the latest writes are flushed to L0.
+    storage.sync().unwrap();
+    check_scan_rev(&storage, &expected, num_keys);
+}
+
+#[test]
+fn test_scan_rev_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    let snapshot = storage.snapshot();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage.delete(&key_of(3)).unwrap();
+    storage.sync().unwrap();
+
+    // The snapshot sees the earlier version of each key, even though the later one comes after it
+    // going backward.
+    check_iter(
+        snapshot
+            .scan_rev(Bound::Unbounded, Bound::Excluded(&key_of(5)))
+            .unwrap(),
+        (0..5).rev().map(|idx| (key_of(idx), value_of(idx, 0))),
+    );
+    check_iter(
+        storage
+            .scan_rev(Bound::Unbounded, Bound::Excluded(&key_of(5)))
+            .unwrap(),
+        [4, 2, 1, 0]
+            .into_iter()
+            .map(|idx| (key_of(idx), value_of(idx, 1))),
+    );
+}
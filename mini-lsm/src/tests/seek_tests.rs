@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::table::CompressionType;
+
+use super::harness::{compact_until_done, key_of, value_of};
+
+/// A storage with data in the memtable, L0 and the lower levels, and the expected contents.
+fn generate_storage(dir: &tempfile::TempDir) -> (LsmStorage, BTreeMap<Vec<u8>, Vec<u8>>) {
+    let options = LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 256,
+        write_buffer_size: 1 << 20,
+        bloom_bits_per_key: 10,
+        compression_per_level: vec![CompressionType::None],
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size: 1024,
+            level_size_multiplier: 2,
+        },
+        ..Default::default()
+    };
+    let storage = LsmStorage::open_with_options(dir, options).unwrap();
+    let mut expected = BTreeMap::new();
+    for round in 0..5 {
+        for idx in (round..100).step_by(3) {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+            expected.insert(key_of(idx), value_of(idx, round));
+        }
+        for idx in (round..100).step_by(7) {
+            storage.delete(&key_of(idx)).unwrap();
+            expected.remove(&key_of(idx));
+        }
+        storage.sync().unwrap();
+        if round < 4 {
+            compact_until_done(&storage);
+        }
+    }
+    for idx in (0..100).step_by(10) {
+        storage.put(&key_of(idx), &value_of(idx, 5)).unwrap();
+        expected.insert(key_of(idx), value_of(idx, 5));
+    }
+    storage.delete_range(&key_of(30), &key_of(40)).unwrap();
+    for idx in 30..40 {
+        expected.remove(&key_of(idx));
+    }
+    (storage, expected)
+}
+
+#[test]
+fn test_seek() {
+    let dir = tempdir().unwrap();
+    let (storage, expected) = generate_storage(&dir);
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    // Skip-scan: read two keys from every 10 keys, and seek backward as well.
+    for start in (0..110).step_by(10).chain([55, 5, 0]) {
+        iter.seek(&key_of(start)).unwrap();
+        let mut actual = Vec::new();
+        while iter.is_valid() && actual.len() < 2 {
+            actual.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next().unwrap();
+        }
+        let expected = expected
+            .range(key_of(start)..)
+            .take(2)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected, "seek to {}", start);
+    }
+}
+
+#[test]
+fn test_seek_keeps_bound_and_snapshot() {
+    let dir = tempdir().unwrap();
+    let (storage, expected) = generate_storage(&dir);
+    let mut iter = storage
+        .scan(Bound::Included(&key_of(20)), Bound::Excluded(&key_of(60)))
+        .unwrap();
+    // The writes after the iterator is created are not visible after seeking, even once they are
+    // flushed.
+    for idx in 0..100 {
+        storage.put(&key_of(idx), b"new").unwrap();
+    }
+    storage.sync().unwrap();
+
+    iter.seek(&key_of(50)).unwrap();
+    let mut actual = Vec::new();
+    while iter.is_valid() {
+        actual.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    let expected = expected
+        .range(key_of(50)..key_of(60))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(actual, expected);
+    iter.seek(&key_of(60)).unwrap();
+    assert!(!iter.is_valid());
+
+    let mut iter = storage
+        .scan_rev(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert!(iter.seek(&key_of(0)).is_err());
+}
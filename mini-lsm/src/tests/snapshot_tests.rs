@@ -0,0 +1,190 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::compact::LeveledCompactionOptions;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::table::{CompressionType, SsTableIterator};
+use crate::write_batch::WriteBatch;
+
+use super::harness::{check_iter, key_of, value_of};
+
+#[test]
+fn test_snapshot_get_and_scan() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    let snapshot = storage.snapshot();
+    storage.put(b"1", b"a").unwrap();
+    storage.delete(b"2").unwrap();
+    storage.put(b"3", b"b").unwrap();
+
+    for _ in 0..2 {
+        assert_eq!(&snapshot.get(b"1").unwrap().unwrap()[..], b"233");
+        assert_eq!(&snapshot.get(b"2").unwrap().unwrap()[..], b"2333");
+        assert!(snapshot.get(b"3").unwrap().is_none());
+        check_iter(
+            snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![(&b"1"[..], &b"233"[..]), (b"2", b"2333")],
+        );
+        check_iter(
+            snapshot
+                .scan(Bound::Excluded(b"1"), Bound::Unbounded)
+                .unwrap(),
+            vec![(b"2", b"2333")],
+        );
+        check_iter(
+            storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![(&b"1"[..], &b"a"[..]), (b"3", b"b")],
+        );
+        // The versions are kept when they are flushed to SSTs.
+        storage.sync().unwrap();
+    }
+}
+
+#[test]
+fn test_snapshot_sees_whole_batches() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(LsmStorage::open(&dir).unwrap());
+    storage.put(b"x", b"0").unwrap();
+    storage.put(b"y", b"0").unwrap();
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || {
+            for i in 1..=1000 {
+                let mut batch = WriteBatch::new();
+                batch
+                    .put(b"x", i.to_string().as_bytes())
+                    .put(b"y", (-i).to_string().as_bytes());
+                storage.write(&batch).unwrap();
+            }
+        })
+    };
+    let parse = |value: &[u8]| std::str::from_utf8(value).unwrap().parse::<i64>().unwrap();
+    for _ in 0..200 {
+        // A scan reads lazily while the writer goes on, but sees no partial batch.
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        let x = parse(iter.value());
+        std::thread::yield_now();
+        iter.next().unwrap();
+        assert_eq!(x + parse(iter.value()), 0);
+
+        let snapshot = storage.snapshot();
+        let x = parse(&snapshot.get(b"x").unwrap().unwrap());
+        std::thread::yield_now();
+        assert_eq!(x + parse(&snapshot.get(b"y").unwrap().unwrap()), 0);
+    }
+    writer.join().unwrap();
+}
+
+#[test]
+fn test_snapshot_after_recovery() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.sync().unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    drop(storage);
+
+    // Sequence numbers go on from the ones in the SSTs and WALs.
+    let storage = LsmStorage::open(&dir).unwrap();
+    let snapshot = storage.snapshot();
+    storage.put(b"1", b"a").unwrap();
+    storage.put(b"2", b"b").unwrap();
+    check_iter(
+        snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![(&b"1"[..], &b"233"[..]), (b"2", b"2333")],
+    );
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![(&b"1"[..], &b"a"[..]), (b"2", b"b")],
+    );
+}
+
+fn compaction_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 64,
+        target_sst_size: 256,
+        write_buffer_size: 1 << 20,
+        bloom_bits_per_key: 10,
+        compression_per_level: vec![CompressionType::None],
+        // All SSTs are compacted into L1, which is the bottom level.
+        compaction_options: LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 1,
+            base_level_size: 1 << 20,
+            level_size_multiplier: 2,
+        },
+        ..Default::default()
+    }
+}
+
+/// The number of entries in all SSTs of L1, counting every version.
+fn num_of_versions_in_l1(storage: &LsmStorage) -> usize {
+    let snapshot = storage.core.inner.read().clone();
+    let mut count = 0;
+    for sst in &snapshot.levels[0] {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            count += 1;
+            iter.next().unwrap();
+        }
+    }
+    count
+}
+
+#[test]
+fn test_compaction_keeps_versions_of_snapshots() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, compaction_options()).unwrap();
+    let num_keys = 100;
+    let write_round = |round: usize| {
+        for idx in 0..num_keys {
+            if idx % 3 == 0 && round > 0 {
+                storage.delete(&key_of(idx)).unwrap();
+            } else {
+                storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+            }
+        }
+        storage.sync().unwrap();
+    };
+
+    write_round(0);
+    let snapshot = storage.snapshot();
+    write_round(1);
+    while storage.core.trigger_compaction().unwrap() {}
+    assert!(storage.core.inner.read().l0_sstables.is_empty());
+    // Both rounds are kept, including the tombstones shadowing round 0.
+    assert_eq!(num_of_versions_in_l1(&storage), num_keys * 2);
+    for idx in 0..num_keys {
+        assert_eq!(
+            snapshot.get(&key_of(idx)).unwrap().unwrap(),
+            value_of(idx, 0)
+        );
+        let value = storage.get(&key_of(idx)).unwrap();
+        if idx % 3 == 0 {
+            assert!(value.is_none());
+        } else {
+            assert_eq!(value.unwrap(), value_of(idx, 1));
+        }
+    }
+
+    // Without snapshots, only the latest version of each key is kept, and tombstones are dropped
+    // at the bottom level.
+    drop(snapshot);
+    write_round(2);
+    write_round(3);
+    while storage.core.trigger_compaction().unwrap() {}
+    let num_live_keys = (0..num_keys).filter(|idx| idx % 3 != 0).count();
+    assert_eq!(num_of_versions_in_l1(&storage), num_live_keys);
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for idx in (0..num_keys).filter(|idx| idx % 3 != 0) {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, 3));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
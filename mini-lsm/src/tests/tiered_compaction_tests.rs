@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::harness::{check_storage, compact_until_done, key_of, value_of};
+use crate::compact::{TieredCompactionController, TieredCompactionOptions};
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+fn options(max_levels: usize) -> LsmStorageOptions {
+    LsmStorageOptions {
+        block_size: 256,
+        target_sst_size: 1024,
+        compaction_strategy: Some(Arc::new(TieredCompactionController::new(
+            TieredCompactionOptions {
+                num_sorted_runs_trigger: 3,
+                max_levels,
+                ..Default::default()
+            },
+        ))),
+        ..Default::default()
+    }
+}
+
+/// The number of sorted runs: each L0 SST, and each level that is not empty.
+fn num_sorted_runs(storage: &LsmStorage) -> usize {
+    let snapshot = storage.core.inner.read().clone();
+    let levels = snapshot.levels().iter().filter(|x| !x.is_empty()).count();
+    snapshot.l0_sstables().len() + levels
+}
+
+#[test]
+fn test_tiered_compaction() {
+    for max_levels in [1, 3] {
+        let dir = tempdir().unwrap();
+        let storage = LsmStorage::open_with_options(&dir, options(max_levels)).unwrap();
+        let num_keys = 200;
+        let mut expected = BTreeMap::new();
+        for round in 0..12 {
+            // Flushes of different sizes, overwriting and deleting some of the earlier keys.
+            for idx in (round % 3..num_keys).step_by(round % 4 + 1) {
+                storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+                expected.insert(key_of(idx), value_of(idx, round));
+            }
+            for idx in (round..num_keys).step_by(17) {
+                storage.delete(&key_of(idx)).unwrap();
+                expected.remove(&key_of(idx));
+            }
+            storage.sync().unwrap();
+            compact_until_done(&storage);
+            assert!(num_sorted_runs(&storage) < 3);
+            check_storage(&storage, &expected, num_keys);
+        }
+
+        drop(storage);
+        let storage = LsmStorage::open_with_options(&dir, options(max_levels)).unwrap();
+        check_storage(&storage, &expected, num_keys);
+    }
+}
+
+#[test]
+fn test_tiered_compaction_space_amplification() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options(3)).unwrap();
+    // Overwriting the same keys compacts all sorted runs into the last level, where the
+    // overwritten versions are dropped.
+    for round in 0..10 {
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+        }
+        storage.sync().unwrap();
+        compact_until_done(&storage);
+    }
+    let snapshot = storage.core.inner.read().clone();
+    let size = snapshot
+        .levels()
+        .iter()
+        .flatten()
+        .chain(snapshot.l0_sstables())
+        .map(|x| x.table_size())
+        .sum::<u64>();
+    let last_level_size = snapshot.levels()[2]
+        .iter()
+        .map(|x| x.table_size())
+        .sum::<u64>();
+    assert!(size <= last_level_size * 3, "{} {}", size, last_level_size);
+    let expected = (0..100)
+        .map(|idx| (key_of(idx), value_of(idx, 9)))
+        .collect();
+    check_storage(&storage, &expected, 100);
+}
+
+#[test]
+fn test_open_rejects_zero_tiered_levels() {
+    let dir = tempdir().unwrap();
+    assert!(LsmStorage::open_with_options(&dir, options(0)).is_err());
+}
@@ -0,0 +1,255 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::LsmStorage;
+use crate::transaction::{ConflictError, IsolationLevel, ReadSet};
+
+use super::harness::check_iter;
+
+fn conflict_of(err: anyhow::Error) -> Bytes {
+    err.downcast_ref::<ConflictError>().unwrap().key.clone()
+}
+
+#[test]
+fn test_txn_reads_own_writes() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.put(b"3", b"23333").unwrap();
+    storage.sync().unwrap();
+
+    let txn = storage.new_txn();
+    txn.put(b"2", b"a");
+    txn.delete(b"3");
+    txn.put(b"4", b"b");
+    // Writes after the transaction started are not seen.
+    storage.put(b"5", b"c").unwrap();
+    assert_eq!(&txn.get(b"1").unwrap().unwrap()[..], b"233");
+    assert_eq!(&txn.get(b"2").unwrap().unwrap()[..], b"a");
+    assert!(txn.get(b"3").unwrap().is_none());
+    assert_eq!(&txn.get(b"4").unwrap().unwrap()[..], b"b");
+    check_iter(
+        txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![(&b"1"[..], &b"233"[..]), (b"2", b"a"), (b"4", b"b")],
+    );
+    check_iter(
+        txn.scan(Bound::Excluded(b"1"), Bound::Included(b"3"))
+            .unwrap(),
+        vec![(b"2", b"a")],
+    );
+
+    // Nothing is written until the transaction commits.
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+    txn.commit().unwrap();
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"a");
+    assert!(storage.get(b"3").unwrap().is_none());
+    assert_eq!(&storage.get(b"4").unwrap().unwrap()[..], b"b");
+    assert_eq!(&storage.get(b"5").unwrap().unwrap()[..], b"c");
+}
+
+#[test]
+fn test_txn_rollback() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    let txn = storage.new_txn();
+    txn.put(b"1", b"a");
+    txn.put(b"2", b"b");
+    drop(txn);
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert!(storage.get(b"2").unwrap().is_none());
+}
+
+#[test]
+fn test_txn_write_conflict() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    let txn1 = storage.new_txn();
+    let txn2 = storage.new_txn();
+    txn1.put(b"1", b"a");
+    txn2.put(b"1", b"b");
+    txn2.put(b"2", b"b");
+    txn1.commit().unwrap();
+    assert_eq!(conflict_of(txn2.commit().err().unwrap()), "1");
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"a");
+    assert!(storage.get(b"2").unwrap().is_none());
+}
+
+#[test]
+fn test_txn_read_conflict() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+
+    // A write outside of transactions conflicts too.
+    let txn = storage.new_txn();
+    assert_eq!(&txn.get(b"1").unwrap().unwrap()[..], b"233");
+    txn.put(b"3", b"a");
+    storage.delete(b"1").unwrap();
+    assert_eq!(conflict_of(txn.commit().err().unwrap()), "1");
+
+    // So does a write to a key that was missing when it was read.
+    let txn = storage.new_txn();
+    assert!(txn.get(b"4").unwrap().is_none());
+    txn.put(b"3", b"a");
+    storage.put(b"4", b"b").unwrap();
+    assert_eq!(conflict_of(txn.commit().err().unwrap()), "4");
+
+    // Keys read through scan are in the read set.
+    let txn = storage.new_txn();
+    check_iter(
+        txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![(&b"2"[..], &b"2333"[..]), (b"4", b"b")],
+    );
+    txn.put(b"3", b"a");
+    storage.put(b"2", b"c").unwrap();
+    assert_eq!(conflict_of(txn.commit().err().unwrap()), "2");
+    assert!(storage.get(b"3").unwrap().is_none());
+}
+
+#[test]
+fn test_txn_no_conflict() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    let txn1 = storage.new_txn();
+    let txn2 = storage.new_txn();
+    assert_eq!(&txn1.get(b"1").unwrap().unwrap()[..], b"233");
+    txn1.put(b"2", b"a");
+    txn2.put(b"3", b"b");
+    // Writes to keys the transactions did not touch do not conflict.
+    storage.put(b"4", b"c").unwrap();
+    txn2.commit().unwrap();
+    txn1.commit().unwrap();
+    // A transaction started after a commit does not conflict with it.
+    let txn3 = storage.new_txn();
+    assert_eq!(&txn3.get(b"2").unwrap().unwrap()[..], b"a");
+    txn3.put(b"3", b"d");
+    txn3.commit().unwrap();
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"d");
+}
+
+#[test]
+fn test_txn_read_modify_write() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(LsmStorage::open(&dir).unwrap());
+    storage.put(b"counter", b"0").unwrap();
+    let threads = (0..4)
+        .map(|_| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    loop {
+                        let txn = storage.new_txn();
+                        let value = txn.get(b"counter").unwrap().unwrap();
+                        let value = std::str::from_utf8(&value).unwrap().parse::<u64>().unwrap();
+                        txn.put(b"counter", (value + 1).to_string().as_bytes());
+                        match txn.commit() {
+                            Ok(()) => break,
+                            Err(e) => assert!(e.is::<ConflictError>()),
+                        }
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(&storage.get(b"counter").unwrap().unwrap()[..], b"200");
+}
+
+/// Each transaction scans `[a, z]`, and adds a key to it if it is empty.
+fn insert_if_range_empty(
+    storage: &LsmStorage,
+    isolation_level: IsolationLevel,
+) -> anyhow::Result<()> {
+    let txn1 = storage.new_txn_with_isolation(isolation_level);
+    let txn2 = storage.new_txn_with_isolation(isolation_level);
+    for (txn, key) in [(&txn1, b"b"), (&txn2, b"c")] {
+        let iter = txn
+            .scan(Bound::Included(b"a"), Bound::Included(b"z"))
+            .unwrap();
+        assert!(!iter.is_valid());
+        txn.put(key, b"1");
+    }
+    txn1.commit().unwrap();
+    txn2.commit()
+}
+
+#[test]
+fn test_txn_phantom() {
+    // Under repeatable read, both transactions commit, as neither read a key the other wrote.
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    insert_if_range_empty(&storage, IsolationLevel::RepeatableRead).unwrap();
+    check_iter(
+        storage
+            .new_txn()
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![(b"b", b"1"), (b"c", b"1")],
+    );
+
+    // Under serializable, the second one fails as the first wrote in the range it scanned.
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    let err = insert_if_range_empty(&storage, IsolationLevel::Serializable)
+        .err()
+        .unwrap();
+    assert_eq!(conflict_of(err), "b");
+    assert!(storage.get(b"c").unwrap().is_none());
+}
+
+#[test]
+fn test_txn_serializable_read_set() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"5", b"2333").unwrap();
+
+    let txn = storage.new_txn_with_isolation(IsolationLevel::Serializable);
+    assert_eq!(txn.isolation_level(), IsolationLevel::Serializable);
+    txn.get(b"1").unwrap();
+    check_iter(
+        txn.scan(Bound::Excluded(b"2"), Bound::Excluded(b"6"))
+            .unwrap(),
+        vec![(b"5", b"2333")],
+    );
+    txn.put(b"7", b"a");
+    let read_set = txn.read_set();
+    assert_eq!(
+        read_set,
+        ReadSet {
+            keys: [Bytes::from("1")].into_iter().collect(),
+            ranges: vec![(
+                Bound::Excluded(Bytes::from("2")),
+                Bound::Excluded(Bytes::from("6"))
+            )],
+        }
+    );
+    for (key, contained) in [
+        ("1", true),
+        ("2", false),
+        ("3", true),
+        ("6", false),
+        ("7", false),
+    ] {
+        assert_eq!(read_set.contains(key.as_bytes()), contained, "key {}", key);
+    }
+
+    // Writes outside of the read set do not conflict.
+    storage.put(b"2", b"b").unwrap();
+    storage.put(b"6", b"b").unwrap();
+    let txn2 = storage.new_txn_with_isolation(IsolationLevel::Serializable);
+    txn2.get(b"8").unwrap();
+    storage.put(b"8", b"b").unwrap();
+    txn.commit().unwrap();
+    assert_eq!(conflict_of(txn2.commit().err().unwrap()), "8");
+}
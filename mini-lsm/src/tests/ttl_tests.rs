@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use super::harness::{check_storage, count_in_ssts, key_of, value_of, AppendOperator};
+use crate::iterators::ValueType;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::write_batch::WriteBatch;
+
+const SHORT_TTL: Duration = Duration::from_millis(500);
+const LONG_TTL: Duration = Duration::from_secs(3600);
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        merge_operator: Some(Arc::new(AppendOperator)),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_put_with_ttl() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    let mut expected = BTreeMap::new();
+    // The expiring values of the first keys overwrite older values.
+    for idx in 0..5 {
+        storage.put(&key_of(idx), b"old").unwrap();
+    }
+    storage.sync().unwrap();
+    for idx in 0..30 {
+        let value = value_of(idx, 0);
+        match idx / 10 {
+            0 => storage
+                .put_with_ttl(&key_of(idx), &value, SHORT_TTL)
+                .unwrap(),
+            1 => storage.put(&key_of(idx), &value).unwrap(),
+            _ => storage
+                .put_with_ttl(&key_of(idx), &value, LONG_TTL)
+                .unwrap(),
+        }
+        expected.insert(key_of(idx), value);
+    }
+    check_storage(&storage, &expected, 30);
+
+    // The expiry times are recovered from the WAL.
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    check_storage(&storage, &expected, 30);
+
+    std::thread::sleep(SHORT_TTL);
+    for idx in 0..10 {
+        expected.remove(&key_of(idx));
+    }
+    check_storage(&storage, &expected, 30);
+    storage.sync().unwrap();
+    check_storage(&storage, &expected, 30);
+
+    // Compaction into the bottom level drops the expired values, along with the versions before
+    // them.
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(count_in_ssts(&storage, ValueType::PutWithExpiry), 10);
+    assert_eq!(count_in_ssts(&storage, ValueType::Put), 10);
+    assert_eq!(count_in_ssts(&storage, ValueType::Delete), 0);
+    check_storage(&storage, &expected, 30);
+
+    drop(storage);
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    check_storage(&storage, &expected, 30);
+}
+
+#[test]
+fn test_put_with_ttl_merge() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open_with_options(&dir, options()).unwrap();
+    storage.put_with_ttl(&key_of(0), b"a", SHORT_TTL).unwrap();
+    storage.merge(&key_of(0), b"b").unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put_with_ttl(&key_of(1), b"a", SHORT_TTL);
+    batch.merge(&key_of(1), b"b");
+    storage.write(&batch).unwrap();
+    storage.put_with_ttl(&key_of(2), b"a", LONG_TTL).unwrap();
+    storage.merge(&key_of(2), b"b").unwrap();
+    let expected = (0..3).map(|idx| (key_of(idx), b"ab".to_vec())).collect();
+    check_storage(&storage, &expected, 3);
+
+    // The merged values expire with the values they are merged onto, whether or not compaction
+    // merged them before.
+    storage.sync().unwrap();
+    storage
+        .compact_range(Bound::Unbounded, Bound::Included(&key_of(0)))
+        .unwrap();
+    check_storage(&storage, &expected, 3);
+    std::thread::sleep(SHORT_TTL);
+    let expected = BTreeMap::from([(key_of(2), b"ab".to_vec())]);
+    check_storage(&storage, &expected, 3);
+    storage
+        .compact_range(Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    check_storage(&storage, &expected, 3);
+}
@@ -0,0 +1,58 @@
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::lsm_storage::LsmStorage;
+use crate::write_batch::WriteBatch;
+
+use super::harness::check_iter;
+
+#[test]
+fn test_write_batch() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.sync().unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch
+        .put(b"3", b"23333")
+        .delete(b"1")
+        .put(b"2", b"a")
+        .put(b"2", b"b");
+    assert_eq!(batch.len(), 4);
+    storage.write(&batch).unwrap();
+    // A later write to a key in the batch takes precedence.
+    assert!(storage.get(b"1").unwrap().is_none());
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"b");
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        [(&b"2"[..], &b"b"[..]), (b"3", b"23333")],
+    );
+
+    storage.write(&WriteBatch::new()).unwrap();
+    storage.sync().unwrap();
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        [(&b"2"[..], &b"b"[..]), (b"3", b"23333")],
+    );
+}
+
+#[test]
+fn test_write_batch_recover_from_wal() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"2", b"2333").put(b"3", b"23333").delete(b"1");
+    storage.write(&batch).unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir).unwrap();
+    check_iter(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        [(&b"2"[..], &b"2333"[..]), (b"3", b"23333")],
+    );
+}
@@ -0,0 +1,447 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use crossbeam_skiplist::map::Entry;
+use crossbeam_skiplist::SkipMap;
+use ouroboros::self_referencing;
+use parking_lot::Mutex;
+
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::{StorageIterator, ValueType};
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::LsmStorageCore;
+use crate::mem_table::map_bound;
+use crate::snapshot::Snapshot;
+use crate::write_batch::WriteBatchRecord;
+
+/// A transaction failed to commit because another write changed a key it read or wrote after the
+/// transaction started. Commit errors can be downcast to this type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConflictError {
+    /// The key changed by the other write. For a range deletion, this is the first key in the
+    /// range that was read, or the start of the range.
+    pub key: Bytes,
+}
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "transaction conflicts with a later write to key {:?}",
+            self.key
+        )
+    }
+}
+
+impl std::error::Error for ConflictError {}
+
+/// How much of what a transaction read is checked for conflicts at commit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// The keys read through `get` and the keys returned by `scan` are checked. A key that another
+    /// commit adds to a scanned range is not a conflict, so a repeated scan may see it.
+    #[default]
+    RepeatableRead,
+    /// The ranges passed to `scan` are checked as a whole, along with the keys read through `get`.
+    /// Committed transactions behave as if they ran one at a time.
+    Serializable,
+}
+
+/// What a transaction has read, which a commit by someone else must not have changed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadSet {
+    /// The keys read through `get`, and through `scan` under
+    /// [`IsolationLevel::RepeatableRead`].
+    pub keys: BTreeSet<Bytes>,
+    /// The ranges passed to `scan` under [`IsolationLevel::Serializable`].
+    pub ranges: Vec<(Bound<Bytes>, Bound<Bytes>)>,
+}
+
+impl ReadSet {
+    /// Check if a write to `key` changes what was read.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.keys.contains(key) || self.ranges.iter().any(|range| range.contains(key))
+    }
+
+    /// Find out if a deletion of `[lower, upper)` changes what was read. Returns the first key read
+    /// in the range, or `lower` if only a scanned range overlaps with it.
+    pub fn overlapping_key(&self, lower: &Bytes, upper: &Bytes) -> Option<Bytes> {
+        let range = (Bound::Included(lower), Bound::Excluded(upper));
+        if let Some(key) = self.keys.range::<Bytes, _>(range).next() {
+            return Some(key.clone());
+        }
+        let overlaps = |(start, end): &(Bound<Bytes>, Bound<Bytes>)| {
+            let starts_before_upper = match start {
+                Bound::Included(start) | Bound::Excluded(start) => start < upper,
+                Bound::Unbounded => true,
+            };
+            let ends_after_lower = match end {
+                Bound::Included(end) => end >= lower,
+                Bound::Excluded(end) => end > lower,
+                Bound::Unbounded => true,
+            };
+            starts_before_upper && ends_after_lower
+        };
+        self.ranges.iter().any(overlaps).then(|| lower.clone())
+    }
+}
+
+/// The writes since the oldest live transaction started.
+#[derive(Default)]
+pub(crate) struct CommittedWrites {
+    /// The start sequence numbers of the live transactions, with the number of transactions at
+    /// each.
+    live_txns: BTreeMap<u64, usize>,
+    /// The entries of each write, by sequence number.
+    writes: BTreeMap<u64, Vec<WriteBatchRecord>>,
+}
+
+impl CommittedWrites {
+    fn begin(&mut self, start_seq: u64) {
+        *self.live_txns.entry(start_seq).or_default() += 1;
+    }
+
+    fn end(&mut self, start_seq: u64) {
+        let count = self
+            .live_txns
+            .get_mut(&start_seq)
+            .expect("transaction not registered");
+        *count -= 1;
+        if *count == 0 {
+            self.live_txns.remove(&start_seq);
+        }
+        // Writes seen by every live transaction can no longer conflict.
+        match self.live_txns.keys().next() {
+            Some(&oldest) => self.writes = self.writes.split_off(&(oldest + 1)),
+            None => self.writes.clear(),
+        }
+    }
+
+    /// Record the entries of the write with sequence number `seq`, if any transaction may conflict
+    /// with it.
+    pub(crate) fn record(&mut self, seq: u64, entries: &[WriteBatchRecord]) {
+        if !self.live_txns.is_empty() {
+            self.writes.insert(seq, entries.to_vec());
+        }
+    }
+
+    /// The entries written after `start_seq`.
+    fn written_since(&self, start_seq: u64) -> impl Iterator<Item = &WriteBatchRecord> {
+        self.writes
+            .range(start_seq + 1..)
+            .flat_map(|(_, keys)| keys.iter())
+    }
+}
+
+/// An optimistic transaction, created by
+/// [`LsmStorage::new_txn`](crate::lsm_storage::LsmStorage::new_txn) or
+/// [`LsmStorage::new_txn_with_isolation`](crate::lsm_storage::LsmStorage::new_txn_with_isolation).
+///
+/// Reads see a snapshot of the storage taken when the transaction started, together with the
+/// transaction's own writes. Writes are buffered in the transaction and applied atomically by
+/// [`Transaction::commit`]. A transaction dropped without committing is rolled back.
+pub struct Transaction {
+    core: Arc<LsmStorageCore>,
+    snapshot: Snapshot,
+    /// The writes of the transaction, as puts and deletions.
+    local_storage: Arc<SkipMap<Bytes, (ValueType, Bytes)>>,
+    read_set: Arc<Mutex<ReadSet>>,
+    isolation_level: IsolationLevel,
+}
+
+impl Transaction {
+    pub(crate) fn new(core: Arc<LsmStorageCore>, isolation_level: IsolationLevel) -> Self {
+        // No write becomes visible while the lock is held, so every write after the snapshot is
+        // recorded for the transaction.
+        let snapshot = {
+            let mut committed_writes = core.committed_writes.lock();
+            let snapshot = Snapshot::new(core.clone());
+            committed_writes.begin(snapshot.seq());
+            snapshot
+        };
+        Self {
+            core,
+            snapshot,
+            local_storage: Arc::new(SkipMap::new()),
+            read_set: Arc::new(Mutex::new(ReadSet::default())),
+            isolation_level,
+        }
+    }
+
+    pub fn isolation_level(&self) -> IsolationLevel {
+        self.isolation_level
+    }
+
+    /// What the transaction has read so far, for finding out why a commit failed.
+    pub fn read_set(&self) -> ReadSet {
+        self.read_set.lock().clone()
+    }
+
+    /// The sequence number of the snapshot the transaction reads from.
+    pub fn start_seq(&self) -> u64 {
+        self.snapshot.seq()
+    }
+
+    /// Get a key, as written by the transaction or as of its snapshot.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        if let Some(entry) = self.local_storage.get(key) {
+            let (value_type, value) = entry.value();
+            return Ok((*value_type != ValueType::Delete).then(|| value.clone()));
+        }
+        self.read_set
+            .lock()
+            .keys
+            .insert(Bytes::copy_from_slice(key));
+        self.snapshot.get(key)
+    }
+
+    /// Create an iterator over a range of keys, as written by the transaction or as of its
+    /// snapshot. The whole range is added to the read set under
+    /// [`IsolationLevel::Serializable`], and only the keys the iterator goes through otherwise.
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
+        let local_iter = TxnLocalIterator::create(self.local_storage.clone(), lower, upper);
+        let iter = TwoMergeIterator::create(local_iter, self.snapshot.scan(lower, upper)?)?;
+        let read_set = match self.isolation_level {
+            IsolationLevel::RepeatableRead => Some(self.read_set.clone()),
+            IsolationLevel::Serializable => {
+                self.read_set
+                    .lock()
+                    .ranges
+                    .push((map_bound(lower), map_bound(upper)));
+                None
+            }
+        };
+        TxnIterator::new(iter, read_set)
+    }
+
+    /// Put a key-value pair in the transaction.
+    pub fn put(&self, key: &[u8], value: &[u8]) {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.local_storage.insert(
+            Bytes::copy_from_slice(key),
+            (ValueType::Put, Bytes::copy_from_slice(value)),
+        );
+    }
+
+    /// Remove a key in the transaction.
+    pub fn delete(&self, key: &[u8]) {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.local_storage.insert(
+            Bytes::copy_from_slice(key),
+            (ValueType::Delete, Bytes::new()),
+        );
+    }
+
+    /// Apply the writes of the transaction atomically. Fails with a [`ConflictError`], and writes
+    /// nothing, if anyone else wrote a key in the read set, or a key the transaction wrote, after
+    /// the transaction started.
+    pub fn commit(self) -> Result<()> {
+        // Holding the write lock keeps other writes out between the check and the write.
+        let write_lock = self.core.write_lock.lock();
+        {
+            let committed_writes = self.core.committed_writes.lock();
+            let read_set = self.read_set.lock();
+            let conflict = committed_writes
+                .written_since(self.start_seq())
+                .find_map(|entry| match entry {
+                    WriteBatchRecord::Put(key, _)
+                    | WriteBatchRecord::PutWithExpiry(key, ..)
+                    | WriteBatchRecord::Delete(key)
+                    | WriteBatchRecord::Merge(key, _) => (read_set.contains(key)
+                        || self.local_storage.contains_key(key))
+                    .then(|| key.clone()),
+                    WriteBatchRecord::DeleteRange(lower, upper) => {
+                        read_set.overlapping_key(lower, upper).or_else(|| {
+                            let mut written =
+                                self.local_storage.range(lower.clone()..upper.clone());
+                            written.next().map(|entry| entry.key().clone())
+                        })
+                    }
+                });
+            if let Some(key) = conflict {
+                return Err(ConflictError { key }.into());
+            }
+        }
+        if self.local_storage.is_empty() {
+            return Ok(());
+        }
+        let entries = self
+            .local_storage
+            .iter()
+            .map(|entry| {
+                let key = entry.key().clone();
+                match entry.value() {
+                    (ValueType::Delete, _) => WriteBatchRecord::Delete(key),
+                    (_, value) => WriteBatchRecord::Put(key, value.clone()),
+                }
+            })
+            .collect::<Vec<_>>();
+        self.core.write_entries_locked(write_lock, &entries)
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        self.core.committed_writes.lock().end(self.start_seq());
+    }
+}
+
+type SkipMapRangeIter<'a> = crossbeam_skiplist::map::Range<
+    'a,
+    Bytes,
+    (Bound<Bytes>, Bound<Bytes>),
+    Bytes,
+    (ValueType, Bytes),
+>;
+
+/// An iterator over a range of the writes of a transaction.
+#[self_referencing]
+pub struct TxnLocalIterator {
+    map: Arc<SkipMap<Bytes, (ValueType, Bytes)>>,
+    #[borrows(map)]
+    #[not_covariant]
+    iter: SkipMapRangeIter<'this>,
+    item: (Bytes, (ValueType, Bytes)),
+}
+
+impl TxnLocalIterator {
+    fn create(
+        map: Arc<SkipMap<Bytes, (ValueType, Bytes)>>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Self {
+        let (lower, upper) = (map_bound(lower), map_bound(upper));
+        let mut iter = TxnLocalIteratorBuilder {
+            map,
+            iter_builder: |map| map.range((lower, upper)),
+            item: (Bytes::new(), (ValueType::Put, Bytes::new())),
+        }
+        .build();
+        let entry = iter.with_iter_mut(|iter| Self::entry_to_item(iter.next()));
+        iter.with_mut(|x| *x.item = entry);
+        iter
+    }
+
+    fn entry_to_item(
+        entry: Option<Entry<'_, Bytes, (ValueType, Bytes)>>,
+    ) -> (Bytes, (ValueType, Bytes)) {
+        entry
+            .map(|x| (x.key().clone(), x.value().clone()))
+            .unwrap_or_else(|| (Bytes::new(), (ValueType::Put, Bytes::new())))
+    }
+}
+
+impl StorageIterator for TxnLocalIterator {
+    fn value(&self) -> &[u8] {
+        &self.borrow_item().1 .1[..]
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.borrow_item().0[..]
+    }
+
+    /// The writes of the transaction are newer than everything in its snapshot.
+    fn seq(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.borrow_item().1 .0
+    }
+
+    fn is_valid(&self) -> bool {
+        !self.borrow_item().0.is_empty()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        let entry = self.with_iter_mut(|iter| Self::entry_to_item(iter.next()));
+        self.with_mut(|x| *x.item = entry);
+        Ok(())
+    }
+}
+
+/// Iterates over the writes of a transaction merged with its snapshot. A key written by the
+/// transaction hides the key in the snapshot, and deleted keys are skipped.
+pub struct TxnIterator {
+    iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+    /// The read set to add the keys gone through to, if the range is not tracked as a whole.
+    read_set: Option<Arc<Mutex<ReadSet>>>,
+}
+
+impl TxnIterator {
+    fn new(
+        iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+        read_set: Option<Arc<Mutex<ReadSet>>>,
+    ) -> Result<Self> {
+        let mut iter = Self { iter, read_set };
+        iter.move_to_non_delete()?;
+        iter.record_read();
+        Ok(iter)
+    }
+
+    fn record_read(&self) {
+        if let Some(read_set) = &self.read_set {
+            if self.iter.is_valid() {
+                read_set
+                    .lock()
+                    .keys
+                    .insert(Bytes::copy_from_slice(self.iter.key()));
+            }
+        }
+    }
+
+    /// Skip the snapshot's version of the current key if the transaction wrote it, and move past
+    /// deleted keys.
+    fn move_to_non_delete(&mut self) -> Result<()> {
+        while self.iter.is_valid() && self.iter.value_type() == ValueType::Delete {
+            self.next_key()?;
+        }
+        Ok(())
+    }
+
+    /// Move to the next key, skipping the snapshot's version of the current one.
+    fn next_key(&mut self) -> Result<()> {
+        let key = Bytes::copy_from_slice(self.iter.key());
+        self.iter.next()?;
+        while self.iter.is_valid() && self.iter.key() == key {
+            self.iter.next()?;
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for TxnIterator {
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn seq(&self) -> u64 {
+        self.iter.seq()
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.iter.value_type()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if !self.iter.is_valid() {
+            return Ok(());
+        }
+        self.next_key()?;
+        self.move_to_non_delete()?;
+        self.record_read();
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+//! Per-key time to live: a value written with [`LsmStorage::put_with_ttl`] is stored with the
+//! time it expires at, as a [`ValueType::PutWithExpiry`] entry. Reads treat an expired value as
+//! deleted, and flush and compaction replace it with a tombstone, which is dropped at the bottom
+//! level.
+//!
+//! [`LsmStorage::put_with_ttl`]: crate::lsm_storage::LsmStorage::put_with_ttl
+//! [`ValueType::PutWithExpiry`]: crate::iterators::ValueType::PutWithExpiry
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, Bytes};
+
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// The current time, in milliseconds since the UNIX epoch.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// The time a value written now with the given TTL expires at.
+pub(crate) fn expiry_after(ttl: Duration) -> u64 {
+    now().saturating_add(ttl.as_millis() as u64)
+}
+
+/// Encode a value with its expiry time as `| expires_at (u64) | value |`.
+pub(crate) fn encode(expires_at: u64, value: &[u8]) -> Bytes {
+    let mut buf = Vec::with_capacity(SIZEOF_U64 + value.len());
+    buf.put_u64(expires_at);
+    buf.put_slice(value);
+    buf.into()
+}
+
+/// Split an encoded value into its expiry time and the value.
+pub(crate) fn decode(mut encoded: &[u8]) -> (u64, &[u8]) {
+    let expires_at = encoded.get_u64();
+    (expires_at, encoded)
+}
+
+pub(crate) fn is_expired(expires_at: u64) -> bool {
+    expires_at <= now()
+}
+
+/// The value of an encoded value, or `None` if it has expired.
+pub(crate) fn live_value(encoded: &Bytes) -> Option<Bytes> {
+    let (expires_at, _) = decode(encoded);
+    (!is_expired(expires_at)).then(|| encoded.slice(SIZEOF_U64..))
+}
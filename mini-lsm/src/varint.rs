@@ -0,0 +1,43 @@
+//! LEB128 variable-length integers, used for lengths in the on-disk formats: 7 bits per byte,
+//! from the least significant, with the high bit set on every byte but the last. A length below
+//! 128 takes a single byte, and any `u64` takes at most 10.
+
+use bytes::{Buf, BufMut};
+
+/// The largest number of bytes a varint takes.
+pub(crate) const MAX_VARINT_LEN: usize = 10;
+
+/// The number of bytes `value` takes as a varint.
+pub(crate) fn varint_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+pub(crate) fn put_varint(buf: &mut impl BufMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Read a varint from the front of `buf`. Panics if `buf` ends in the middle of it, like the
+/// other `get_*` methods of [`Buf`].
+pub(crate) fn get_varint(buf: &mut impl Buf) -> u64 {
+    let mut value = 0;
+    for shift in (0..MAX_VARINT_LEN * 7).step_by(7) {
+        let byte = buf.get_u8();
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+    panic!("varint is too long");
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,31 @@
+use super::*;
+
+#[test]
+fn test_varint_roundtrip() {
+    let values = [
+        0,
+        1,
+        127,
+        128,
+        300,
+        16383,
+        16384,
+        1 << 32,
+        u64::MAX - 1,
+        u64::MAX,
+    ];
+    let mut buf = Vec::new();
+    for value in values {
+        let len = buf.len();
+        put_varint(&mut buf, value);
+        assert_eq!(buf.len() - len, varint_len(value), "length of {}", value);
+    }
+    assert_eq!(varint_len(127), 1);
+    assert_eq!(varint_len(128), 2);
+    assert_eq!(varint_len(u64::MAX), MAX_VARINT_LEN);
+    let mut buf = &buf[..];
+    for value in values {
+        assert_eq!(get_varint(&mut buf), value);
+    }
+    assert!(buf.is_empty());
+}
@@ -0,0 +1,190 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::Mutex;
+
+use crate::ttl;
+use crate::varint::{get_varint, put_varint, varint_len};
+use crate::write_batch::WriteBatchRecord;
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+const ENTRY_PUT: u8 = 0;
+const ENTRY_DELETE: u8 = 1;
+const ENTRY_DELETE_RANGE: u8 = 2;
+const ENTRY_MERGE: u8 = 3;
+const ENTRY_PUT_WITH_EXPIRY: u8 = 4;
+
+/// A write-ahead log for a single memtable.
+///
+/// Each record holds the entries of one write, so that a write batch is recovered completely or
+/// not at all. All entries of a record share the sequence number of the write:
+///
+/// ```text
+/// | len (u32) | seq (u64) | entries | checksum (u32) |
+/// ```
+///
+/// `len` covers the sequence number and the entries, so a write cannot be larger than 4 GiB. Each
+/// entry is `| type (u8) | key_len (varint) | key | value_len (varint) | value |`, where the type
+/// is 0 for a put, 1 for a deletion (with an empty value), 2 for a range deletion (with the range
+/// as the key and the value), 3 for a merge operand and 4 for a put with an expiry time (with the
+/// value as `| expires_at (u64) | value |`). The checksum is the crc32 of the sequence
+/// number and the entries.
+pub struct Wal {
+    file: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl Wal {
+    /// Create a new, empty WAL at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .create_new(true)
+            .write(true)
+            .open(path.as_ref())
+            .with_context(|| format!("failed to create WAL {}", path.as_ref().display()))?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    /// Replay the WAL at `path` by passing each write and its sequence number to `apply`, and
+    /// reopen it for appending.
+    ///
+    /// A torn record at the end of the file (left by a crash in the middle of a write) is ignored.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        mut apply: impl FnMut(u64, WriteBatchRecord),
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to recover WAL {}", path.display()))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let mut rbuf = &buf[..];
+        while let Some((seq, records)) = Self::decode_record(&mut rbuf)? {
+            for record in records {
+                apply(seq, record);
+            }
+        }
+        Ok(Self {
+            file: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    /// Decode the sequence number and the entries of the next record from `buf`. Returns `None`
+    /// if there is no complete record left.
+    fn decode_record(buf: &mut &[u8]) -> Result<Option<(u64, Vec<WriteBatchRecord>)>> {
+        if buf.remaining() < SIZEOF_U32 {
+            return Ok(None);
+        }
+        let len = (&buf[..SIZEOF_U32]).get_u32() as usize;
+        if buf.remaining() < SIZEOF_U32 + len + SIZEOF_U32 {
+            return Ok(None);
+        }
+        buf.advance(SIZEOF_U32);
+        let mut record = &buf[..len];
+        buf.advance(len);
+        if buf.get_u32() != crc32fast::hash(record) {
+            bail!("WAL checksum mismatched");
+        }
+        let seq = record.get_u64();
+        let mut entries = Vec::new();
+        while record.has_remaining() {
+            let entry_type = record.get_u8();
+            let key_len = get_varint(&mut record) as usize;
+            let key = record.copy_to_bytes(key_len);
+            let value_len = get_varint(&mut record) as usize;
+            let value = record.copy_to_bytes(value_len);
+            entries.push(match entry_type {
+                ENTRY_PUT => WriteBatchRecord::Put(key, value),
+                ENTRY_DELETE => WriteBatchRecord::Delete(key),
+                ENTRY_DELETE_RANGE => WriteBatchRecord::DeleteRange(key, value),
+                ENTRY_MERGE => WriteBatchRecord::Merge(key, value),
+                ENTRY_PUT_WITH_EXPIRY => {
+                    let (expires_at, _) = ttl::decode(&value);
+                    WriteBatchRecord::PutWithExpiry(key, value.slice(SIZEOF_U64..), expires_at)
+                }
+                _ => bail!("unknown WAL entry type {}", entry_type),
+            });
+        }
+        Ok(Some((seq, entries)))
+    }
+
+    /// Append a key-value pair written with sequence number `seq` to the WAL. The record is handed
+    /// to the OS before this returns, so it survives a process crash; call [`Wal::sync`] to make
+    /// it survive a power loss.
+    pub fn put(&self, key: &[u8], seq: u64, value: &[u8]) -> Result<()> {
+        let record =
+            WriteBatchRecord::Put(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        self.put_batch(seq, &[record])
+    }
+
+    /// Append the writes of a batch written with sequence number `seq` to the WAL as a single
+    /// record.
+    pub fn put_batch(&self, seq: u64, records: &[WriteBatchRecord]) -> Result<()> {
+        let entries = records
+            .iter()
+            .map(|record| match record {
+                WriteBatchRecord::Put(key, value) => (ENTRY_PUT, &key[..], value.clone()),
+                WriteBatchRecord::PutWithExpiry(key, value, expires_at) => (
+                    ENTRY_PUT_WITH_EXPIRY,
+                    &key[..],
+                    ttl::encode(*expires_at, value),
+                ),
+                WriteBatchRecord::Delete(key) => (ENTRY_DELETE, &key[..], Bytes::new()),
+                WriteBatchRecord::DeleteRange(lower, upper) => {
+                    (ENTRY_DELETE_RANGE, &lower[..], upper.clone())
+                }
+                WriteBatchRecord::Merge(key, operand) => (ENTRY_MERGE, &key[..], operand.clone()),
+            })
+            .collect::<Vec<_>>();
+        let len = SIZEOF_U64
+            + entries
+                .iter()
+                .map(|(_, key, value)| {
+                    1 + varint_len(key.len() as u64)
+                        + key.len()
+                        + varint_len(value.len() as u64)
+                        + value.len()
+                })
+                .sum::<usize>();
+        if len > u32::MAX as usize {
+            bail!("write of {} bytes is too large for a WAL record", len);
+        }
+        let mut buf: Vec<u8> = Vec::with_capacity(len + SIZEOF_U32 * 2);
+        buf.put_u32(len as u32);
+        buf.put_u64(seq);
+        for (entry_type, key, value) in entries {
+            buf.put_u8(entry_type);
+            put_varint(&mut buf, key.len() as u64);
+            buf.put_slice(key);
+            put_varint(&mut buf, value.len() as u64);
+            buf.put_slice(&value);
+        }
+        buf.put_u32(crc32fast::hash(&buf[SIZEOF_U32..]));
+        let mut file = self.file.lock();
+        file.write_all(&buf)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Persist the WAL to disk.
+    pub fn sync(&self) -> Result<()> {
+        let mut file = self.file.lock();
+        file.flush()?;
+        file.get_mut().sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
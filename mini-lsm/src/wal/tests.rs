@@ -0,0 +1,232 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
+use tempfile::tempdir;
+
+use super::Wal;
+use crate::mem_table::InternalKey;
+use crate::write_batch::WriteBatchRecord;
+
+/// Recover the WAL at `path` into a skiplist of its puts and deletions.
+fn recover(path: &Path) -> Result<(Wal, SkipMap<InternalKey, Bytes>)> {
+    let map = SkipMap::new();
+    let wal = Wal::recover(path, |seq, record| match record {
+        WriteBatchRecord::Put(key, value) => {
+            map.insert(InternalKey::new(key, seq), value);
+        }
+        WriteBatchRecord::Delete(key) => {
+            map.insert(InternalKey::new(key, seq), Bytes::new());
+        }
+        WriteBatchRecord::DeleteRange(..) => panic!("unexpected range deletion"),
+        WriteBatchRecord::Merge(..) => panic!("unexpected merge"),
+        WriteBatchRecord::PutWithExpiry(..) => panic!("unexpected put with expiry"),
+    })?;
+    Ok((wal, map))
+}
+
+fn get(map: &SkipMap<InternalKey, Bytes>, key: &'static [u8], seq: u64) -> Option<Bytes> {
+    map.get(&InternalKey::new(Bytes::from_static(key), seq))
+        .map(|e| e.value().clone())
+}
+
+#[test]
+fn test_wal_recover() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00001.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, b"value1").unwrap();
+        wal.put(b"key2", 2, b"value2").unwrap();
+        wal.put_batch(3, &[WriteBatchRecord::Delete(Bytes::from("key1"))])
+            .unwrap();
+        wal.sync().unwrap();
+    }
+    let (wal, map) = recover(&path).unwrap();
+    assert_eq!(map.len(), 3);
+    assert_eq!(get(&map, b"key1", 1), Some(Bytes::from("value1")));
+    assert_eq!(get(&map, b"key1", 3), Some(Bytes::new()));
+    assert_eq!(get(&map, b"key2", 2), Some(Bytes::from("value2")));
+
+    // The recovered WAL can be appended to.
+    wal.put(b"key3", 4, b"value3").unwrap();
+    drop(wal);
+    let (_, map) = recover(&path).unwrap();
+    assert_eq!(map.len(), 4);
+}
+
+#[test]
+fn test_wal_torn_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00001.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, b"value1").unwrap();
+    }
+    // Simulate a crash in the middle of writing a record.
+    OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(&[0, 4, b'k', b'e'])
+        .unwrap();
+    let (_, map) = recover(&path).unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!(get(&map, b"key1", 1), Some(Bytes::from("value1")));
+}
+
+#[test]
+fn test_wal_corrupted_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00001.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, b"value1").unwrap();
+    }
+    let mut data = std::fs::read(&path).unwrap();
+    data[14] ^= 0xff;
+    std::fs::write(&path, data).unwrap();
+    assert!(recover(&path).is_err());
+}
+
+#[test]
+fn test_wal_batch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00001.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, b"value1").unwrap();
+        wal.put_batch(
+            2,
+            &[
+                WriteBatchRecord::Put(Bytes::from("key2"), Bytes::from("value2")),
+                WriteBatchRecord::Delete(Bytes::from("key1")),
+            ],
+        )
+        .unwrap();
+    }
+    let (_, map) = recover(&path).unwrap();
+    assert_eq!(map.len(), 3);
+    assert_eq!(get(&map, b"key1", 2), Some(Bytes::new()));
+    assert_eq!(get(&map, b"key2", 2), Some(Bytes::from("value2")));
+
+    // A batch cut off in the middle is not recovered at all.
+    let len = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(len - 6)
+        .unwrap();
+    let (_, map) = recover(&path).unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!(get(&map, b"key1", 1), Some(Bytes::from("value1")));
+}
+
+#[test]
+fn test_wal_range_deletion() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00001.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, b"value1").unwrap();
+        wal.put_batch(
+            2,
+            &[WriteBatchRecord::DeleteRange(
+                Bytes::from("key1"),
+                Bytes::from("key5"),
+            )],
+        )
+        .unwrap();
+    }
+    let mut records = Vec::new();
+    Wal::recover(&path, |seq, record| records.push((seq, record))).unwrap();
+    assert_eq!(
+        records,
+        vec![
+            (
+                1,
+                WriteBatchRecord::Put(Bytes::from("key1"), Bytes::from("value1"))
+            ),
+            (
+                2,
+                WriteBatchRecord::DeleteRange(Bytes::from("key1"), Bytes::from("key5"))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_wal_merge() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00001.wal");
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(b"key1", 1, b"value1").unwrap();
+        wal.put_batch(
+            2,
+            &[
+                WriteBatchRecord::Merge(Bytes::from("key1"), Bytes::from("operand1")),
+                WriteBatchRecord::Merge(Bytes::from("key2"), Bytes::new()),
+            ],
+        )
+        .unwrap();
+    }
+    let mut records = Vec::new();
+    Wal::recover(&path, |seq, record| records.push((seq, record))).unwrap();
+    assert_eq!(
+        records,
+        vec![
+            (
+                1,
+                WriteBatchRecord::Put(Bytes::from("key1"), Bytes::from("value1"))
+            ),
+            (
+                2,
+                WriteBatchRecord::Merge(Bytes::from("key1"), Bytes::from("operand1"))
+            ),
+            (
+                2,
+                WriteBatchRecord::Merge(Bytes::from("key2"), Bytes::new())
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_wal_put_with_expiry() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00001.wal");
+    let records = vec![
+        WriteBatchRecord::PutWithExpiry(Bytes::from("key1"), Bytes::from("value1"), 1 << 40),
+        WriteBatchRecord::PutWithExpiry(Bytes::from("key2"), Bytes::new(), 0),
+    ];
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put_batch(1, &records).unwrap();
+    }
+    let mut recovered = Vec::new();
+    Wal::recover(&path, |_, record| recovered.push(record)).unwrap();
+    assert_eq!(recovered, records);
+}
+
+#[test]
+fn test_wal_large_entries() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("00001.wal");
+    let key = b"key".repeat(30000);
+    let value = b"value".repeat(100000);
+    {
+        let wal = Wal::create(&path).unwrap();
+        wal.put(&key, 1, &value).unwrap();
+        wal.put(b"key2", 2, b"value2").unwrap();
+    }
+    let (_, map) = recover(&path).unwrap();
+    assert_eq!(map.len(), 2);
+    let entry = map.get(&InternalKey::new(Bytes::from(key), 1)).unwrap();
+    assert_eq!(entry.value(), &value[..]);
+    assert_eq!(get(&map, b"key2", 2), Some(Bytes::from("value2")));
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::ttl;
+
+/// A write in a [`WriteBatch`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WriteBatchRecord {
+    Put(Bytes, Bytes),
+    /// Put a key-value pair that expires at the given time, in milliseconds since the UNIX epoch.
+    PutWithExpiry(Bytes, Bytes, u64),
+    Delete(Bytes),
+    /// Delete every key in `[lower, upper)`.
+    DeleteRange(Bytes, Bytes),
+    /// Merge an operand into the value of a key with the
+    /// [`MergeOperator`](crate::merge_operator::MergeOperator) of the storage.
+    Merge(Bytes, Bytes),
+}
+
+/// A group of writes applied atomically by [`LsmStorage::write`](crate::lsm_storage::LsmStorage::write).
+///
+/// Writes are applied in the order they are added, so a later write to a key in the same batch
+/// takes precedence.
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    records: Vec<WriteBatchRecord>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a put of a key-value pair to the batch.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.records.push(WriteBatchRecord::Put(
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(value),
+        ));
+        self
+    }
+
+    /// Add a put of a key-value pair that is deleted once `ttl` has passed.
+    pub fn put_with_ttl(&mut self, key: &[u8], value: &[u8], ttl: Duration) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.records.push(WriteBatchRecord::PutWithExpiry(
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(value),
+            ttl::expiry_after(ttl),
+        ));
+        self
+    }
+
+    /// Add a deletion of a key to the batch.
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.records
+            .push(WriteBatchRecord::Delete(Bytes::copy_from_slice(key)));
+        self
+    }
+
+    /// Add a merge of an operand into the value of a key to the batch.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+        self.records.push(WriteBatchRecord::Merge(
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(operand),
+        ));
+        self
+    }
+
+    /// Add a deletion of every key in `[lower, upper)` to the batch. The range cannot be empty.
+    pub fn delete_range(&mut self, lower: &[u8], upper: &[u8]) -> &mut Self {
+        assert!(lower < upper, "range cannot be empty");
+        // The writes of a batch share a sequence number, so the range tombstone cannot delete the
+        // writes before it in the batch. They are dropped here instead.
+        self.records.retain(|record| match record {
+            WriteBatchRecord::Put(key, _)
+            | WriteBatchRecord::PutWithExpiry(key, ..)
+            | WriteBatchRecord::Delete(key)
+            | WriteBatchRecord::Merge(key, _) => !(lower <= &key[..] && &key[..] < upper),
+            WriteBatchRecord::DeleteRange(..) => true,
+        });
+        self.records.push(WriteBatchRecord::DeleteRange(
+            Bytes::copy_from_slice(lower),
+            Bytes::copy_from_slice(upper),
+        ));
+        self
+    }
+
+    pub fn records(&self) -> &[WriteBatchRecord] {
+        &self.records
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}